@@ -0,0 +1,205 @@
+//! RGB ↔ Y'CbCr conversion with a chosen matrix and range. swscale (as exposed
+//! by ac-ffmpeg) always converts with BT.601 limited range, so frames are
+//! converted here to or from `yuva444p16le` and swscale only changes the
+//! subsampling and bit depth, which keeps the colors as they are
+
+/// Pixel format frames are converted to and from, planar with full resolution chroma
+pub(crate) const INTERMEDIATE_FORMAT: &str = "yuva444p16le";
+
+/// Y'CbCr matrix, given by the luma weights of red and blue
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Matrix {
+    kr: f32,
+    kb: f32,
+}
+
+impl Matrix {
+    pub(crate) const BT601: Self = Self { kr: 0.299, kb: 0.114 };
+    pub(crate) const BT709: Self = Self { kr: 0.2126, kb: 0.0722 };
+    pub(crate) const BT2020: Self = Self { kr: 0.2627, kb: 0.0593 };
+
+    /// Matrix of a colorspace name like `bt709`, see [`ColorTags::from_name`]
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match ColorTags::from_name(name)?.colorspace {
+            "bt709" => Some(Self::BT709),
+            "bt2020nc" | "bt2020c" => Some(Self::BT2020),
+            _ => Some(Self::BT601),
+        }
+    }
+
+    /// What players assume for video without colorspace tags: BT.709 for HD, BT.601 below
+    pub(crate) fn for_height(height: usize) -> Self {
+        if height >= 720 {
+            Self::BT709
+        } else {
+            Self::BT601
+        }
+    }
+}
+
+/// Values of ffmpeg's `colorspace`, `color_primaries` and `color_trc`
+/// options, which name the same standard differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ColorTags {
+    pub colorspace: &'static str,
+    pub primaries: &'static str,
+    pub transfer: &'static str,
+}
+
+impl ColorTags {
+    /// Tags of `bt709`, `bt601` or `smpte170m` (NTSC), `bt470bg` (PAL) and
+    /// `bt2020` (or `bt2020nc`, `bt2020c` for constant luminance)
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let tags = |colorspace, primaries, transfer| Some(Self { colorspace, primaries, transfer });
+        match name {
+            "bt709" => tags("bt709", "bt709", "bt709"),
+            // BT.601 uses the transfer function of BT.709, which ffmpeg calls smpte170m for SD
+            "bt601" | "smpte170m" => tags("smpte170m", "smpte170m", "smpte170m"),
+            "bt470bg" => tags("bt470bg", "bt470bg", "smpte170m"),
+            // The transfer function is the same for 10 and 12 bits
+            "bt2020" | "bt2020nc" => tags("bt2020nc", "bt2020", "bt2020-10"),
+            "bt2020c" => tags("bt2020c", "bt2020", "bt2020-10"),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `pixel_format` (an ffmpeg pixel format name) stores Y'CbCr
+pub(crate) fn is_yuv(pixel_format: &str) -> bool {
+    ["yuv", "nv", "p010", "p016", "y210"].iter().any(|prefix| pixel_format.starts_with(prefix))
+}
+
+/// `yuvj` formats are always full range
+pub(crate) fn is_full_range_format(pixel_format: &str) -> bool {
+    pixel_format.starts_with("yuvj")
+}
+
+/// Converts RGBA8 to the planes of [`INTERMEDIATE_FORMAT`], one `u16` per sample
+pub(crate) fn rgba_to_yuva(rgba: &[u8], matrix: Matrix, full_range: bool) -> [Vec<u16>; 4] {
+    let pixels = rgba.len() / 4;
+    let mut planes = [(); 4].map(|_| Vec::with_capacity(pixels));
+    let (kr, kb) = (matrix.kr, matrix.kb);
+    let (luma, chroma) = scales(full_range);
+
+    for pixel in rgba.chunks_exact(4) {
+        let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]].map(|channel| channel as f32 / 255.0);
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        let cb = (b - y) / (2.0 * (1.0 - kb));
+        let cr = (r - y) / (2.0 * (1.0 - kr));
+
+        planes[0].push(quantize(luma.0 + luma.1 * y));
+        planes[1].push(quantize(CHROMA_ZERO + chroma * cb));
+        planes[2].push(quantize(CHROMA_ZERO + chroma * cr));
+        planes[3].push(quantize(a));
+    }
+    planes
+}
+
+/// Converts the planes of [`INTERMEDIATE_FORMAT`] (as rows of `line_size`
+/// bytes) to tightly packed RGBA8
+pub(crate) fn yuva_to_rgba(planes: [(&[u8], usize); 4], (width, height): (usize, usize), matrix: Matrix, full_range: bool) -> Vec<u8> {
+    let (kr, kb) = (matrix.kr, matrix.kb);
+    let kg = 1.0 - kr - kb;
+    let (luma, chroma) = scales(full_range);
+    let sample = |plane: usize, x: usize, y: usize| {
+        let (data, line_size) = planes[plane];
+        let i = y * line_size + x * 2;
+        u16::from_le_bytes([data[i], data[i + 1]]) as f32 / u16::MAX as f32
+    };
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let luma_value = (sample(0, x, y) - luma.0) / luma.1;
+            let cb = (sample(1, x, y) - CHROMA_ZERO) / chroma;
+            let cr = (sample(2, x, y) - CHROMA_ZERO) / chroma;
+
+            let r = luma_value + 2.0 * (1.0 - kr) * cr;
+            let b = luma_value + 2.0 * (1.0 - kb) * cb;
+            let g = (luma_value - kr * r - kb * b) / kg;
+            rgba.extend([r, g, b, sample(3, x, y)].map(|channel| (channel * 255.0).round().clamp(0.0, 255.0) as u8));
+        }
+    }
+    rgba
+}
+
+/// Chroma value of gray, 128 in 8 bits
+const CHROMA_ZERO: f32 = 32768.0 / 65535.0;
+
+/// Offset and scale of luma and the scale of chroma, as fractions of the
+/// sample range. Limited range is 16-235 (240 for chroma) shifted to 16 bits
+fn scales(full_range: bool) -> ((f32, f32), f32) {
+    match full_range {
+        true => ((0.0, 1.0), 1.0),
+        false => ((4096.0 / 65535.0, 56064.0 / 65535.0), 57344.0 / 65535.0),
+    }
+}
+
+fn quantize(value: f32) -> u16 {
+    (value * u16::MAX as f32).round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rgba_to_yuva, yuva_to_rgba, ColorTags, Matrix};
+
+    /// Samples of a plane in 8-bit steps: limited range is shifted by 8 bits,
+    /// full range scaled from 65535 to 255
+    fn eight_bit(plane: &[u16], full_range: bool) -> Vec<f32> {
+        let scale = if full_range { 257.0 } else { 256.0 };
+        plane.iter().map(|&sample| sample as f32 / scale).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 0.5), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn every_name_maps_to_valid_ffmpeg_tags() {
+        let bt2020 = ColorTags {
+            colorspace: "bt2020nc",
+            primaries: "bt2020",
+            transfer: "bt2020-10",
+        };
+        assert_eq!(ColorTags::from_name("bt2020"), Some(bt2020));
+        assert_eq!(ColorTags::from_name("bt2020nc"), Some(bt2020));
+        assert_eq!(ColorTags::from_name("bt601").unwrap().colorspace, "smpte170m");
+        assert_eq!(ColorTags::from_name("bt470bg").unwrap().transfer, "smpte170m");
+        assert_eq!(ColorTags::from_name("bt709").unwrap().transfer, "bt709");
+        assert_eq!(ColorTags::from_name("srgb"), None);
+
+        assert_eq!(Matrix::from_name("bt709"), Some(Matrix::BT709));
+        assert_eq!(Matrix::from_name("bt601"), Some(Matrix::BT601));
+        assert_eq!(Matrix::from_name("bt470bg"), Some(Matrix::BT601));
+        assert_eq!(Matrix::from_name("bt2020c"), Some(Matrix::BT2020));
+        assert_eq!(Matrix::from_name("srgb"), None);
+    }
+
+    #[test]
+    fn primaries_have_known_bt709_values() {
+        // White, black, red, green and blue
+        let rgba = [[255, 255, 255, 255], [0, 0, 0, 255], [255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 0]].concat();
+
+        let [y, cb, cr, a] = rgba_to_yuva(&rgba, Matrix::BT709, false);
+        assert_close(&eight_bit(&y, false), &[235.0, 16.0, 62.6, 172.6, 31.8]);
+        assert_close(&eight_bit(&cb, false), &[128.0, 128.0, 102.3, 41.7, 240.0]);
+        assert_close(&eight_bit(&cr, false), &[128.0, 128.0, 240.0, 26.3, 117.7]);
+        assert_eq!(a, [65535, 65535, 65535, 65535, 0]);
+
+        let [y, ..] = rgba_to_yuva(&rgba, Matrix::BT709, true);
+        assert_close(&eight_bit(&y, true), &[255.0, 0.0, 54.2, 182.4, 18.4]);
+    }
+
+    #[test]
+    fn conversion_round_trips() {
+        let rgba = (0..=255u8).flat_map(|value| [value, value.wrapping_mul(7), 255 - value, value / 2]).collect::<Vec<_>>();
+
+        for matrix in [Matrix::BT601, Matrix::BT709, Matrix::BT2020] {
+            for full_range in [false, true] {
+                let planes = rgba_to_yuva(&rgba, matrix, full_range).map(|plane| plane.iter().flat_map(|sample| sample.to_le_bytes()).collect::<Vec<_>>());
+                let planes = [0, 1, 2, 3].map(|index| (&planes[index][..], 256 * 2));
+                assert_eq!(yuva_to_rgba(planes, (256, 1), matrix, full_range), rgba);
+            }
+        }
+    }
+}
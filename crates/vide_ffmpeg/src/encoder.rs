@@ -0,0 +1,179 @@
+use ac_ffmpeg::codec::video::VideoEncoderBuilder;
+use vide_lib::error::{Error, Result};
+
+use crate::color::ColorTags;
+
+/// How the encoder trades file size for quality
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant quality, lower is better. 23 is the x264 default, 18 looks lossless to most people
+    Crf(u8),
+    /// Average bitrate in bits per second
+    Bitrate(u64),
+    /// Whatever the encoder does without options
+    Default,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorRange {
+    /// 16-235, what players expect from almost all video
+    #[default]
+    Limited,
+    /// 0-255
+    Full,
+}
+
+/// Settings of the video encoder used by [`FFmpegExporter`](crate::FFmpegExporter)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderOptions {
+    /// Name of the ffmpeg encoder, like `libx264`, `libvpx-vp9` or `prores_ks`
+    pub codec: String,
+    /// Name of the ffmpeg pixel format frames are converted to before encoding
+    pub pixel_format: String,
+    pub rate_control: RateControl,
+    /// Encoder speed preset, like `medium` or `slow` for x264
+    pub preset: Option<String>,
+    /// Frames between two keyframes
+    pub gop_size: Option<u32>,
+    /// Maximum number of consecutive B-frames
+    pub b_frames: Option<u32>,
+    /// Range tag of the output
+    pub color_range: ColorRange,
+    /// Colorspace of the output: `bt709`, `bt601` or `smpte170m` (NTSC),
+    /// `bt470bg` (PAL) or `bt2020`. Tags the matrix, primaries and transfer
+    /// characteristics with the names ffmpeg has for each of them
+    pub colorspace: Option<String>,
+    /// Other options passed to the encoder as they are, like `("tune", "animation")`
+    pub codec_options: Vec<(String, String)>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            codec: "libx264".to_string(),
+            pixel_format: "yuv420p".to_string(),
+            rate_control: RateControl::Crf(20),
+            preset: Some("medium".to_string()),
+            gop_size: None,
+            b_frames: None,
+            color_range: ColorRange::Limited,
+            colorspace: Some("bt709".to_string()),
+            codec_options: Vec::new(),
+        }
+    }
+}
+
+impl EncoderOptions {
+    /// Options that suit `codec`: constant quality for x264, x265, VP9 and
+    /// AV1, 10-bit 4:2:2 for ProRes, untagged RGB for image and lossless RGB
+    /// codecs and the encoder's own defaults for anything else
+    pub fn codec(codec: impl ToString) -> Self {
+        let codec = codec.to_string();
+        let defaults = Self {
+            codec: codec.clone(),
+            ..Default::default()
+        };
+        let rgb = |pixel_format: &str| Self {
+            pixel_format: pixel_format.to_string(),
+            rate_control: RateControl::Default,
+            preset: None,
+            color_range: ColorRange::Full,
+            colorspace: None,
+            ..defaults.clone()
+        };
+
+        match codec.as_str() {
+            "libx264" => defaults,
+            "libx265" => Self {
+                rate_control: RateControl::Crf(24),
+                ..defaults
+            },
+            "libvpx-vp9" | "libvpx" => Self {
+                rate_control: RateControl::Crf(31),
+                preset: None,
+                // libvpx only uses constant quality when the bitrate is 0
+                codec_options: vec![("b".to_string(), "0".to_string()), ("row-mt".to_string(), "1".to_string())],
+                ..defaults
+            },
+            "libaom-av1" | "libsvtav1" => Self {
+                rate_control: RateControl::Crf(30),
+                preset: None,
+                ..defaults
+            },
+            "prores_ks" | "prores" | "prores_aw" => Self {
+                pixel_format: "yuv422p10le".to_string(),
+                rate_control: RateControl::Default,
+                preset: None,
+                ..defaults
+            },
+            "png" | "apng" | "gif" | "ffv1" | "utvideo" => rgb("rgba"),
+            "exr" => rgb("gbrapf32le"),
+            "qtrle" => rgb("argb"),
+            _ => Self {
+                rate_control: RateControl::Default,
+                preset: None,
+                ..defaults
+            },
+        }
+    }
+
+    /// ProRes 4444 with alpha, for `.mov` files
+    pub fn prores_4444() -> Self {
+        Self {
+            pixel_format: "yuva444p10le".to_string(),
+            codec_options: vec![("profile".to_string(), "4".to_string()), ("alpha_bits".to_string(), "16".to_string())],
+            ..Self::codec("prores_ks")
+        }
+    }
+
+    /// VP9 with alpha, for `.webm` files
+    pub fn vp9_alpha() -> Self {
+        Self {
+            pixel_format: "yuva420p".to_string(),
+            ..Self::codec("libvpx-vp9")
+        }
+    }
+
+    /// Lossless QuickTime Animation with alpha, for `.mov` files. Large, but
+    /// every editor can read it
+    pub fn qtrle() -> Self {
+        Self::codec("qtrle")
+    }
+
+    /// Fails for colorspace names [`ColorTags::from_name`] doesn't know
+    pub(crate) fn apply(&self, mut builder: VideoEncoderBuilder) -> Result<VideoEncoderBuilder> {
+        builder = match self.rate_control {
+            // Encoders without a crf option ignore it
+            RateControl::Crf(crf) => builder.set_option("crf", crf),
+            RateControl::Bitrate(bit_rate) => builder.bit_rate(bit_rate),
+            RateControl::Default => builder,
+        };
+
+        if let Some(preset) = self.preset.as_ref() {
+            builder = builder.set_option("preset", preset);
+        }
+        if let Some(gop_size) = self.gop_size {
+            builder = builder.set_option("g", gop_size);
+        }
+        if let Some(b_frames) = self.b_frames {
+            builder = builder.set_option("bf", b_frames);
+        }
+
+        builder = builder.set_option("color_range", match self.color_range {
+            ColorRange::Limited => "tv",
+            ColorRange::Full => "pc",
+        });
+        if let Some(colorspace) = self.colorspace.as_ref() {
+            let tags = ColorTags::from_name(colorspace).ok_or_else(|| Error::Codec(format!("unknown colorspace {}, expected bt709, bt601, smpte170m, bt470bg or bt2020", colorspace)))?;
+            builder = builder
+                .set_option("colorspace", tags.colorspace)
+                .set_option("color_primaries", tags.primaries)
+                .set_option("color_trc", tags.transfer);
+        }
+
+        for (name, value) in self.codec_options.iter() {
+            builder = builder.set_option(name, value);
+        }
+        Ok(builder)
+    }
+}
@@ -1,22 +1,334 @@
 pub mod quick_export;
+mod color;
+mod encoder;
 
-use std::{fs::File, io::Write};
+pub use encoder::{ColorRange, EncoderOptions, RateControl};
 
-use ac_ffmpeg::{codec::{video::{VideoEncoder, self, VideoFrameMut, PixelFormat}, Encoder}, time::{TimeBase, Timestamp}, format::{muxer::{Muxer, OutputFormat}, io::IO}};
-use vide_lib::io::{Import, Export};
+use std::{fs::File, ops::Range, path::Path};
 
-pub struct FFmpegImporter {
+use ac_ffmpeg::{codec::{video::{VideoEncoder, VideoDecoder, self, VideoFrame, VideoFrameMut, scaler::{VideoFrameScaler, Algorithm}}, audio::{self, AudioDecoder, AudioEncoder, AudioFrame, AudioFrameMut, AudioResampler, ChannelLayout, SampleFormat}, Encoder, Decoder}, time::{TimeBase, Timestamp}, format::{muxer::{Muxer, OutputFormat}, demuxer::{Demuxer, DemuxerWithStreamInfo, SeekTarget}, io::IO}};
+use vide_lib::{io::{Import, Export, ChunkedExport, VideoDecoder as DecodeVideo, DecodedFrame, remove_partial}, error::{Error, Result}, api::{audio::AudioBuffer, image_sequence::format_pattern, video::VideoSettings}};
 
+use color::Matrix;
+
+/// Decoding forward is faster than seeking for jumps shorter than this (in seconds)
+const SEEK_THRESHOLD: f64 = 2.0;
+
+fn codec_error(err: ac_ffmpeg::Error) -> Error {
+    Error::Codec(err.to_string())
 }
 
+pub struct FFmpegImporter;
+
 impl Import for FFmpegImporter {
+    type Decoder = FFmpegDecoder;
+
     fn supported_import_extensions() -> Vec<String> {
-        todo!()
+        ["mp4", "m4v", "mov", "mkv", "webm", "avi", "flv", "wmv", "mpg", "mpeg", "ts", "ogv", "gif"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn open(path: impl AsRef<Path>) -> Result<FFmpegDecoder> {
+        FFmpegDecoder::open(path.as_ref())
+    }
+
+    fn open_audio(path: impl AsRef<Path>) -> Result<AudioBuffer> {
+        decode_audio(path.as_ref())
     }
 }
 
-fn open_output(path: &str, elementary_streams: &[ac_ffmpeg::codec::CodecParameters]) -> Result<Muxer<File>, ac_ffmpeg::Error> {
-    let output_format = OutputFormat::guess_from_file_name(path)
+fn open_input(path: &Path) -> Result<DemuxerWithStreamInfo<File>> {
+    let input = File::open(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let io = IO::from_seekable_read_stream(input);
+    Demuxer::builder()
+        .build(io)
+        .and_then(|demuxer| demuxer.find_stream_info(None).map_err(|(_, err)| err))
+        .map_err(codec_error)
+}
+
+/// Decodes the first audio stream of a file to interleaved floats at its own
+/// sample rate, sources with more than two channels are downmixed to stereo
+fn decode_audio(path: &Path) -> Result<AudioBuffer> {
+    let mut demuxer = open_input(path)?;
+    let stream_index = demuxer
+        .streams()
+        .iter()
+        .position(|stream| stream.codec_parameters().is_audio_codec())
+        .ok_or_else(|| Error::Codec(format!("{} has no audio stream", path.display())))?;
+    let mut decoder = AudioDecoder::from_stream(&demuxer.streams()[stream_index])
+        .and_then(|builder| builder.build())
+        .map_err(codec_error)?;
+
+    let mut resampler: Option<AudioResampler> = None;
+    let mut buffer = AudioBuffer::new(0, 0, Vec::new());
+
+    let mut resample = |frame: Option<AudioFrame>, buffer: &mut AudioBuffer| -> Result<()> {
+        if let Some(frame) = frame.as_ref() {
+            if resampler.is_none() {
+                let channels = frame.channel_layout().channels().min(2);
+                buffer.sample_rate = frame.sample_rate();
+                buffer.channels = channels as u16;
+                resampler = Some(
+                    AudioResampler::builder()
+                        .source_channel_layout(frame.channel_layout().to_owned())
+                        .source_sample_format(frame.sample_format())
+                        .source_sample_rate(frame.sample_rate())
+                        .target_channel_layout(ChannelLayout::from_channels(channels).unwrap())
+                        .target_sample_format(audio::frame::get_sample_format("flt"))
+                        .target_sample_rate(frame.sample_rate())
+                        .build()
+                        .map_err(codec_error)?,
+                );
+            }
+        }
+
+        let resampler = match resampler.as_mut() {
+            Some(resampler) => resampler,
+            None => return Ok(()),
+        };
+        match frame {
+            Some(frame) => resampler.push(frame).map_err(codec_error)?,
+            None => resampler.flush().map_err(codec_error)?,
+        }
+
+        while let Some(frame) = resampler.take().map_err(codec_error)? {
+            let bytes = frame.samples() * buffer.channels as usize * 4;
+            buffer.samples.extend(
+                frame.planes()[0].data()[..bytes]
+                    .chunks_exact(4)
+                    .map(|sample| f32::from_ne_bytes([sample[0], sample[1], sample[2], sample[3]])),
+            );
+        }
+        Ok(())
+    };
+
+    while let Some(packet) = demuxer.take().map_err(codec_error)? {
+        if packet.stream_index() != stream_index {
+            continue;
+        }
+
+        decoder.push(packet).map_err(codec_error)?;
+        while let Some(frame) = decoder.take().map_err(codec_error)? {
+            resample(Some(frame), &mut buffer)?;
+        }
+    }
+
+    decoder.flush().map_err(codec_error)?;
+    while let Some(frame) = decoder.take().map_err(codec_error)? {
+        resample(Some(frame), &mut buffer)?;
+    }
+    resample(None, &mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// Converts decoded frames to RGBA, or to [`color::INTERMEDIATE_FORMAT`] for
+/// Y'CbCr sources
+struct SourceScaler {
+    scaler: VideoFrameScaler,
+    /// Width, height and pixel format of the frames `scaler` takes
+    source: (usize, usize, &'static str),
+    /// Converts the scaled Y'CbCr frames to RGBA
+    matrix: Option<Matrix>,
+}
+
+/// Decodes the first video stream of a file, see [`DecodeVideo::frame_at`]
+pub struct FFmpegDecoder {
+    demuxer: DemuxerWithStreamInfo<File>,
+    decoder: VideoDecoder,
+    stream_index: usize,
+    /// Time the stream starts at in the file, timestamps are counted from it
+    start_time: f64,
+    /// Created once the source pixel format is known, and again whenever the
+    /// frames change size or format
+    scaler: Option<SourceScaler>,
+    resolution: (u32, u32),
+    end_of_stream: bool,
+    /// No seek happened yet, so `current` is the first frame of the file
+    at_start: bool,
+
+    /// Frame that is visible at the last requested time
+    current: Option<(f64, VideoFrame)>,
+    /// Frame decoded after `current`
+    next: Option<(f64, VideoFrame)>,
+    /// `current` converted to RGBA
+    converted: Option<(f64, Vec<u8>)>,
+}
+
+impl FFmpegDecoder {
+    pub fn open(path: &Path) -> Result<Self> {
+        let demuxer = open_input(path)?;
+
+        let (stream_index, parameters) = demuxer
+            .streams()
+            .iter()
+            .map(|stream| stream.codec_parameters())
+            .enumerate()
+            .find(|(_, parameters)| parameters.is_video_codec())
+            .ok_or_else(|| Error::Codec(format!("{} has no video stream", path.display())))?;
+        let parameters = parameters.as_video_codec_parameters().unwrap();
+        let resolution = (parameters.width() as u32, parameters.height() as u32);
+        let start_time = demuxer.streams()[stream_index].start_time().as_micros().map_or(0.0, |micros| micros as f64 / 1_000_000.0);
+
+        let decoder = VideoDecoder::from_stream(&demuxer.streams()[stream_index])
+            .and_then(|builder| builder.build())
+            .map_err(codec_error)?;
+
+        Ok(Self {
+            demuxer,
+            decoder,
+            stream_index,
+            start_time,
+            scaler: None,
+            resolution,
+            end_of_stream: false,
+            at_start: true,
+            current: None,
+            next: None,
+            converted: None,
+        })
+    }
+
+    fn seek(&mut self, time: f64) -> Result<()> {
+        // Lands on the last keyframe before `time`, decoding continues from there
+        self.demuxer
+            .seek_to_timestamp(Timestamp::from_micros(((self.start_time + time) * 1_000_000.0) as i64), SeekTarget::UpTo)
+            .map_err(codec_error)?;
+        self.decoder = VideoDecoder::from_stream(&self.demuxer.streams()[self.stream_index])
+            .and_then(|builder| builder.build())
+            .map_err(codec_error)?;
+
+        self.end_of_stream = false;
+        self.at_start = false;
+        self.current = None;
+        self.next = None;
+        Ok(())
+    }
+
+    fn decode_next(&mut self) -> Result<Option<(f64, VideoFrame)>> {
+        loop {
+            if let Some(frame) = self.decoder.take().map_err(codec_error)? {
+                let previous = self.current.as_ref().map(|(timestamp, _)| *timestamp).unwrap_or(0.0);
+                let timestamp = frame.pts().as_micros().map(|micros| micros as f64 / 1_000_000.0 - self.start_time).unwrap_or(previous);
+                return Ok(Some((timestamp, frame)));
+            }
+
+            if self.end_of_stream {
+                return Ok(None);
+            }
+
+            match self.demuxer.take().map_err(codec_error)? {
+                Some(packet) if packet.stream_index() == self.stream_index => self.decoder.push(packet).map_err(codec_error)?,
+                Some(_) => (),
+                None => {
+                    self.decoder.flush().map_err(codec_error)?;
+                    self.end_of_stream = true;
+                }
+            }
+        }
+    }
+
+    fn convert(scaler: &mut Option<SourceScaler>, resolution: (u32, u32), frame: &VideoFrame) -> Result<Vec<u8>> {
+        let (width, height) = (resolution.0 as usize, resolution.1 as usize);
+
+        // Streams can switch resolution or pixel format midway, swscale only takes the frames it was built for
+        let source = (frame.width(), frame.height(), frame.pixel_format().name());
+        if scaler.as_ref().is_none_or(|scaler| scaler.source != source) {
+            // Frames don't carry their colorspace through ac-ffmpeg, untagged
+            // video is assumed like players do. swscale converts `yuvj`
+            // (full range) sources to limited range on the way
+            let matrix = color::is_yuv(source.2).then(|| Matrix::for_height(frame.height()));
+            let target_format = if matrix.is_some() { color::INTERMEDIATE_FORMAT } else { "rgba" };
+            let new_scaler = VideoFrameScaler::builder()
+                .source_pixel_format(frame.pixel_format())
+                .source_width(frame.width())
+                .source_height(frame.height())
+                .target_pixel_format(video::frame::get_pixel_format(target_format))
+                .target_width(width)
+                .target_height(height)
+                .algorithm(Algorithm::Bicubic)
+                .build()
+                .map_err(codec_error)?;
+            *scaler = Some(SourceScaler {
+                scaler: new_scaler,
+                source,
+                matrix,
+            });
+        }
+        let SourceScaler { scaler, matrix, .. } = scaler.as_mut().unwrap();
+
+        let scaled = scaler.scale(frame).map_err(codec_error)?;
+        let planes = scaled.planes();
+        if let Some(matrix) = *matrix {
+            let planes = [0, 1, 2, 3].map(|index| (planes[index].data(), planes[index].line_size()));
+            return Ok(color::yuva_to_rgba(planes, (width, height), matrix, false));
+        }
+
+        let (data, line_size) = (planes[0].data(), planes[0].line_size());
+
+        // Rows of ffmpeg frames are padded
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for row in 0..height {
+            pixels.extend_from_slice(&data[row * line_size..row * line_size + width * 4]);
+        }
+        Ok(pixels)
+    }
+}
+
+impl DecodeVideo for FFmpegDecoder {
+    fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    fn frame_at(&mut self, time: f64) -> Result<Option<DecodedFrame<'_>>> {
+        let time = time.max(0.0);
+        let needs_seek = match self.current.as_ref() {
+            // Times before the first frame show the first frame
+            Some((timestamp, _)) => (time < *timestamp && !self.at_start) || time > timestamp + SEEK_THRESHOLD,
+            None => time > SEEK_THRESHOLD,
+        };
+        if needs_seek {
+            self.seek(time)?;
+        }
+
+        loop {
+            if self.next.is_none() {
+                self.next = self.decode_next()?;
+            }
+
+            match self.next.take() {
+                Some((timestamp, frame)) if timestamp <= time || self.current.is_none() => self.current = Some((timestamp, frame)),
+                Some(next) => {
+                    self.next = Some(next);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let timestamp = match self.current.as_ref() {
+            Some((timestamp, frame)) => {
+                if self.converted.as_ref().map(|(converted, _)| *converted) != Some(*timestamp) {
+                    self.converted = Some((*timestamp, Self::convert(&mut self.scaler, self.resolution, frame)?));
+                }
+                *timestamp
+            }
+            None => return Ok(None),
+        };
+
+        Ok(self.converted.as_ref().map(|(_, data)| DecodedFrame { timestamp, data }))
+    }
+}
+
+fn open_output(path: &str, format: &str, elementary_streams: &[ac_ffmpeg::codec::CodecParameters]) -> Result<Muxer<File>, ac_ffmpeg::Error> {
+    let output_format = OutputFormat::find_by_name(format)
+        .or_else(|| OutputFormat::guess_from_file_name(path))
         .ok_or_else(|| ac_ffmpeg::Error::new(format!("unable to guess output format for file: {}", path)))?;
 
     let output = File::create(path)
@@ -37,92 +349,433 @@ pub struct FFmpegExporter {
     output: String,
 
     container: String,
-    video_coding: String,
+    video_options: EncoderOptions,
     audio_coding: Option<String>,
 
     encoder: Option<VideoEncoder>,
+    /// Number of the next image, when exporting an image sequence
+    images: usize,
+    /// Number of the first image this exporter writes
+    first_image: usize,
+    /// Converts the rendered RGBA frames to the pixel format of the encoder
+    scaler: Option<VideoFrameScaler>,
+    /// Matrix and range frames are converted to Y'CbCr with before scaling,
+    /// `None` when the encoder takes RGB
+    yuv: Option<(Matrix, bool)>,
+    audio: Option<AudioTrack>,
     muxer: Option<Muxer<File>>,
 
     current_timestamp: i64,
     ms_per_frame: i64,
-    pixel_format: Option<PixelFormat>,
     resolution: (usize, usize),
 }
 
 impl FFmpegExporter { // TODO: Support multiple encoders and stuff
+    /// Exports to `output` in the `container` format (an ffmpeg muxer name
+    /// like `mp4`). The video is encoded with `video_coding` and the audio of
+    /// the video with `audio_coding`, `None` leaves out the audio.
+    ///
+    /// With the `image2` container every frame is written to its own file,
+    /// `output` has to contain a frame number pattern like `frame_%04d.png`
     pub fn new(output: impl ToString, container: impl ToString, video_coding: impl ToString, audio_coding: Option<String>) -> Self {
         Self {
             output: output.to_string(),
 
             container: container.to_string(),
-            video_coding: video_coding.to_string(),
+            video_options: EncoderOptions::codec(video_coding),
             audio_coding: audio_coding,
 
             encoder: None,
+            images: 0,
+            first_image: 0,
+            scaler: None,
+            yuv: None,
+            audio: None,
             muxer: None,
 
             current_timestamp: 0,
             ms_per_frame: 0,
-            pixel_format: None,
             resolution: (1920, 1080),
         }
     }
+
+    fn is_image_sequence(&self) -> bool {
+        self.container == "image2"
+    }
+
+    /// Every packet of an image encoder is a whole image file
+    fn write_image(&mut self, packet: &[u8]) -> Result<()> {
+        let path = format_pattern(&self.output, self.images);
+        // Counted before writing, so a partly written image is removed as well
+        self.images += 1;
+        std::fs::write(&path, packet).map_err(|source| Error::Io {
+            path: path.into(),
+            source,
+        })
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let image_sequence = self.is_image_sequence();
+        let encoder = self.encoder.as_mut().unwrap();
+        encoder.flush().map_err(codec_error)?;
+
+        if image_sequence {
+            while let Some(packet) = self.encoder.as_mut().unwrap().take().map_err(codec_error)? {
+                self.write_image(packet.data())?;
+            }
+            return Ok(());
+        }
+
+        let muxer = self.muxer.as_mut().unwrap();
+        while let Some(packet) = encoder.take().map_err(codec_error)? {
+            muxer.push(packet.with_stream_index(0)).map_err(codec_error)?;
+        }
+        if let Some(audio) = self.audio.as_mut() {
+            audio.finish(muxer).map_err(codec_error)?;
+        }
+        muxer.flush().map_err(codec_error)
+    }
+
+    /// Replaces the video encoder settings, including the codec passed to [`FFmpegExporter::new`]
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.video_options = options;
+        self
+    }
+
+    /// Copies the packets of every segment into the output, shifted by the
+    /// time their segment starts at. The audio is encoded alongside
+    fn join_segments(&self, settings: VideoSettings, audio: Option<AudioBuffer>, segments: &[Range<u64>]) -> Result<()> {
+        let first = open_input(Path::new(&segment_path(&self.output, 0)))?;
+        let mut audio = match audio.zip(self.audio_coding.as_deref()) {
+            Some((audio, codec)) => Some(AudioTrack::new(codec, audio).map_err(codec_error)?),
+            None => None,
+        };
+
+        let mut streams = vec![first.streams()[0].codec_parameters()];
+        if let Some(audio) = audio.as_ref() {
+            streams.push(audio.encoder.codec_parameters().into());
+        }
+        let mut muxer = open_output(self.output.as_str(), self.container.as_str(), &streams).map_err(codec_error)?;
+        drop(first);
+
+        for (index, frames) in segments.iter().enumerate() {
+            let offset = (frames.start as f64 / settings.fps * 1_000_000.0) as i64;
+            let shift = |timestamp: Timestamp| match timestamp.as_micros() {
+                Some(micros) => Timestamp::from_micros(micros + offset),
+                None => timestamp,
+            };
+
+            let mut demuxer = open_input(Path::new(&segment_path(&self.output, index)))?;
+            while let Some(packet) = demuxer.take().map_err(codec_error)? {
+                let (pts, dts) = (shift(packet.pts()), shift(packet.dts()));
+                muxer.push(packet.with_pts(pts).with_dts(dts).with_stream_index(0)).map_err(codec_error)?;
+
+                if let (Some(audio), Some(micros)) = (audio.as_mut(), pts.as_micros()) {
+                    audio.encode_until(micros as f64 / 1_000_000.0, &mut muxer).map_err(codec_error)?;
+                }
+            }
+        }
+
+        if let Some(audio) = audio.as_mut() {
+            audio.finish(&mut muxer).map_err(codec_error)?;
+        }
+        muxer.flush().map_err(codec_error)
+    }
+}
+
+/// `output.mp4` becomes `output.part3.mp4`
+fn segment_path(output: &str, index: usize) -> String {
+    match output.rfind('.') {
+        Some(dot) => format!("{}.part{}{}", &output[..dot], index, &output[dot..]),
+        None => format!("{}.part{}", output, index),
+    }
+}
+
+/// Mixed audio that is encoded alongside the video
+struct AudioTrack {
+    encoder: AudioEncoder,
+    buffer: AudioBuffer,
+    /// Next frame of `buffer` to encode
+    position: usize,
+    frame_size: usize,
+    sample_format: SampleFormat,
+    channel_layout: ChannelLayout,
+}
+
+impl AudioTrack {
+    fn new(codec: &str, buffer: AudioBuffer) -> Result<Self, ac_ffmpeg::Error> {
+        // The native AAC encoder only takes planar samples, libopus only packed ones
+        let sample_format = audio::frame::get_sample_format(if codec == "libopus" { "flt" } else { "fltp" });
+        let channel_layout = ChannelLayout::from_channels(buffer.channels as u32)
+            .ok_or_else(|| ac_ffmpeg::Error::new(format!("unsupported number of audio channels: {}", buffer.channels)))?;
+
+        let encoder = AudioEncoder::builder(codec)?
+            .sample_rate(buffer.sample_rate)
+            .channel_layout(channel_layout.clone())
+            .sample_format(sample_format)
+            .bit_rate(192_000)
+            .time_base(TimeBase::new(1, buffer.sample_rate as i32))
+            .build()?;
+
+        Ok(Self {
+            frame_size: encoder.samples_per_frame().unwrap_or(1024),
+            encoder,
+            buffer,
+            position: 0,
+            sample_format,
+            channel_layout,
+        })
+    }
+
+    /// Encodes all whole audio frames that start before `time` (in seconds),
+    /// so audio packets stay interleaved with the video
+    fn encode_until(&mut self, time: f64, muxer: &mut Muxer<File>) -> Result<(), ac_ffmpeg::Error> {
+        let end = ((time * self.buffer.sample_rate as f64) as usize).min(self.buffer.frames());
+        let channels = self.buffer.channels as usize;
+
+        while self.position < end {
+            let samples = self.frame_size.min(self.buffer.frames() - self.position);
+            let mut frame = AudioFrameMut::silence(&self.channel_layout, self.sample_format, self.buffer.sample_rate, self.frame_size);
+
+            let interleaved = &self.buffer.samples[self.position * channels..(self.position + samples) * channels];
+            let mut planes = frame.planes_mut();
+            if self.sample_format.is_planar() {
+                for (channel, plane) in planes.iter_mut().enumerate().take(channels) {
+                    for (sample, bytes) in interleaved.iter().skip(channel).step_by(channels).zip(plane.data_mut().chunks_exact_mut(4)) {
+                        bytes.copy_from_slice(&sample.to_ne_bytes());
+                    }
+                }
+            } else {
+                for (sample, bytes) in interleaved.iter().zip(planes[0].data_mut().chunks_exact_mut(4)) {
+                    bytes.copy_from_slice(&sample.to_ne_bytes());
+                }
+            }
+
+            let timestamp = Timestamp::new(self.position as i64, TimeBase::new(1, self.buffer.sample_rate as i32));
+            self.encoder.push(frame.with_pts(timestamp).freeze())?;
+            while let Some(packet) = self.encoder.take()? {
+                muxer.push(packet.with_stream_index(1))?;
+            }
+
+            self.position += self.frame_size;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, muxer: &mut Muxer<File>) -> Result<(), ac_ffmpeg::Error> {
+        self.encode_until(self.buffer.duration(), muxer)?;
+
+        self.encoder.flush()?;
+        while let Some(packet) = self.encoder.take()? {
+            muxer.push(packet.with_stream_index(1))?;
+        }
+        Ok(())
+    }
 }
 
 impl Export for FFmpegExporter {
-    fn begin(&mut self, settings: vide_lib::api::video::VideoSettings) {
+    fn begin(&mut self, settings: vide_lib::api::video::VideoSettings, audio: Option<AudioBuffer>) -> Result<()> {
         let time_base = TimeBase::new(1, 1_000_000);
-        let pixel_format = video::frame::get_pixel_format("rgb24");
-        
-        let encoder = VideoEncoder::builder("libx264rgb")
-            .unwrap()
+        let (width, height) = (settings.resolution.0 as usize, settings.resolution.1 as usize);
+        let pixel_format = video::frame::get_pixel_format(&self.video_options.pixel_format);
+
+        // Frames are converted to Y'CbCr here with the matrix and range the
+        // stream is tagged with, swscale would always use BT.601 limited range
+        let yuv = color::is_yuv(&self.video_options.pixel_format).then(|| {
+            let matrix = self.video_options.colorspace.as_deref().and_then(Matrix::from_name).unwrap_or_else(|| Matrix::for_height(height));
+            let full_range = self.video_options.color_range == ColorRange::Full || color::is_full_range_format(&self.video_options.pixel_format);
+            (matrix, full_range)
+        });
+        let source_format = video::frame::get_pixel_format(if yuv.is_some() { color::INTERMEDIATE_FORMAT } else { "rgba" });
+
+        let builder = VideoEncoder::builder(&self.video_options.codec)
+            .map_err(codec_error)?
             .pixel_format(pixel_format)
-            .width(settings.resolution.0 as usize)
-            .height(settings.resolution.1 as usize)
-            .time_base(time_base)
-            .build()
-            .unwrap();
-        
-        let codec_parameters = encoder.codec_parameters().into();
-        let muxer = open_output(self.output.as_str(), &[codec_parameters]).unwrap();
+            .width(width)
+            .height(height)
+            .time_base(time_base);
+        let encoder = self.video_options.apply(builder)?.build().map_err(codec_error)?;
+
+        let scaler = match pixel_format != source_format {
+            true => Some(
+                VideoFrameScaler::builder()
+                    .source_pixel_format(source_format)
+                    .source_width(width)
+                    .source_height(height)
+                    .target_pixel_format(pixel_format)
+                    .target_width(width)
+                    .target_height(height)
+                    .algorithm(Algorithm::Bicubic)
+                    .build()
+                    .map_err(codec_error)?,
+            ),
+            false => None,
+        };
+
+        self.yuv = yuv;
+        if self.is_image_sequence() {
+            self.encoder = Some(encoder);
+            self.scaler = scaler;
+            self.ms_per_frame = ((1.0 / settings.fps) * 1000000.0) as i64;
+            self.resolution = (width, height);
+            return Ok(());
+        }
+
+        let audio = match audio.zip(self.audio_coding.as_deref()) {
+            Some((audio, codec)) => Some(AudioTrack::new(codec, audio).map_err(codec_error)?),
+            None => None,
+        };
+
+        let mut streams = vec![encoder.codec_parameters().into()];
+        if let Some(audio) = audio.as_ref() {
+            streams.push(audio.encoder.codec_parameters().into());
+        }
+        let muxer = open_output(self.output.as_str(), self.container.as_str(), &streams).map_err(codec_error)?;
 
         self.encoder = Some(encoder);
+        self.scaler = scaler;
+        self.audio = audio;
         self.muxer = Some(muxer);
         self.ms_per_frame = ((1.0 / settings.fps) * 1000000.0) as i64;
-        self.pixel_format = Some(pixel_format);
-        self.resolution = (settings.resolution.0 as usize, settings.resolution.1 as usize);
+        self.resolution = (width, height);
+        Ok(())
     }
 
-    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) {
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
         let timestamp = Timestamp::from_micros(self.current_timestamp);
+        let image_sequence = self.is_image_sequence();
         let encoder = self.encoder.as_mut().unwrap();
-        let muxer = self.muxer.as_mut().unwrap();
 
         {
-            // Allocate frame
-            let mut new_frame = VideoFrameMut::black(self.pixel_format.unwrap(), self.resolution.0, self.resolution.1);
-            // Copy texture (and remove 4th component of each pixel) to frame
-            new_frame.planes_mut()[0].data_mut().write_all(&frame.chunks(4).flat_map(|p|[p[0], p[1], p[2]]).collect::<Vec<_>>()[..]).unwrap();
+            let (width, height) = self.resolution;
+            if frame.len() != width * height * 4 {
+                return Err(Error::Codec(format!("frame has {} bytes, expected {}x{} RGBA", frame.len(), width, height)));
+            }
+
+            // Copy texture to frame, rows of ffmpeg frames are padded
+            let new_frame = match self.yuv {
+                Some((matrix, full_range)) => {
+                    let mut new_frame = VideoFrameMut::black(video::frame::get_pixel_format(color::INTERMEDIATE_FORMAT), width, height);
+                    let mut planes = new_frame.planes_mut();
+                    for (index, plane) in color::rgba_to_yuva(frame, matrix, full_range).iter().enumerate() {
+                        let line_size = planes[index].line_size();
+                        for (row, samples) in plane.chunks_exact(width).enumerate() {
+                            let data = &mut planes[index].data_mut()[row * line_size..row * line_size + width * 2];
+                            for (bytes, sample) in data.chunks_exact_mut(2).zip(samples) {
+                                bytes.copy_from_slice(&sample.to_le_bytes());
+                            }
+                        }
+                    }
+                    new_frame
+                }
+                None => {
+                    let mut new_frame = VideoFrameMut::black(video::frame::get_pixel_format("rgba"), width, height);
+                    let mut planes = new_frame.planes_mut();
+                    let line_size = planes[0].line_size();
+                    for (row, pixels) in frame.chunks_exact(width * 4).enumerate() {
+                        planes[0].data_mut()[row * line_size..row * line_size + width * 4].copy_from_slice(pixels);
+                    }
+                    new_frame
+                }
+            };
+
+            let new_frame = new_frame.freeze();
+            let new_frame = match self.scaler.as_mut() {
+                Some(scaler) => scaler.scale(&new_frame).map_err(codec_error)?,
+                None => new_frame,
+            };
             // Add to encoder queue
-            encoder.push(new_frame.with_pts(timestamp).freeze()).unwrap();
+            encoder.push(new_frame.with_pts(timestamp)).map_err(codec_error)?;
+        }
+
+        self.current_timestamp += self.ms_per_frame;
+
+        if image_sequence {
+            while let Some(packet) = self.encoder.as_mut().unwrap().take().map_err(codec_error)? {
+                self.write_image(packet.data())?;
+            }
+            return Ok(());
         }
 
         // Await encoder and add to muxer queue
-        while let Some(packet) = encoder.take().unwrap() {
-            muxer.push(packet.with_stream_index(0)).unwrap();
+        let muxer = self.muxer.as_mut().unwrap();
+        while let Some(packet) = encoder.take().map_err(codec_error)? {
+            muxer.push(packet.with_stream_index(0)).map_err(codec_error)?;
         }
 
-        self.current_timestamp += self.ms_per_frame;
+        if let Some(audio) = self.audio.as_mut() {
+            audio.encode_until(self.current_timestamp as f64 / 1_000_000.0, muxer).map_err(codec_error)?;
+        }
+        Ok(())
     }
 
-    fn end(mut self) {
-        let encoder = self.encoder.as_mut().unwrap();
-        let muxer = self.muxer.as_mut().unwrap();
+    fn end(mut self) -> Result<()> {
+        let result = self.finish();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
 
-        encoder.flush().unwrap();
-        while let Some(packet) = encoder.take().unwrap() {
-            muxer.push(packet.with_stream_index(0)).unwrap();
+    fn abort(mut self) {
+        if self.is_image_sequence() {
+            for image in self.first_image..self.images {
+                remove_partial(Path::new(&format_pattern(&self.output, image)));
+            }
+            return;
         }
-        muxer.flush().unwrap();
+
+        // Closes the file before removing it
+        drop(self.muxer.take());
+        remove_partial(Path::new(&self.output));
+    }
+}
+
+/// Segments are encoded without audio and joined without encoding the video
+/// again. Image sequences are written to their final names right away
+impl ChunkedExport for FFmpegExporter {
+    type Segment = FFmpegExporter;
+
+    fn segment(&self, index: usize, frames: Range<u64>) -> Self {
+        let (output, images) = match self.is_image_sequence() {
+            true => (self.output.clone(), frames.start as usize),
+            false => (segment_path(&self.output, index), 0),
+        };
+
+        let mut segment = FFmpegExporter::new(output, &self.container, &self.video_options.codec, None).with_options(EncoderOptions {
+            // Without B-frames, the timestamps of one segment all come before those of the next one
+            b_frames: Some(0),
+            ..self.video_options.clone()
+        });
+        segment.images = images;
+        segment.first_image = images;
+        segment
     }
-}
\ No newline at end of file
+
+    fn concat(self, settings: VideoSettings, audio: Option<AudioBuffer>, segments: &[Range<u64>]) -> Result<()> {
+        if self.is_image_sequence() {
+            return Ok(());
+        }
+
+        let result = self.join_segments(settings, audio, segments);
+        self.abort_segments(segments);
+        if result.is_err() {
+            remove_partial(Path::new(&self.output));
+        }
+        result
+    }
+
+    fn abort_segments(self, segments: &[Range<u64>]) {
+        if self.is_image_sequence() {
+            for image in segments.iter().flat_map(|frames| frames.clone()) {
+                remove_partial(Path::new(&format_pattern(&self.output, image as usize)));
+            }
+            return;
+        }
+
+        for index in 0..segments.len() {
+            remove_partial(Path::new(&segment_path(&self.output, index)));
+        }
+    }
+}
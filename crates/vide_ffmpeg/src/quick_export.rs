@@ -1,16 +1,126 @@
-use crate::FFmpegExporter;
-
-pub fn to(output_file: impl ToString) -> FFmpegExporter {
-    let extension = output_file
-        .to_string()
-        .split('.')
-        .last()
-        .unwrap_or_else(|| panic!("Vide Quick Export couldn't detect the file extension for {}", output_file.to_string()))
-        .to_string();
-    let extension = extension.as_str();
-
-    match extension {
-        "mp4" => FFmpegExporter::new(output_file, "mp4", "libx264", None),
-        other => panic!("Vide Quick Export does not support or recognize {} (yet", other),
-    }
-}
\ No newline at end of file
+use std::fmt::Display;
+
+use vide_lib::{
+    api::{audio::AudioBuffer, video::VideoSettings},
+    error::Result,
+    io::{gif::{GifExporter, GifOptions}, Export},
+};
+
+use crate::{EncoderOptions, FFmpegExporter};
+
+/// `quick_export::to` doesn't know what to export to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuickExportError {
+    /// The output file has no extension
+    MissingExtension(String),
+    /// No preset exists for the extension
+    UnknownExtension(String),
+}
+
+impl Display for QuickExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuickExportError::MissingExtension(output) => write!(f, "Vide Quick Export couldn't detect the file extension for {}", output),
+            QuickExportError::UnknownExtension(extension) => write!(f, "Vide Quick Export does not support or recognize .{} (yet)", extension),
+        }
+    }
+}
+
+impl std::error::Error for QuickExportError {}
+
+/// Picks an exporter with sensible settings for the extension of `output_file`:
+///
+/// - `.mp4` and `.mkv`: H.264 with AAC audio
+/// - `.webm`: VP9 with Opus audio
+/// - `.mov`: ProRes 422 HQ with AAC audio
+/// - `.gif`: [`GifExporter`] with a palette generated from the frames
+/// - `.apng`: animated PNG without audio
+/// - `.png` and `.exr`: one image per frame, the file name gets a frame
+///   number unless it already has a pattern like `frame_%04d.png`
+///
+/// The video presets (`.mp4`, `.mkv`, `.webm` and `.mov`) drop the alpha
+/// channel. To keep it, use an [`FFmpegExporter`] with
+/// [`EncoderOptions::prores_4444`] or [`EncoderOptions::qtrle`] for `.mov`,
+/// or [`EncoderOptions::vp9_alpha`] for `.webm`
+pub fn to(output_file: impl ToString) -> Result<QuickExporter, QuickExportError> {
+    let output_file = output_file.to_string();
+    let extension = std::path::Path::new(&output_file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .ok_or_else(|| QuickExportError::MissingExtension(output_file.clone()))?
+        .to_lowercase();
+
+    let exporter = match extension.as_str() {
+        "mp4" => FFmpegExporter::new(output_file, "mp4", "libx264", Some("aac".to_string())),
+        "mkv" => FFmpegExporter::new(output_file, "matroska", "libx264", Some("aac".to_string())),
+        "webm" => FFmpegExporter::new(output_file, "webm", "libvpx-vp9", Some("libopus".to_string())),
+        "mov" => FFmpegExporter::new(output_file, "mov", "prores_ks", Some("aac".to_string())).with_options(EncoderOptions {
+            // 422 HQ
+            codec_options: vec![("profile".to_string(), "3".to_string())],
+            ..EncoderOptions::codec("prores_ks")
+        }),
+        // ffmpeg's GIF encoder only has a fixed palette, which bands gradients
+        "gif" => return Ok(QuickExporter::Gif(GifExporter::new(output_file, GifOptions::default()))),
+        "apng" => FFmpegExporter::new(output_file, "apng", "apng", None),
+        "png" => FFmpegExporter::new(with_frame_pattern(&output_file), "image2", "png", None),
+        "exr" => FFmpegExporter::new(with_frame_pattern(&output_file), "image2", "exr", None),
+        _ => return Err(QuickExportError::UnknownExtension(extension)),
+    };
+
+    Ok(QuickExporter::FFmpeg(exporter))
+}
+
+/// Exporter picked by [`to`]
+pub enum QuickExporter {
+    FFmpeg(FFmpegExporter),
+    Gif(GifExporter),
+}
+
+impl Export for QuickExporter {
+    fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()> {
+        match self {
+            QuickExporter::FFmpeg(exporter) => exporter.begin(settings, audio),
+            QuickExporter::Gif(exporter) => exporter.begin(settings, audio),
+        }
+    }
+
+    fn push_frame(&mut self, keyframe: bool, frame: &[u8]) -> Result<()> {
+        match self {
+            QuickExporter::FFmpeg(exporter) => exporter.push_frame(keyframe, frame),
+            QuickExporter::Gif(exporter) => exporter.push_frame(keyframe, frame),
+        }
+    }
+
+    fn skip_frame(&mut self, index: u64) -> bool {
+        match self {
+            QuickExporter::FFmpeg(exporter) => exporter.skip_frame(index),
+            QuickExporter::Gif(exporter) => exporter.skip_frame(index),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        match self {
+            QuickExporter::FFmpeg(exporter) => exporter.end(),
+            QuickExporter::Gif(exporter) => exporter.end(),
+        }
+    }
+
+    fn abort(self) {
+        match self {
+            QuickExporter::FFmpeg(exporter) => exporter.abort(),
+            QuickExporter::Gif(exporter) => exporter.abort(),
+        }
+    }
+}
+
+/// `frames.png` becomes `frames_%05d.png`, paths that already have a pattern stay the same
+fn with_frame_pattern(output_file: &str) -> String {
+    if output_file.contains('%') {
+        return output_file.to_string();
+    }
+
+    match output_file.rfind('.') {
+        Some(dot) => format!("{}_%05d{}", &output_file[..dot], &output_file[dot..]),
+        None => format!("{}_%05d", output_file),
+    }
+}
@@ -0,0 +1,85 @@
+//! Renders a clip in parallel segments and checks the joined output
+
+use std::{fs::File, path::Path, time::Duration};
+
+use ac_ffmpeg::format::{demuxer::Demuxer, io::IO};
+use vide_ffmpeg::FFmpegExporter;
+use vide_lib::prelude::*;
+
+const FPS: f64 = 10.0;
+const FRAMES: u64 = 30;
+const WORKERS: usize = 3;
+
+/// Same scene on every worker, a rect moving over the background
+fn build() -> Video<'static> {
+    let settings = VideoSettings {
+        fps: FPS,
+        resolution: (64, 48),
+        duration: Duration::from_secs_f64(FRAMES as f64 / FPS),
+        // Renders the same on machines without a GPU
+        adapter: AdapterSelection::Software,
+        ..Default::default()
+    };
+
+    let mut video = Video::new(settings);
+    video.root().new_clip(0.0..3.0).effect(Rect {
+        position: Animation::new(FPS)
+            .keyframe(Abs(0.0), ease::LINEAR, (-16.0, 0.0))
+            .keyframe(Rel(3.0), ease::LINEAR, (16.0, 0.0))
+            .build(),
+        size: unanimated!((24.0, 24.0)),
+        color: unanimated!(rgb8!(0xda, 0x00, 0x37)),
+    });
+    video
+}
+
+/// Decode timestamps of every video packet in `path`, in microseconds
+fn packet_timestamps(path: &Path) -> Vec<i64> {
+    let io = IO::from_seekable_read_stream(File::open(path).unwrap());
+    let mut demuxer = Demuxer::builder()
+        .build(io)
+        .unwrap()
+        .find_stream_info(None)
+        .map_err(|(_, err)| err)
+        .unwrap();
+
+    let mut timestamps = Vec::new();
+    while let Some(packet) = demuxer.take().unwrap() {
+        assert_eq!(packet.stream_index(), 0);
+        timestamps.push(packet.dts().as_micros().expect("packet without timestamp"));
+    }
+    timestamps
+}
+
+fn render_and_join(extension: &str, container: &str) {
+    let dir = std::env::temp_dir().join(format!("vide_join_segments_{}_{}", extension, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join(format!("output.{}", extension));
+
+    Video::render_parallel(build, WORKERS, FFmpegExporter::new(output.to_str().unwrap(), container, "libx264", None)).unwrap();
+
+    let timestamps = packet_timestamps(&output);
+    assert_eq!(timestamps.len() as u64, FRAMES);
+    assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]), "timestamps aren't monotonic: {:?}", timestamps);
+
+    // Every segment is shifted by the time it starts at, so the last frame
+    // lands at the end of the video rather than the end of the first segment
+    let frame_duration = (1_000_000.0 / FPS) as i64;
+    let span = timestamps[timestamps.len() - 1] - timestamps[0];
+    assert!((span - (FRAMES as i64 - 1) * frame_duration).abs() <= frame_duration / 10, "span {}", span);
+
+    // Only the joined output is left
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    assert_eq!(files, 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn mp4() {
+    render_and_join("mp4", "mp4");
+}
+
+#[test]
+fn mkv() {
+    render_and_join("mkv", "matroska");
+}
@@ -0,0 +1,114 @@
+//! Renders a short clip with every quick export preset and probes the result
+
+use std::{path::{Path, PathBuf}, time::Duration};
+
+use vide_ffmpeg::{quick_export, FFmpegDecoder};
+use vide_lib::{io::VideoDecoder, prelude::*};
+
+const RESOLUTION: (u32, u32) = (64, 48);
+const FRAMES: u64 = 10;
+
+fn output_dir(preset: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vide_quick_export_{}_{}", preset, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Renders a rect moving over the background to `output`
+fn render(output: &Path) {
+    let settings = VideoSettings {
+        fps: 10.0,
+        resolution: RESOLUTION,
+        duration: Duration::from_secs_f64(FRAMES as f64 / 10.0),
+        // Renders the same on machines without a GPU
+        adapter: AdapterSelection::Software,
+        ..Default::default()
+    };
+
+    let mut video = Video::new(settings);
+    video.root().new_clip(0.0..1.0).effect(Rect {
+        position: Animation::new(10.0)
+            .keyframe(Abs(0.0), ease::LINEAR, (-16.0, 0.0))
+            .keyframe(Rel(1.0), ease::LINEAR, (16.0, 0.0))
+            .build(),
+        size: unanimated!((24.0, 24.0)),
+        color: unanimated!(rgb8!(0xda, 0x00, 0x37)),
+    });
+
+    video.render(quick_export::to(output.to_str().unwrap()).unwrap()).unwrap();
+}
+
+/// Opens `path` with ffmpeg and checks its resolution and that the first frame decodes
+fn probe(path: &Path) {
+    let mut decoder = FFmpegDecoder::open(path).unwrap();
+    assert_eq!(decoder.resolution(), RESOLUTION, "{}", path.display());
+
+    let frame = decoder.frame_at(0.0).unwrap().expect("no frame decoded");
+    assert_eq!(frame.data.len(), (RESOLUTION.0 * RESOLUTION.1 * 4) as usize);
+}
+
+fn render_and_probe(extension: &str) {
+    let dir = output_dir(extension);
+    let output = dir.join(format!("output.{}", extension));
+    render(&output);
+    probe(&output);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn render_and_probe_sequence(extension: &str) {
+    let dir = output_dir(extension);
+    render(&dir.join(format!("frame.{}", extension)));
+
+    for frame in 0..FRAMES {
+        let image = dir.join(format!("frame_{:05}.{}", frame, extension));
+        assert!(image.is_file(), "{} is missing", image.display());
+    }
+    probe(&dir.join(format!("frame_00000.{}", extension)));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn mp4() {
+    render_and_probe("mp4");
+}
+
+#[test]
+fn mkv() {
+    render_and_probe("mkv");
+}
+
+#[test]
+fn webm() {
+    render_and_probe("webm");
+}
+
+#[test]
+fn mov() {
+    render_and_probe("mov");
+}
+
+#[test]
+fn gif() {
+    render_and_probe("gif");
+}
+
+#[test]
+fn apng() {
+    render_and_probe("apng");
+}
+
+#[test]
+fn png() {
+    render_and_probe_sequence("png");
+}
+
+#[test]
+fn exr() {
+    render_and_probe_sequence("exr");
+}
+
+#[test]
+fn unknown_extension() {
+    assert!(matches!(quick_export::to("output.xyz"), Err(quick_export::QuickExportError::UnknownExtension(_))));
+    assert!(matches!(quick_export::to("output"), Err(quick_export::QuickExportError::MissingExtension(_))));
+}
@@ -139,6 +139,23 @@ where
     }
 }
 
+/// Types out the new text: the part of `a` that `b` doesn't share is erased
+/// first, then the rest of `b` is typed
+impl Interpolate for String {
+    fn interpolate(a: Self, b: Self, t: f64) -> Self {
+        let common = a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count();
+        let erase = a.chars().count() - common;
+        let write = b.chars().count() - common;
+
+        let steps = ((erase + write) as f64 * t.clamp(0.0, 1.0)).round() as usize;
+        if steps <= erase {
+            a.chars().take(a.chars().count() - steps).collect()
+        } else {
+            b.chars().take(common + steps - erase).collect()
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Keyframe<T: Interpolate> {
     pub easing: EasingFunction,
@@ -291,3 +308,31 @@ impl<T: Interpolate + Clone + std::fmt::Debug> AnimatedPropertyBuilder<T> {
         AnimatedProperty::new(self.initial.to_owned().unwrap(), self.keyframes.to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Interpolate;
+
+    fn typed(a: &str, b: &str, t: f64) -> String {
+        String::interpolate(a.to_string(), b.to_string(), t)
+    }
+
+    #[test]
+    fn strings_are_erased_then_typed() {
+        let steps = [0.0, 0.25, 0.5, 0.75, 1.0].map(|t| typed("hello", "help!", t));
+        assert_eq!(steps, ["hello", "hell", "hel", "help", "help!"]);
+
+        assert_eq!(typed("", "abc", 1.0 / 3.0), "a");
+        assert_eq!(typed("abc", "", 1.0 / 3.0), "ab");
+        assert_eq!(typed("abc", "abcdef", 0.5), "abcde");
+        assert_eq!(typed("same", "same", 0.5), "same");
+    }
+
+    #[test]
+    fn strings_are_typed_by_character() {
+        assert_eq!(typed("día", "dúo", 0.25), "dí");
+        assert_eq!(typed("día", "dúo", 0.75), "dú");
+        assert_eq!(typed("ab", "cd", -1.0), "ab");
+        assert_eq!(typed("ab", "cd", 2.0), "cd");
+    }
+}
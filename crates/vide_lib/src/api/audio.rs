@@ -0,0 +1,293 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{error::{Error, Result}, io::Import, lerp, unanimated};
+
+use super::animation::AnimatedProperty;
+
+/// Interleaved 32-bit float PCM samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// One sample per channel for every frame, `[left, right, left, right, ...]` for stereo
+    pub samples: Vec<f32>,
+}
+
+impl AudioBuffer {
+    pub fn new(sample_rate: u32, channels: u16, samples: Vec<f32>) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            samples,
+        }
+    }
+
+    /// `duration` seconds of silence
+    pub fn silent(sample_rate: u32, channels: u16, duration: f64) -> Self {
+        let frames = (duration * sample_rate as f64).ceil() as usize;
+        Self::new(sample_rate, channels, vec![0.0; frames * channels as usize])
+    }
+
+    /// Number of samples per channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    /// Length in seconds
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+
+    /// The part between `start` and `end` seconds, clamped to the buffer
+    pub fn slice(&self, start: f64, end: f64) -> Self {
+        let frame = |time: f64| ((time * self.sample_rate as f64).round().max(0.0) as usize).min(self.frames());
+        let (start, end) = (frame(start), frame(end));
+        let channels = self.channels as usize;
+
+        Self::new(self.sample_rate, self.channels, self.samples[start * channels..end.max(start) * channels].to_vec())
+    }
+
+    /// Sample of `channel` at `time` seconds, interpolated between samples
+    /// with a Catmull-Rom spline. Silent outside of the buffer. Channels past
+    /// the last one repeat the buffer's channels, so mono plays on both sides
+    pub fn sample_at(&self, channel: u16, time: f64) -> f32 {
+        let channel = (channel % self.channels.max(1)) as usize;
+        let position = time * self.sample_rate as f64;
+        let index = position.floor() as i64;
+        let t = (position - index as f64) as f32;
+
+        let sample = |i: i64| {
+            if i < 0 || i as usize >= self.frames() {
+                0.0
+            } else {
+                self.samples[i as usize * self.channels as usize + channel]
+            }
+        };
+
+        let (p0, p1, p2, p3) = (sample(index - 1), sample(index), sample(index + 1), sample(index + 2));
+        if t == 0.0 {
+            return p1;
+        }
+
+        0.5 * (2.0 * p1
+            + (p2 - p0) * t
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+            + (3.0 * (p1 - p2) + p3 - p0) * t * t * t)
+    }
+
+    /// Writes the samples as a 16-bit PCM WAV file
+    pub fn write_wav(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let data_size = self.samples.len() as u32 * 2;
+        let block_align = self.channels * 2;
+
+        let mut wav = Vec::with_capacity(44 + data_size as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&self.channels.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        for sample in self.samples.iter() {
+            wav.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+        }
+
+        std::fs::write(path, wav).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Decoded audio file, cheap to clone
+#[derive(Debug, Clone)]
+pub struct AudioSource {
+    buffer: Arc<AudioBuffer>,
+}
+
+impl AudioSource {
+    /// Decodes an audio file with an importer like `vide_ffmpeg::FFmpegImporter`
+    pub fn open<I: Import>(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_buffer(I::open_audio(path)?))
+    }
+
+    pub fn from_buffer(buffer: AudioBuffer) -> Self {
+        Self {
+            buffer: Arc::new(buffer),
+        }
+    }
+
+    pub fn buffer(&self) -> &AudioBuffer {
+        &self.buffer
+    }
+
+    /// Length in seconds
+    pub fn duration(&self) -> f64 {
+        self.buffer.duration()
+    }
+}
+
+/// Converts decibels to a linear factor, `0.0` dB is `1.0`
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Lowers the volume of an [`Audio`] while any audio marked as
+/// [`Audio::sidechain`] is playing, e.g. music under a voice-over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ducking {
+    /// Level (in dB) the sidechain has to exceed to duck
+    pub threshold: f32,
+    /// Volume change in decibels while ducked, usually negative
+    pub amount: f32,
+    /// Seconds to fade down once the sidechain gets loud
+    pub attack: f64,
+    /// Seconds to fade back up once the sidechain is quiet again
+    pub release: f64,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            threshold: -40.0,
+            amount: -12.0,
+            attack: 0.05,
+            release: 0.5,
+        }
+    }
+}
+
+impl Ducking {
+    /// Gain for every frame of `sidechain`
+    fn gain_curve(&self, sidechain: &AudioBuffer) -> Vec<f32> {
+        let rate = sidechain.sample_rate as f64;
+        // Bridges the gaps between the periods of low voices, so the detected level doesn't flutter
+        let hold = (0.02 * rate) as usize;
+        let ducked = db_to_gain(self.amount);
+        let threshold = db_to_gain(self.threshold);
+        let coefficient = |seconds: f64| if seconds > 0.0 { (1.0 - (-1.0 / (seconds * rate)).exp()) as f32 } else { 1.0 };
+        let (attack, release) = (coefficient(self.attack), coefficient(self.release));
+
+        let mut gain = 1.0;
+        let mut held = 0;
+        sidechain
+            .samples
+            .chunks_exact(sidechain.channels.max(1) as usize)
+            .map(|frame| {
+                if frame.iter().any(|sample| sample.abs() > threshold) {
+                    held = hold;
+                } else {
+                    held = held.saturating_sub(1);
+                }
+
+                let target = if held > 0 { ducked } else { 1.0 };
+                gain += (target - gain) * if target < gain { attack } else { release };
+                gain
+            })
+            .collect()
+    }
+}
+
+/// Plays an [`AudioSource`] in the clip it is added to. The audio follows the
+/// clip's start, end, speed and time remapping like effects do
+pub struct Audio {
+    pub source: AudioSource,
+    /// Volume change in decibels, `0.0` plays the source unchanged
+    pub gain: AnimatedProperty<f32>,
+    /// Stereo balance from `-1.0` (left) to `1.0` (right)
+    pub pan: AnimatedProperty<f32>,
+    /// Time (in seconds) into the source played at the start of the clip
+    pub start: f64,
+    /// Seconds of the source to play from `start`, `None` plays until the source or clip ends
+    pub length: Option<f64>,
+    /// Seconds the volume rises from silence at the start
+    pub fade_in: f64,
+    /// Seconds the volume falls to silence before the clip or source ends
+    pub fade_out: f64,
+    /// Other audio is ducked under this one, see [`Ducking`]
+    pub sidechain: bool,
+    pub ducking: Option<Ducking>,
+}
+
+impl Audio {
+    pub fn new(source: AudioSource) -> Self {
+        Self {
+            source,
+            gain: unanimated!(0.0),
+            pan: unanimated!(0.0),
+            start: 0.0,
+            length: None,
+            fade_in: 0.0,
+            fade_out: 0.0,
+            sidechain: false,
+            ducking: None,
+        }
+    }
+
+    /// Adds this audio to `output`. `local_time` maps a time of the output to
+    /// the time seen by the clip (or `None` while the clip isn't playing) and
+    /// `clip_length` is the time the clip stops at, both in seconds
+    pub(crate) fn mix_into(&self, output: &mut AudioBuffer, local_time: &dyn Fn(f64) -> Option<f64>, clip_length: f64, fps: f64, sidechain: Option<&AudioBuffer>) {
+        let source = self.source.buffer();
+        let length = self.length.unwrap_or(f64::INFINITY).min(source.duration() - self.start).min(clip_length);
+        let ducking = self.ducking.zip(sidechain).map(|(ducking, sidechain)| ducking.gain_curve(sidechain));
+
+        // Properties are evaluated once per frame and interpolated in between, to avoid clicks
+        let mut evaluated: Option<(u64, [f32; 4])> = None;
+        let rate = output.sample_rate as f64;
+        let channels = output.channels as usize;
+
+        for frame in 0..output.frames() {
+            let time = match local_time(frame as f64 / rate) {
+                Some(time) if time >= 0.0 && time < length => time,
+                _ => continue,
+            };
+
+            let position = time * fps;
+            let clip_frame = position.floor() as u64;
+            let [gain, next_gain, pan, next_pan] = match evaluated {
+                Some((evaluated_frame, values)) if evaluated_frame == clip_frame => values,
+                _ => {
+                    let values = [
+                        self.gain.evaluate(clip_frame),
+                        self.gain.evaluate(clip_frame + 1),
+                        self.pan.evaluate(clip_frame),
+                        self.pan.evaluate(clip_frame + 1),
+                    ];
+                    evaluated = Some((clip_frame, values));
+                    values
+                }
+            };
+            let t = (position - clip_frame as f64) as f32;
+
+            let mut fade = 1.0f64;
+            if self.fade_in > 0.0 {
+                fade = fade.min(time / self.fade_in);
+            }
+            if self.fade_out > 0.0 {
+                fade = fade.min((length - time) / self.fade_out);
+            }
+
+            let mut volume = db_to_gain(lerp!(gain, next_gain, t)) * fade.clamp(0.0, 1.0) as f32;
+            if let Some(ducking) = ducking.as_ref() {
+                volume *= ducking[frame];
+            }
+
+            let pan = lerp!(pan, next_pan, t).clamp(-1.0, 1.0);
+            for channel in 0..channels {
+                let balance = match (channels, channel) {
+                    (2, 0) => (1.0 - pan).min(1.0),
+                    (2, _) => (1.0 + pan).min(1.0),
+                    _ => 1.0,
+                };
+                output.samples[frame * channels + channel] += source.sample_at(channel as u16, self.start + time) * volume * balance;
+            }
+        }
+    }
+}
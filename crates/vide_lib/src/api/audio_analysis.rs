@@ -0,0 +1,307 @@
+use std::f32::consts::PI;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use super::{
+    animation::{ease::LINEAR, AnimatedProperty, Interpolate, Keyframe},
+    audio::AudioSource,
+};
+
+/// How the frequency range of an [`AudioAnalysis`] is split into bands
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BandScale {
+    /// Every band covers the same number of Hz, most bands end up in the treble
+    Linear,
+    /// Every band covers the same number of octaves
+    #[default]
+    Log,
+    /// Follows the mel scale, close to how far apart pitches sound
+    Mel,
+}
+
+impl BandScale {
+    fn scaled(self, frequency: f32) -> f32 {
+        match self {
+            BandScale::Linear => frequency,
+            BandScale::Log => frequency.max(1.0).ln(),
+            BandScale::Mel => 2595.0 * (1.0 + frequency / 700.0).log10(),
+        }
+    }
+
+    fn unscaled(self, value: f32) -> f32 {
+        match self {
+            BandScale::Linear => value,
+            BandScale::Log => value.exp(),
+            BandScale::Mel => 700.0 * (10f32.powf(value / 2595.0) - 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalysisSettings {
+    /// Frames per second of the analysis, use the fps of the video
+    pub fps: f64,
+    /// Number of spectrum bands
+    pub bands: usize,
+    pub scale: BandScale,
+    /// Lowest frequency (in Hz) covered by the bands
+    pub min_frequency: f32,
+    /// Highest frequency (in Hz) covered by the bands
+    pub max_frequency: f32,
+    /// Samples per FFT, larger windows resolve low frequencies better but react slower
+    pub window_size: usize,
+    /// Bands at this level (in dB) or quieter are `0.0`
+    pub floor: f32,
+    /// Seconds a value takes to rise, `0.0` follows the audio immediately
+    pub attack: f64,
+    /// Seconds a value takes to fall, larger values make bars fall slowly
+    pub release: f64,
+    /// How far (relative to its recent average) the spectral flux has to rise
+    /// to count as an onset, lower values detect more onsets
+    pub onset_sensitivity: f32,
+    /// Shortest time (in seconds) between two onsets
+    pub onset_gap: f64,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        Self {
+            fps: 60.0,
+            bands: 32,
+            scale: BandScale::Log,
+            min_frequency: 20.0,
+            max_frequency: 20000.0,
+            window_size: 2048,
+            floor: -60.0,
+            attack: 0.0,
+            release: 0.15,
+            onset_sensitivity: 1.5,
+            onset_gap: 0.1,
+        }
+    }
+}
+
+/// Something about the audio measured at every frame of an [`AudioAnalysis`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Root mean square of the samples, how loud the audio is
+    Rms,
+    /// Largest sample
+    Peak,
+    /// Level of a spectrum band, `0` is the lowest
+    Band(usize),
+    /// `1.0` at every onset, then falls with [`AnalysisSettings::release`]
+    Onset,
+}
+
+/// Per-frame levels of an [`AudioSource`] for audio-reactive animation. All
+/// values are between `0.0` and `1.0` and smoothed with the attack and release
+/// of the [`AnalysisSettings`]
+///
+/// ```ignore
+/// let analysis = AudioAnalysis::new(&source, AnalysisSettings::default());
+/// let size = analysis.bind(Signal::Band(0), |level| (100.0, 100.0 + level * 400.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AudioAnalysis {
+    settings: AnalysisSettings,
+    rms: Vec<f32>,
+    peak: Vec<f32>,
+    /// One `Vec` of all bands per frame
+    bands: Vec<Vec<f32>>,
+    onset: Vec<f32>,
+    /// Frames at which onsets were detected
+    onsets: Vec<u64>,
+}
+
+impl AudioAnalysis {
+    pub fn new(source: &AudioSource, settings: AnalysisSettings) -> Self {
+        let buffer = source.buffer();
+        let channels = buffer.channels.max(1) as usize;
+        let mono = buffer
+            .samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect::<Vec<_>>();
+
+        let rate = buffer.sample_rate as f64;
+        let frames = (buffer.duration() * settings.fps).ceil() as usize;
+        let window_size = settings.window_size.max(2);
+        let hop = (rate / settings.fps) as usize;
+
+        let window = (0..window_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / window_size as f32).cos())
+            .collect::<Vec<_>>();
+        // A full scale sine reaches 1.0 in its bin
+        let window_gain = window.iter().sum::<f32>() / 2.0;
+        let fft = FftPlanner::new().plan_fft_forward(window_size);
+
+        let band_bins = band_bins(&settings, window_size, buffer.sample_rate);
+
+        let mut rms = Vec::with_capacity(frames);
+        let mut peak = Vec::with_capacity(frames);
+        let mut bands = Vec::with_capacity(frames);
+        let mut flux = Vec::with_capacity(frames);
+        let mut previous_spectrum: Option<Vec<f32>> = None;
+        let mut spectrum = vec![Complex::new(0.0, 0.0); window_size];
+
+        for frame in 0..frames {
+            let start = (frame as f64 / settings.fps * rate) as usize;
+            let samples = &mono[start.min(mono.len())..(start + hop.max(1)).min(mono.len())];
+            rms.push((samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len().max(1) as f32).sqrt());
+            peak.push(samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs())));
+
+            // The window is centered on the frame
+            let offset = start as isize - window_size as isize / 2;
+            for (i, value) in spectrum.iter_mut().enumerate() {
+                let sample = usize::try_from(offset + i as isize).ok().and_then(|i| mono.get(i)).copied().unwrap_or(0.0);
+                *value = Complex::new(sample * window[i], 0.0);
+            }
+            fft.process(&mut spectrum);
+
+            let magnitudes = spectrum[..window_size / 2].iter().map(|bin| bin.norm() / window_gain).collect::<Vec<_>>();
+            bands.push(
+                band_bins
+                    .iter()
+                    .map(|bins| {
+                        let level = magnitudes[bins.clone()].iter().fold(0.0f32, |level, &magnitude| level.max(magnitude));
+                        ((20.0 * level.max(1e-9).log10() - settings.floor) / -settings.floor).clamp(0.0, 1.0)
+                    })
+                    .collect::<Vec<_>>(),
+            );
+
+            // Spectral flux, how much louder the bins got since the last frame
+            let log_magnitudes = magnitudes.iter().map(|magnitude| (1.0 + 1000.0 * magnitude).ln()).collect::<Vec<_>>();
+            flux.push(match previous_spectrum.as_ref() {
+                Some(previous) => log_magnitudes.iter().zip(previous.iter()).map(|(now, before)| (now - before).max(0.0)).sum::<f32>(),
+                None => 0.0,
+            });
+            previous_spectrum = Some(log_magnitudes);
+        }
+
+        let onsets = detect_onsets(&flux, &settings);
+        let mut onset = vec![0.0; frames];
+        for &frame in onsets.iter() {
+            onset[frame as usize] = 1.0;
+        }
+
+        let mut analysis = Self {
+            settings,
+            rms,
+            peak,
+            bands,
+            onset,
+            onsets,
+        };
+        analysis.smooth();
+        analysis
+    }
+
+    /// Applies attack and release to every signal
+    fn smooth(&mut self) {
+        let fps = self.settings.fps;
+        let coefficient = |seconds: f64| if seconds > 0.0 { (1.0 - (-1.0 / (seconds * fps)).exp()) as f32 } else { 1.0 };
+        let (attack, release) = (coefficient(self.settings.attack), coefficient(self.settings.release));
+
+        let smooth = |values: &mut dyn Iterator<Item = &mut f32>| {
+            let mut current = 0.0;
+            for value in values {
+                current += (*value - current) * if *value > current { attack } else { release };
+                *value = current;
+            }
+        };
+
+        smooth(&mut self.rms.iter_mut());
+        smooth(&mut self.peak.iter_mut());
+        smooth(&mut self.onset.iter_mut());
+        for band in 0..self.settings.bands {
+            smooth(&mut self.bands.iter_mut().map(|bands| &mut bands[band]));
+        }
+    }
+
+    /// Number of analysed frames
+    pub fn frames(&self) -> usize {
+        self.rms.len()
+    }
+
+    /// Value of `signal` at `frame`, `0.0` after the end of the audio
+    pub fn value(&self, signal: Signal, frame: u64) -> f32 {
+        let frame = frame as usize;
+        match signal {
+            Signal::Rms => self.rms.get(frame).copied(),
+            Signal::Peak => self.peak.get(frame).copied(),
+            Signal::Band(band) => self.bands.get(frame).and_then(|bands| bands.get(band)).copied(),
+            Signal::Onset => self.onset.get(frame).copied(),
+        }
+        .unwrap_or(0.0)
+    }
+
+    /// Frames at which a note, hit or beat starts
+    pub fn onsets(&self) -> &[u64] {
+        &self.onsets
+    }
+
+    /// Center frequency (in Hz) of a band
+    pub fn band_frequency(&self, band: usize) -> f32 {
+        let settings = &self.settings;
+        let scale = settings.scale;
+        let (low, high) = (scale.scaled(settings.min_frequency), scale.scaled(settings.max_frequency));
+        scale.unscaled(low + (high - low) * (band as f32 + 0.5) / settings.bands as f32)
+    }
+
+    /// Animates a property with `signal`, with one keyframe per frame. `map`
+    /// turns the value of the signal into the value of the property. Frame 0
+    /// of the property is the start of the audio
+    pub fn bind<T: Interpolate + Clone + std::fmt::Debug>(&self, signal: Signal, map: impl Fn(f32) -> T) -> AnimatedProperty<T> {
+        let keyframes = (1..self.frames() as u64 + 1)
+            .map(|frame| Keyframe {
+                easing: LINEAR,
+                state: map(self.value(signal, frame)),
+                frame,
+            })
+            .collect();
+
+        AnimatedProperty::new(map(self.value(signal, 0)), keyframes)
+    }
+}
+
+/// Range of FFT bins covered by every band, at least one bin each
+fn band_bins(settings: &AnalysisSettings, window_size: usize, sample_rate: u32) -> Vec<std::ops::Range<usize>> {
+    let scale = settings.scale;
+    let bins = window_size / 2;
+    let bin_width = sample_rate as f32 / window_size as f32;
+    let max_frequency = settings.max_frequency.min(sample_rate as f32 / 2.0);
+    let (low, high) = (scale.scaled(settings.min_frequency), scale.scaled(max_frequency));
+
+    let edge = |band: usize| {
+        let frequency = scale.unscaled(low + (high - low) * band as f32 / settings.bands as f32);
+        ((frequency / bin_width).round() as usize).min(bins - 1)
+    };
+
+    (0..settings.bands)
+        .map(|band| {
+            let start = edge(band);
+            start..edge(band + 1).max(start + 1)
+        })
+        .collect()
+}
+
+/// Frames where the spectral flux peaks above its recent average
+fn detect_onsets(flux: &[f32], settings: &AnalysisSettings) -> Vec<u64> {
+    let radius = (0.25 * settings.fps).ceil() as usize;
+    let gap = (settings.onset_gap * settings.fps).round() as usize;
+    let mut onsets: Vec<u64> = Vec::new();
+
+    for (frame, &value) in flux.iter().enumerate() {
+        let neighbours = &flux[frame.saturating_sub(radius)..(frame + radius + 1).min(flux.len())];
+        let average = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+        let is_peak = neighbours.iter().all(|&neighbour| neighbour <= value);
+        let after_gap = onsets.last().is_none_or(|&last| frame - last as usize >= gap);
+
+        if is_peak && value > 0.0 && value > average * settings.onset_sensitivity && after_gap {
+            onsets.push(frame as u64);
+        }
+    }
+
+    onsets
+}
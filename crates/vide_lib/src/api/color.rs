@@ -29,7 +29,7 @@ impl Color {
     /// Transparent white `(255, 255, 255 / #ffffff00)`
     pub const TRANSPARENT_WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 0.0 };
     /// Transparent black `(0, 0, 0, 0 / #00000000)`
-    pub const TRANSPARENT_BLACK: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 0.0 };
+    pub const TRANSPARENT_BLACK: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
 
     /// Create a new color from 4 linear components, it will automatically be converted to srgb at runtime
     pub fn new(r: f64, g: f64, b: f64, a: f64) -> Self {
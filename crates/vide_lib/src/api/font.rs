@@ -0,0 +1,402 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// Size (in pixels per em) glyphs are rasterized at before being converted to a signed distance field
+pub(crate) const SDF_EM_SIZE: f32 = 48.0;
+/// Distance (in pixels at [`SDF_EM_SIZE`]) encoded on either side of a glyph's outline
+pub(crate) const SDF_SPREAD: f32 = 6.0;
+/// Width and height of a page of the glyph atlas
+pub(crate) const ATLAS_SIZE: u32 = 2048;
+
+static FONT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A TrueType or OpenType font, cheap to clone
+#[derive(Clone)]
+pub struct Font {
+    id: usize,
+    data: Arc<Vec<u8>>,
+    index: u32,
+}
+
+impl Font {
+    /// Loads the first face in a TTF/OTF file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Self::from_bytes(data)
+    }
+
+    /// Loads the first face in TTF/OTF data
+    pub fn from_bytes(data: impl Into<Vec<u8>>) -> Result<Self> {
+        Self::from_collection(data, 0)
+    }
+
+    /// Loads face `index` from a font collection (TTC/OTC)
+    pub fn from_collection(data: impl Into<Vec<u8>>, index: u32) -> Result<Self> {
+        let data = data.into();
+        if rustybuzz::Face::from_slice(&data, index).is_none() {
+            return Err(Error::Font(format!("unable to parse face {} of font", index)));
+        }
+
+        Ok(Self {
+            id: FONT_COUNTER.fetch_add(1, Ordering::Relaxed),
+            data: Arc::new(data),
+            index,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn face(&self) -> rustybuzz::Face<'_> {
+        // Checked when the font was loaded
+        rustybuzz::Face::from_slice(&self.data, self.index).unwrap()
+    }
+}
+
+impl std::fmt::Debug for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Font").field("id", &self.id).field("index", &self.index).finish()
+    }
+}
+
+/// Location of a glyph in the [`GlyphAtlas`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AtlasGlyph {
+    /// Index of the [`AtlasPage`] holding the glyph
+    pub page: usize,
+    /// Left, top, width and height in texture coordinates
+    pub uv: [f32; 4],
+    /// Offset of the top left corner of the bitmap from the pen position in
+    /// pixels at [`SDF_EM_SIZE`], with y pointing up
+    pub offset: (f32, f32),
+    /// Size of the bitmap in pixels at [`SDF_EM_SIZE`]
+    pub size: (f32, f32),
+}
+
+/// Single channel texture of [`ATLAS_SIZE`]² holding signed distance fields of glyphs
+pub(crate) struct AtlasPage {
+    pub data: Vec<u8>,
+    cursor: (u32, u32),
+    row_height: u32,
+    /// Set when glyphs were added since the page was last uploaded
+    pub dirty: bool,
+    /// Set when a glyph on this page was used since the last [`GlyphAtlas::end_frame`]
+    used: bool,
+}
+
+impl AtlasPage {
+    fn new() -> Self {
+        Self {
+            data: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            cursor: (0, 0),
+            row_height: 0,
+            dirty: false,
+            used: false,
+        }
+    }
+
+    /// Finds room for a bitmap, glyphs are placed left to right in rows (shelf packing)
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (position, row_height) = match self.cursor.0 + width > ATLAS_SIZE {
+            true => ((0, self.cursor.1 + self.row_height + 1), 0),
+            false => (self.cursor, self.row_height),
+        };
+        // Smaller glyphs may still fit when this one doesn't
+        if position.1 + height > ATLAS_SIZE {
+            return None;
+        }
+
+        self.cursor = (position.0 + width + 1, position.1);
+        self.row_height = row_height.max(height);
+        Some(position)
+    }
+
+    fn clear(&mut self) {
+        self.data.fill(0);
+        self.cursor = (0, 0);
+        self.row_height = 0;
+    }
+}
+
+/// Signed distance fields of the glyphs rendered recently, shared by all
+/// fonts. A page is added when the others are full, pages none of the glyphs
+/// of a frame were on are cleared again so text that changes a lot (like
+/// counters in many fonts and sizes) doesn't keep growing the atlas
+pub(crate) struct GlyphAtlas {
+    pub pages: Vec<AtlasPage>,
+    /// `None` for glyphs without an outline (like spaces)
+    glyphs: HashMap<(usize, u16), Option<AtlasGlyph>>,
+}
+
+impl GlyphAtlas {
+    pub fn new() -> Self {
+        Self {
+            pages: vec![AtlasPage::new()],
+            glyphs: HashMap::new(),
+        }
+    }
+
+    /// Looks up a glyph, rasterizing it when it isn't in the atlas yet
+    pub fn glyph(&mut self, font: &Font, face: &ttf_parser::Face, glyph_id: u16) -> Result<Option<AtlasGlyph>> {
+        if let Some(glyph) = self.glyphs.get(&(font.id(), glyph_id)) {
+            if let Some(glyph) = glyph {
+                self.pages[glyph.page].used = true;
+            }
+            return Ok(*glyph);
+        }
+
+        let glyph = self.insert(face, glyph_id)?;
+        self.glyphs.insert((font.id(), glyph_id), glyph);
+        Ok(glyph)
+    }
+
+    fn insert(&mut self, face: &ttf_parser::Face, glyph_id: u16) -> Result<Option<AtlasGlyph>> {
+        let bbox = match face.glyph_bounding_box(ttf_parser::GlyphId(glyph_id)) {
+            Some(bbox) => bbox,
+            None => return Ok(None),
+        };
+
+        let scale = SDF_EM_SIZE / face.units_per_em() as f32;
+        let padding = SDF_SPREAD.ceil() + 1.0;
+        let width = ((bbox.x_max - bbox.x_min) as f32 * scale + padding * 2.0).ceil() as u32;
+        let height = ((bbox.y_max - bbox.y_min) as f32 * scale + padding * 2.0).ceil() as u32;
+
+        let mut outline = Outline {
+            segments: Vec::new(),
+            scale,
+            origin: (bbox.x_min as f32, bbox.y_max as f32),
+            padding,
+            start: (0.0, 0.0),
+            last: (0.0, 0.0),
+        };
+        if face.outline_glyph(ttf_parser::GlyphId(glyph_id), &mut outline).is_none() {
+            return Ok(None);
+        }
+        let sdf = signed_distance_field(&outline.segments, width as usize, height as usize);
+
+        if width > ATLAS_SIZE || height > ATLAS_SIZE {
+            return Err(Error::Font(format!("glyph {} is larger than a page of the glyph atlas", glyph_id)));
+        }
+        let (page, (x, y)) = match self.pages.iter_mut().enumerate().find_map(|(index, page)| Some((index, page.allocate(width, height)?))) {
+            Some(allocation) => allocation,
+            None => {
+                let mut page = AtlasPage::new();
+                // Fits, the size was checked above
+                let position = page.allocate(width, height).unwrap();
+                self.pages.push(page);
+                (self.pages.len() - 1, position)
+            }
+        };
+
+        let atlas_page = &mut self.pages[page];
+        for row in 0..height {
+            let start = ((y + row) * ATLAS_SIZE + x) as usize;
+            atlas_page.data[start..start + width as usize].copy_from_slice(&sdf[(row * width) as usize..((row + 1) * width) as usize]);
+        }
+        atlas_page.dirty = true;
+        atlas_page.used = true;
+
+        Ok(Some(AtlasGlyph {
+            page,
+            uv: [
+                x as f32 / ATLAS_SIZE as f32,
+                y as f32 / ATLAS_SIZE as f32,
+                width as f32 / ATLAS_SIZE as f32,
+                height as f32 / ATLAS_SIZE as f32,
+            ],
+            offset: (bbox.x_min as f32 * scale - padding, bbox.y_max as f32 * scale + padding),
+            size: (width as f32, height as f32),
+        }))
+    }
+
+    /// Clears the pages that weren't used since the last call, once there is
+    /// more than one. Called after every frame, when the glyphs of the frame
+    /// have been uploaded
+    pub fn end_frame(&mut self) {
+        if self.pages.len() > 1 {
+            let unused = self.pages.iter().map(|page| !page.used).collect::<Vec<_>>();
+            if unused.contains(&true) {
+                self.glyphs.retain(|_, glyph| glyph.is_none_or(|glyph| !unused[glyph.page]));
+                for page in self.pages.iter_mut().filter(|page| !page.used) {
+                    page.clear();
+                }
+            }
+        }
+
+        for page in self.pages.iter_mut() {
+            page.used = false;
+        }
+    }
+}
+
+type Segment = ((f32, f32), (f32, f32));
+
+/// Collects the outline of a glyph as line segments, converted from font
+/// units to bitmap pixels (with y pointing down)
+struct Outline {
+    segments: Vec<Segment>,
+    scale: f32,
+    /// Top left of the glyph's bounding box in font units
+    origin: (f32, f32),
+    padding: f32,
+    start: (f32, f32),
+    last: (f32, f32),
+}
+
+impl Outline {
+    fn point(&self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.origin.0) * self.scale + self.padding, (self.origin.1 - y) * self.scale + self.padding)
+    }
+
+    fn line(&mut self, to: (f32, f32)) {
+        if to != self.last {
+            self.segments.push((self.last, to));
+        }
+        self.last = to;
+    }
+
+    /// Flattens a curve into lines about a pixel long, `at` evaluates it for `t` in `0..=1`
+    fn curve(&mut self, length: f32, at: impl Fn(f32) -> (f32, f32)) {
+        let steps = length.ceil().clamp(1.0, 64.0) as u32;
+        for step in 1..=steps {
+            self.line(at(step as f32 / steps as f32));
+        }
+    }
+}
+
+fn distance((ax, ay): (f32, f32), (bx, by): (f32, f32)) -> f32 {
+    ((bx - ax).powi(2) + (by - ay).powi(2)).sqrt()
+}
+
+impl ttf_parser::OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.point(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.line(p);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p) = (self.last, self.point(x1, y1), self.point(x, y));
+        // The control polygon is at least as long as the curve
+        let length = distance(p0, p1) + distance(p1, p);
+        self.curve(length, |t| {
+            let u = 1.0 - t;
+            (u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p.0, u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p.1)
+        });
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p) = (self.last, self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        let length = distance(p0, p1) + distance(p1, p2) + distance(p2, p);
+        self.curve(length, |t| {
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            (a * p0.0 + b * p1.0 + c * p2.0 + d * p.0, a * p0.1 + b * p1.1 + c * p2.1 + d * p.1)
+        });
+    }
+
+    fn close(&mut self) {
+        let start = self.start;
+        self.line(start);
+    }
+}
+
+/// Encodes the distance from the center of every pixel to the outline, `0.5`
+/// lies on the outline and values above it are inside the glyph. Distances
+/// are measured to the outline itself rather than to pixel centers, so edges
+/// stay smooth when the glyph is scaled up
+fn signed_distance_field(segments: &[Segment], width: usize, height: usize) -> Vec<u8> {
+    let mut sdf = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            let mut nearest = f32::MAX;
+            // Nonzero winding number, like TrueType and OpenType fill outlines
+            let mut winding = 0;
+
+            for &((ax, ay), (bx, by)) in segments {
+                let (dx, dy) = (bx - ax, by - ay);
+                let t = (((px - ax) * dx + (py - ay) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+                nearest = nearest.min(distance((px, py), (ax + t * dx, ay + t * dy)));
+
+                let side = dx * (py - ay) - (px - ax) * dy;
+                if ay <= py && by > py && side > 0.0 {
+                    winding += 1;
+                } else if ay > py && by <= py && side < 0.0 {
+                    winding -= 1;
+                }
+            }
+
+            let distance = if winding != 0 { nearest } else { -nearest };
+            sdf.push(((0.5 + distance / (SDF_SPREAD * 2.0)).clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+    sdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{signed_distance_field, Segment, SDF_SPREAD};
+
+    /// Square from `(min, min)` to `(max, max)`
+    fn square(min: f32, max: f32) -> Vec<Segment> {
+        let corners = [(min, min), (max, min), (max, max), (min, max)];
+        (0..4).map(|i| (corners[i], corners[(i + 1) % 4])).collect()
+    }
+
+    fn encoded(distance: f32) -> u8 {
+        ((0.5 + distance / (SDF_SPREAD * 2.0)) * 255.0).round() as u8
+    }
+
+    #[test]
+    fn distances_are_measured_to_the_outline() {
+        let sdf = signed_distance_field(&square(4.25, 12.0), 16, 16);
+        let row = &sdf[8 * 16..9 * 16];
+
+        // Pixel centers 0.25 on either side of the edge, and further away
+        assert_eq!(row[4], encoded(0.25));
+        assert_eq!(row[3], encoded(-0.75));
+        assert_eq!(row[0], encoded(-3.75));
+        assert_eq!(row[8], encoded(3.5));
+    }
+
+    #[test]
+    fn edges_between_pixel_centers_are_kept() {
+        // Moving the edge by a fraction of a pixel changes the field
+        let a = signed_distance_field(&square(4.0, 12.0), 16, 16);
+        let b = signed_distance_field(&square(4.4, 12.0), 16, 16);
+        assert!(a[8 * 16 + 4] > b[8 * 16 + 4]);
+        assert!(a[8 * 16 + 3] > b[8 * 16 + 3]);
+    }
+
+    #[test]
+    fn holes_are_outside() {
+        // Counter-clockwise inner square cuts a hole, like in an "O"
+        let mut segments = square(2.0, 14.0);
+        segments.extend(square(6.0, 10.0).into_iter().rev().map(|(a, b)| (b, a)));
+
+        let sdf = signed_distance_field(&segments, 16, 16);
+        assert!(sdf[8 * 16 + 3] > 127);
+        assert!(sdf[8 * 16 + 8] < 127);
+        assert!(sdf[0] < 127);
+    }
+}
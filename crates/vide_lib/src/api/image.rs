@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, MutexGuard,
+    },
+};
+
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::Time, error::{Error, Result}};
+
+use super::{
+    animation::AnimatedProperty,
+    color::Color,
+    instanced_mesh::InstancedMesh,
+    mesh::{Vertex, VertexAttributeDescriptor},
+    shader::Shader,
+    texture::{create_sampler, create_texture, texture_bind_group, texture_bind_group_layout, write_texture},
+    transform::OPENGL_TO_WGPU_MATRIX,
+};
+
+register_effect!(ImageBackend, Image);
+
+static IMAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Decoded RGBA pixels, cheap to clone so the same image can be shown by many clips
+#[derive(Clone)]
+pub struct ImageSource {
+    id: usize,
+    data: Arc<::image::RgbaImage>,
+}
+
+impl ImageSource {
+    /// Loads a PNG, JPEG or WebP file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Self::from_bytes(&data)
+    }
+
+    /// Decodes PNG, JPEG or WebP data, the format is guessed from its contents
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(Self::from_image(::image::load_from_memory(data)?.into_rgba8()))
+    }
+
+    pub fn from_image(image: ::image::RgbaImage) -> Self {
+        Self {
+            id: IMAGE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            data: Arc::new(image),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.data.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.data.height()
+    }
+
+    #[inline]
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn pixels(&self) -> &[u8] {
+        self.data.as_raw()
+    }
+}
+
+impl std::fmt::Debug for ImageSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageSource")
+            .field("id", &self.id)
+            .field("width", &self.width())
+            .field("height", &self.height())
+            .finish()
+    }
+}
+
+/// How an image is placed inside [`Image::size`] when their aspect ratios differ
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFit {
+    /// Fill the whole area, distorting the image
+    #[default]
+    Stretch,
+    /// Show the whole image, leaving empty space on two sides
+    Contain,
+    /// Fill the whole area, cutting off two sides of the image
+    Cover,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFilter {
+    /// Smooth, for photos and anything scaled by non-integer amounts
+    #[default]
+    Linear,
+    /// Blocky, for pixel art
+    Nearest,
+}
+
+impl From<ImageFilter> for wgpu::FilterMode {
+    fn from(filter: ImageFilter) -> Self {
+        match filter {
+            ImageFilter::Linear => wgpu::FilterMode::Linear,
+            ImageFilter::Nearest => wgpu::FilterMode::Nearest,
+        }
+    }
+}
+
+pub struct Image {
+    pub source: ImageSource,
+    pub position: AnimatedProperty<(f32, f32)>,
+    /// Area the image is fitted into, see [`Image::fit`]
+    pub size: AnimatedProperty<(f32, f32)>,
+    /// Counter-clockwise rotation in degrees
+    pub rotation: AnimatedProperty<f32>,
+    /// Multiplied with every pixel, [`Color::WHITE`] leaves the image unchanged
+    pub tint: AnimatedProperty<Color>,
+    pub opacity: AnimatedProperty<f32>,
+    pub fit: ImageFit,
+    /// Part of the source to show as `(x, y, width, height)` in pixels, `None` shows all of it
+    pub crop: Option<(f32, f32, f32, f32)>,
+    pub filter: ImageFilter,
+}
+
+/// Size of the quad and the part of the texture it shows, after cropping and fitting
+pub(crate) fn fit_image(source_size: (u32, u32), crop: Option<(f32, f32, f32, f32)>, fit: ImageFit, size: (f32, f32)) -> ((f32, f32), [f32; 4]) {
+    let (source_width, source_height) = (source_size.0 as f32, source_size.1 as f32);
+    let (x, y, width, height) = crop.unwrap_or((0.0, 0.0, source_width, source_height));
+
+    let (quad, (x, y, width, height)) = match fit {
+        ImageFit::Stretch => (size, (x, y, width, height)),
+        ImageFit::Contain => {
+            let scale = (size.0 / width).min(size.1 / height);
+            ((width * scale, height * scale), (x, y, width, height))
+        }
+        ImageFit::Cover => {
+            let scale = (size.0 / width).max(size.1 / height);
+            let (visible_width, visible_height) = (size.0 / scale, size.1 / scale);
+            (
+                size,
+                (
+                    x + (width - visible_width) / 2.0,
+                    y + (height - visible_height) / 2.0,
+                    visible_width,
+                    visible_height,
+                ),
+            )
+        }
+    };
+
+    (quad, [x / source_width, y / source_height, width / source_width, height / source_height])
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ImageInstance {
+    matrix: [[f32; 4]; 4],
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+impl ImageInstance {
+    pub fn new(position: (f32, f32), size: (f32, f32), rotation: f32, uv_rect: [f32; 4], tint: Color, opacity: f32) -> Self {
+        Self {
+            matrix: (cgmath::Matrix4::from_translation(cgmath::Vector3::new(position.0, position.1, 0.0))
+                * cgmath::Matrix4::from_angle_z(cgmath::Deg(rotation))
+                * cgmath::Matrix4::from_nonuniform_scale(size.0, size.1, 1.0)
+                * OPENGL_TO_WGPU_MATRIX)
+                .into(),
+            uv_rect,
+            color: Color { a: tint.a * opacity as f64, ..tint }.into(),
+        }
+    }
+}
+
+impl VertexAttributeDescriptor for ImageInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ImageInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                },
+            ],
+        }
+    }
+}
+
+unsafe impl bytemuck::Pod for ImageInstance {}
+unsafe impl bytemuck::Zeroable for ImageInstance {}
+
+/// Quad with its texture coordinates, shared by the image effects
+pub(crate) fn image_mesh(renderer: &mut crate::render::Renderer, bind_group_layout: &wgpu::BindGroupLayout) -> Result<InstancedMesh<ImageInstance>> {
+    let shader = Shader::new(renderer, "image.wgsl", include_str!("image.wgsl").into())?;
+    InstancedMesh::new(
+        renderer,
+        vec![
+            Vertex { position: [-0.5, -0.5], uv: [0.0, 1.0] },
+            Vertex { position: [ 0.5, -0.5], uv: [1.0, 1.0] },
+            Vertex { position: [-0.5,  0.5], uv: [0.0, 0.0] },
+            Vertex { position: [ 0.5,  0.5], uv: [1.0, 0.0] },
+        ],
+        Some(vec![
+            0, 1, 2,
+            2, 1, 3,
+        ]),
+        shader,
+        &[bind_group_layout],
+    )
+}
+
+pub struct ImageBackend {
+    mesh: InstancedMesh<ImageInstance>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    samplers: HashMap<ImageFilter, wgpu::Sampler>,
+    /// Textures are uploaded the first time a source is rendered
+    textures: HashMap<usize, wgpu::Texture>,
+    bind_groups: HashMap<(usize, ImageFilter), wgpu::BindGroup>,
+    instances: Vec<(ImageSource, ImageFilter, ImageInstance)>,
+}
+
+impl EffectBackend for ImageBackend {
+    type Instance = Image;
+
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        let frame = time.clip_frame;
+        let source = &instance.source;
+        let (size, uv_rect) = fit_image((source.width(), source.height()), instance.crop, instance.fit, instance.size.evaluate(frame));
+
+        self.instances.push((
+            source.clone(),
+            instance.filter,
+            ImageInstance::new(
+                instance.position.evaluate(frame),
+                size,
+                instance.rotation.evaluate(frame),
+                uv_rect,
+                instance.tint.evaluate(frame),
+                instance.opacity.evaluate(frame),
+            ),
+        ));
+    }
+
+    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        for (source, filter, _) in self.instances.iter() {
+            let texture = self.textures.entry(source.id()).or_insert_with(|| {
+                let texture = create_texture(device, source.width(), source.height(), wgpu::TextureFormat::Rgba8UnormSrgb, "Image Texture");
+                write_texture(queue, &texture, source.width(), source.height(), 4, source.pixels());
+                texture
+            });
+
+            let sampler = &self.samplers[filter];
+            let layout = &self.bind_group_layout;
+            self.bind_groups
+                .entry((source.id(), *filter))
+                .or_insert_with(|| texture_bind_group(device, layout, texture, sampler, "Image Bind Group"));
+        }
+
+        // Consecutive instances showing the same image are drawn together
+        let mut batches: Vec<(std::ops::Range<u32>, Vec<&wgpu::BindGroup>)> = Vec::new();
+        for (i, (source, filter, _)) in self.instances.iter().enumerate() {
+            let bind_group = &self.bind_groups[&(source.id(), *filter)];
+            match batches.last_mut() {
+                Some((range, bind_groups)) if std::ptr::eq(bind_groups[0], bind_group) => range.end = i as u32 + 1,
+                _ => batches.push((i as u32..i as u32 + 1, vec![bind_group])),
+            }
+        }
+
+        let instances = self.instances.drain(..).map(|(_, _, instance)| instance).collect();
+        self.mesh.render_batches(pass, device, queue, instances, &batches);
+        Ok(())
+    }
+}
+
+impl Effect for ImageBackend {
+    fn new(renderer: &mut crate::render::Renderer) -> Result<Self> {
+        let device = renderer.wgpu_device();
+        let bind_group_layout = texture_bind_group_layout(device, "Image Bind Group Layout");
+        let samplers = [ImageFilter::Linear, ImageFilter::Nearest]
+            .into_iter()
+            .map(|filter| (filter, create_sampler(device, filter.into(), "Image Sampler")))
+            .collect();
+
+        Ok(Self {
+            mesh: image_mesh(renderer, &bind_group_layout)?,
+            bind_group_layout,
+            samplers,
+            textures: HashMap::new(),
+            bind_groups: HashMap::new(),
+            instances: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,60 @@
+struct TransformUniform {
+    transform_matrix: mat4x4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> transform_uniform: TransformUniform;
+
+@group(1)
+@binding(0)
+var image_texture: texture_2d<f32>;
+@group(1)
+@binding(1)
+var image_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+struct InstanceInput {
+    @location(5) matrix_0: vec4<f32>,
+    @location(6) matrix_1: vec4<f32>,
+    @location(7) matrix_2: vec4<f32>,
+    @location(8) matrix_3: vec4<f32>,
+    @location(9) uv_rect: vec4<f32>,
+    @location(10) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let instance_matrix = mat4x4<f32>(
+        instance.matrix_0,
+        instance.matrix_1,
+        instance.matrix_2,
+        instance.matrix_3,
+    );
+
+    let transform_matrix = transform_uniform.transform_matrix;
+
+    var out: VertexOutput;
+    out.uv = instance.uv_rect.xy + model.uv * instance.uv_rect.zw;
+    out.color = instance.color;
+    out.clip_position = transform_matrix * instance_matrix * vec4<f32>(model.position, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(image_texture, image_sampler, in.uv) * in.color;
+}
@@ -0,0 +1,373 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, MutexGuard,
+    },
+};
+
+use ::image::{AnimationDecoder, RgbaImage};
+
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::Time, error::{Error, Result}};
+
+use super::{
+    animation::AnimatedProperty,
+    color::Color,
+    image::{fit_image, image_mesh, ImageFilter, ImageFit, ImageInstance},
+    instanced_mesh::InstancedMesh,
+    texture::{create_sampler, create_texture, texture_bind_group, texture_bind_group_layout, write_texture},
+};
+
+register_effect!(ImageSequenceBackend, ImageSequence);
+
+/// Number of frames kept on the GPU, the least recently shown ones are dropped first
+const MAX_CACHED_FRAMES: usize = 64;
+
+static SEQUENCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+enum SequenceFrames {
+    /// Decoded when they are shown
+    Files { paths: Vec<PathBuf>, fps: f64 },
+    /// Decoded up front, with the time (in seconds) each frame starts at
+    Decoded { frames: Vec<RgbaImage>, start_times: Vec<f64>, duration: f64 },
+}
+
+/// Frames of an image sequence or animated GIF, cheap to clone
+#[derive(Clone)]
+pub struct SequenceSource {
+    id: usize,
+    frames: Arc<SequenceFrames>,
+    width: u32,
+    height: u32,
+}
+
+impl SequenceSource {
+    /// Finds numbered files like `frame_%04d.png` or `frame_%d.png`, counting
+    /// up from 0 or 1 until a file is missing
+    pub fn from_pattern(pattern: impl AsRef<str>, fps: f64) -> Result<Self> {
+        let pattern = pattern.as_ref();
+        let path = |index: usize| PathBuf::from(format_pattern(pattern, index));
+
+        let first = if path(0).exists() { 0 } else { 1 };
+        let paths = (first..).map(path).take_while(|path| path.exists()).collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Err(Error::Io {
+                path: path(first),
+                source: std::io::ErrorKind::NotFound.into(),
+            });
+        }
+
+        Self::from_files(paths, fps)
+    }
+
+    /// Shows `paths` one after another, every file needs to be a PNG, JPEG or WebP image
+    pub fn from_files(paths: Vec<PathBuf>, fps: f64) -> Result<Self> {
+        let first = paths.first().ok_or_else(|| Error::Io {
+            path: PathBuf::new(),
+            source: std::io::ErrorKind::NotFound.into(),
+        })?;
+        let (width, height) = ::image::image_dimensions(first)?;
+
+        Ok(Self {
+            id: SEQUENCE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            frames: Arc::new(SequenceFrames::Files { paths, fps }),
+            width,
+            height,
+        })
+    }
+
+    /// Decodes every frame of an animated GIF, using the delays stored in the file
+    pub fn from_gif(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Self::decode_gif(BufReader::new(file))
+    }
+
+    pub fn from_gif_bytes(data: &[u8]) -> Result<Self> {
+        Self::decode_gif(data)
+    }
+
+    fn decode_gif<R: std::io::Read>(reader: R) -> Result<Self> {
+        let decoder = ::image::codecs::gif::GifDecoder::new(reader)?;
+
+        let mut frames = Vec::new();
+        let mut start_times = Vec::new();
+        let mut duration = 0.0;
+        for frame in decoder.into_frames() {
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            start_times.push(duration);
+            // Browsers play delays below 20ms at 100ms, so do we
+            let delay = numerator as f64 / denominator as f64 / 1000.0;
+            duration += if delay < 0.02 { 0.1 } else { delay };
+            frames.push(frame.into_buffer());
+        }
+
+        let (width, height) = frames.first().map(|frame| frame.dimensions()).unwrap_or((0, 0));
+        Ok(Self {
+            id: SEQUENCE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            frames: Arc::new(SequenceFrames::Decoded { frames, start_times, duration }),
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn len(&self) -> usize {
+        match self.frames.as_ref() {
+            SequenceFrames::Files { paths, .. } => paths.len(),
+            SequenceFrames::Decoded { frames, .. } => frames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Length of one playthrough in seconds
+    pub fn duration(&self) -> f64 {
+        match self.frames.as_ref() {
+            SequenceFrames::Files { paths, fps } => paths.len() as f64 / fps,
+            SequenceFrames::Decoded { duration, .. } => *duration,
+        }
+    }
+
+    /// Index of the frame shown `time` seconds into the sequence
+    fn frame_at(&self, time: f64, end: SequenceEnd) -> Option<usize> {
+        let duration = self.duration();
+        if self.is_empty() || duration <= 0.0 {
+            return None;
+        }
+
+        let time = match end {
+            _ if time < duration => time.max(0.0),
+            SequenceEnd::Loop => time % duration,
+            SequenceEnd::Hold => return Some(self.len() - 1),
+            SequenceEnd::Hide => return None,
+        };
+
+        Some(match self.frames.as_ref() {
+            SequenceFrames::Files { paths, fps } => ((time * fps) as usize).min(paths.len() - 1),
+            SequenceFrames::Decoded { start_times, .. } => start_times.partition_point(|start| *start <= time).saturating_sub(1),
+        })
+    }
+
+    fn load(&self, index: usize) -> Result<Cow<'_, RgbaImage>> {
+        match self.frames.as_ref() {
+            SequenceFrames::Files { paths, .. } => {
+                let data = std::fs::read(&paths[index]).map_err(|source| Error::Io {
+                    path: paths[index].clone(),
+                    source,
+                })?;
+                Ok(Cow::Owned(::image::load_from_memory(&data)?.into_rgba8()))
+            }
+            SequenceFrames::Decoded { frames, .. } => Ok(Cow::Borrowed(&frames[index])),
+        }
+    }
+}
+
+impl std::fmt::Debug for SequenceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SequenceSource")
+            .field("id", &self.id)
+            .field("len", &self.len())
+            .field("duration", &self.duration())
+            .finish()
+    }
+}
+
+/// Replaces the first `%d` or `%0Nd` in `pattern` with `index`, like
+/// `frame_%04d.png` becoming `frame_0012.png`
+pub fn format_pattern(pattern: &str, index: usize) -> String {
+    if let Some(start) = pattern.find('%') {
+        let rest = &pattern[start + 1..];
+        if let Some(end) = rest.find('d') {
+            let spec = &rest[..end];
+            let width = if spec.is_empty() { Some(0) } else { spec.parse::<usize>().ok() };
+            if let Some(width) = width {
+                return format!("{}{:0width$}{}", &pattern[..start], index, &rest[end + 1..], width = width);
+            }
+        }
+    }
+
+    pattern.to_string()
+}
+
+/// What an [`ImageSequence`] shows once it has played through
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceEnd {
+    /// Start over from the first frame
+    #[default]
+    Loop,
+    /// Keep showing the last frame
+    Hold,
+    /// Show nothing
+    Hide,
+}
+
+/// Plays a [`SequenceSource`] at its own frame rate, following the time of the
+/// clip it is in, including [`Clip::time_remap`](crate::clip::Clip::time_remap)
+pub struct ImageSequence {
+    pub source: SequenceSource,
+    pub position: AnimatedProperty<(f32, f32)>,
+    /// Area the frames are fitted into, see [`ImageSequence::fit`]
+    pub size: AnimatedProperty<(f32, f32)>,
+    /// Counter-clockwise rotation in degrees
+    pub rotation: AnimatedProperty<f32>,
+    /// Multiplied with every pixel, [`Color::WHITE`] leaves the frames unchanged
+    pub tint: AnimatedProperty<Color>,
+    pub opacity: AnimatedProperty<f32>,
+    pub fit: ImageFit,
+    /// Part of the frames to show as `(x, y, width, height)` in pixels, `None` shows all of it
+    pub crop: Option<(f32, f32, f32, f32)>,
+    pub filter: ImageFilter,
+    pub end: SequenceEnd,
+}
+
+struct CachedFrame {
+    texture: wgpu::Texture,
+    bind_groups: HashMap<ImageFilter, wgpu::BindGroup>,
+    /// Render the frame was last shown in
+    last_used: u64,
+}
+
+pub struct ImageSequenceBackend {
+    mesh: InstancedMesh<ImageInstance>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    samplers: HashMap<ImageFilter, wgpu::Sampler>,
+    /// Keyed by sequence id and frame index
+    frames: HashMap<(usize, usize), CachedFrame>,
+    renders: u64,
+    instances: Vec<(SequenceSource, usize, ImageFilter, ImageInstance)>,
+}
+
+impl EffectBackend for ImageSequenceBackend {
+    type Instance = ImageSequence;
+
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        let frame = time.clip_frame;
+        let source = &instance.source;
+        let index = match source.frame_at(time.clip_time, instance.end) {
+            Some(index) => index,
+            None => return,
+        };
+        let (size, uv_rect) = fit_image((source.width(), source.height()), instance.crop, instance.fit, instance.size.evaluate(frame));
+
+        self.instances.push((
+            source.clone(),
+            index,
+            instance.filter,
+            ImageInstance::new(
+                instance.position.evaluate(frame),
+                size,
+                instance.rotation.evaluate(frame),
+                uv_rect,
+                instance.tint.evaluate(frame),
+                instance.opacity.evaluate(frame),
+            ),
+        ));
+    }
+
+    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        self.renders += 1;
+        let instances = std::mem::take(&mut self.instances);
+
+        for (source, index, filter, _) in instances.iter() {
+            let cached = match self.frames.entry((source.id, *index)) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    let image = source.load(*index)?;
+                    let texture = create_texture(device, image.width(), image.height(), wgpu::TextureFormat::Rgba8UnormSrgb, "Image Sequence Texture");
+                    write_texture(queue, &texture, image.width(), image.height(), 4, image.as_raw());
+                    entry.insert(CachedFrame {
+                        texture,
+                        bind_groups: HashMap::new(),
+                        last_used: 0,
+                    })
+                }
+            };
+
+            cached.last_used = self.renders;
+            let sampler = &self.samplers[filter];
+            let layout = &self.bind_group_layout;
+            let texture = &cached.texture;
+            cached
+                .bind_groups
+                .entry(*filter)
+                .or_insert_with(|| texture_bind_group(device, layout, texture, sampler, "Image Sequence Bind Group"));
+        }
+
+        // Drop frames that haven't been shown for the longest time, but never ones needed right now
+        if self.frames.len() > MAX_CACHED_FRAMES {
+            let mut last_used = self.frames.iter().map(|(key, frame)| (frame.last_used, *key)).collect::<Vec<_>>();
+            last_used.sort_unstable();
+            for (used, key) in last_used.into_iter().take(self.frames.len() - MAX_CACHED_FRAMES) {
+                if used != self.renders {
+                    self.frames.remove(&key);
+                }
+            }
+        }
+
+        let mut batches: Vec<(std::ops::Range<u32>, Vec<&wgpu::BindGroup>)> = Vec::new();
+        for (i, (source, index, filter, _)) in instances.iter().enumerate() {
+            let bind_group = &self.frames[&(source.id, *index)].bind_groups[filter];
+            match batches.last_mut() {
+                Some((range, bind_groups)) if std::ptr::eq(bind_groups[0], bind_group) => range.end = i as u32 + 1,
+                _ => batches.push((i as u32..i as u32 + 1, vec![bind_group])),
+            }
+        }
+
+        let instances = instances.iter().map(|(_, _, _, instance)| *instance).collect();
+        self.mesh.render_batches(pass, device, queue, instances, &batches);
+        Ok(())
+    }
+}
+
+impl Effect for ImageSequenceBackend {
+    fn new(renderer: &mut crate::render::Renderer) -> Result<Self> {
+        let device = renderer.wgpu_device();
+        let bind_group_layout = texture_bind_group_layout(device, "Image Sequence Bind Group Layout");
+        let samplers = [ImageFilter::Linear, ImageFilter::Nearest]
+            .into_iter()
+            .map(|filter| (filter, create_sampler(device, filter.into(), "Image Sequence Sampler")))
+            .collect();
+
+        Ok(Self {
+            mesh: image_mesh(renderer, &bind_group_layout)?,
+            bind_group_layout,
+            samplers,
+            frames: HashMap::new(),
+            renders: 0,
+            instances: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_pattern;
+
+    #[test]
+    fn patterns_are_formatted_like_printf() {
+        assert_eq!(format_pattern("frame_%04d.png", 12), "frame_0012.png");
+        assert_eq!(format_pattern("frame_%04d.png", 123456), "frame_123456.png");
+        assert_eq!(format_pattern("frame_%d.png", 7), "frame_7.png");
+        assert_eq!(format_pattern("%d_%d.png", 7), "7_%d.png");
+        assert_eq!(format_pattern("frame.png", 7), "frame.png");
+        assert_eq!(format_pattern("frame_%xd.png", 7), "frame_%xd.png");
+    }
+}
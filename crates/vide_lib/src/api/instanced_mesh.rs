@@ -1,11 +1,11 @@
-use std::{marker::PhantomData, sync::MutexGuard};
+use std::{marker::PhantomData, ops::Range, sync::MutexGuard};
 
 use wgpu::util::DeviceExt;
 
-use crate::render::Renderer;
+use crate::{render::Renderer, error::Result};
 
 use super::{
-    mesh::{Vertex, VertexAttributeDescriptor},
+    mesh::{create_render_pipeline, Vertex, VertexAttributeDescriptor},
     shader::Shader,
 };
 
@@ -21,6 +21,11 @@ pub struct InstancedMesh<T: VertexAttributeDescriptor> {
     index_buffer: Option<wgpu::Buffer>,
     instance_buffer: wgpu::Buffer,
     instance_buffer_len: usize,
+    /// Kept to rebuild the pipeline when the shader is reloaded
+    #[cfg(feature = "preview")]
+    pipeline_layout: wgpu::PipelineLayout,
+    #[cfg(feature = "preview")]
+    format: wgpu::TextureFormat,
     pipeline: wgpu::RenderPipeline,
 
     _phantom: PhantomData<T>,
@@ -32,7 +37,8 @@ impl<T: VertexAttributeDescriptor + bytemuck::Pod + bytemuck::Zeroable> Instance
         vertices: Vec<Vertex>,
         indices: Option<Vec<u16>>,
         shader: Shader,
-    ) -> Self {
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Result<Self> {
         let device = renderer.wgpu_device();
         let config = renderer.wgpu_config();
 
@@ -67,52 +73,17 @@ impl<T: VertexAttributeDescriptor + bytemuck::Pod + bytemuck::Zeroable> Instance
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[renderer.wgpu_transform_bind_group_layout()],
+            bind_group_layouts: &{
+                let mut l = vec![renderer.wgpu_transform_bind_group_layout()];
+                l.extend(bind_group_layouts);
+                l
+            }[..],
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader.module,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc(), T::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader.module,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: false,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let pipeline = create_render_pipeline(device, &pipeline_layout, &shader, config.format, &[Vertex::desc(), T::desc()], false)?;
 
-        Self {
+        Ok(Self {
             vertices,
             len_vertices,
             indices,
@@ -122,18 +93,48 @@ impl<T: VertexAttributeDescriptor + bytemuck::Pod + bytemuck::Zeroable> Instance
             index_buffer,
             instance_buffer,
             instance_buffer_len: 0,
+            #[cfg(feature = "preview")]
+            pipeline_layout,
+            #[cfg(feature = "preview")]
+            format: config.format,
             pipeline,
             _phantom: Default::default(),
-        }
+        })
+    }
+
+    /// Rebuilds the pipeline when the file of the shader changed, see
+    /// [`Shader::from_file`]. [`InstancedMesh::render_batches`] calls this
+    #[cfg(feature = "preview")]
+    pub fn reload(&mut self, device: &wgpu::Device) -> bool {
+        super::mesh::hot_reload(device, &self.pipeline_layout, &mut self.shader, self.format, &[Vertex::desc(), T::desc()], false, &mut self.pipeline)
     }
 
     pub fn render<'a>(
+        &'a mut self,
+        render_pass: MutexGuard<wgpu::RenderPass<'a>>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: Vec<T>,
+        bind_groups: &[&'a wgpu::BindGroup],
+    ) {
+        let len = instances.len() as u32;
+        self.render_batches(render_pass, device, queue, instances, &[(0..len, bind_groups.to_vec())]);
+    }
+
+    /// Draws ranges of `instances` with different bind groups, for effects whose instances don't share textures
+    pub fn render_batches<'a>(
         &'a mut self,
         mut render_pass: MutexGuard<wgpu::RenderPass<'a>>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         instances: Vec<T>,
+        batches: &[(Range<u32>, Vec<&'a wgpu::BindGroup>)],
     ) {
+        // Only checks a flag set by the file watcher, shaders that aren't
+        // loaded from a file never change
+        #[cfg(feature = "preview")]
+        self.reload(device);
+
         if self.instance_buffer_len != instances.len() {
             self.instance_buffer_len = instances.len();
             self.instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -149,17 +150,24 @@ impl<T: VertexAttributeDescriptor + bytemuck::Pod + bytemuck::Zeroable> Instance
             bytemuck::cast_slice(&instances[..]),
         );
 
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
         if let Some(index_buffer) = self.index_buffer.as_ref() {
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..self.len_indices, 0, 0..(self.instance_buffer_len as u32));
-        } else {
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.draw(0..self.len_vertices, 0..(self.instance_buffer_len as u32));
+        }
+
+        for (range, bind_groups) in batches {
+            // Bind group 0 holds the transform and is set by the renderer
+            for (i, bind_group) in bind_groups.iter().enumerate() {
+                render_pass.set_bind_group(i as u32 + 1, bind_group, &[]);
+            }
+
+            if self.index_buffer.is_some() {
+                render_pass.draw_indexed(0..self.len_indices, 0, range.clone());
+            } else {
+                render_pass.draw(0..self.len_vertices, range.clone());
+            }
         }
     }
 }
@@ -2,9 +2,9 @@ use std::sync::MutexGuard;
 
 use wgpu::util::DeviceExt;
 
-use crate::render::Renderer;
+use crate::{render::Renderer, error::{Error, Result}};
 
-use super::shader::Shader;
+use super::shader::{capture_validation_errors, Shader};
 
 pub trait VertexAttributeDescriptor {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -41,6 +41,95 @@ impl VertexAttributeDescriptor for Vertex {
     }
 }
 
+/// Creates the render pipeline used by meshes, with `fs_main` and `vs_main` as entry points
+pub(crate) fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &Shader,
+    format: wgpu::TextureFormat,
+    buffers: &[wgpu::VertexBufferLayout],
+    depth_write_enabled: bool,
+) -> Result<wgpu::RenderPipeline> {
+    capture_validation_errors(device, || device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader.module,
+            entry_point: "vs_main",
+            buffers,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader.module,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                // Colors blend with straight alpha, but the output ends up premultiplied
+                // so transparent backgrounds keep the right alpha
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendState::ALPHA_BLENDING.color,
+                    alpha: wgpu::BlendComponent::OVER,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    }))
+    .map_err(|err| Error::Pipeline {
+        label: shader.label().to_string(),
+        message: err.to_string(),
+    })
+}
+
+/// Rebuilds `pipeline` when `shader` was loaded from a file that changed.
+/// Errors are logged and leave the previous pipeline in place. Returns
+/// whether the pipeline was replaced
+#[cfg(feature = "preview")]
+pub(crate) fn hot_reload(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &mut Shader,
+    format: wgpu::TextureFormat,
+    buffers: &[wgpu::VertexBufferLayout],
+    depth_write_enabled: bool,
+    pipeline: &mut wgpu::RenderPipeline,
+) -> bool {
+    if !shader.reload_if_changed(device) {
+        return false;
+    }
+
+    match create_render_pipeline(device, layout, shader, format, buffers, depth_write_enabled) {
+        Ok(new_pipeline) => {
+            *pipeline = new_pipeline;
+            true
+        }
+        Err(err) => {
+            log::error!("{}\nKeeping the previous pipeline", err);
+            false
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Mesh {
     vertices: Vec<Vertex>,
@@ -51,11 +140,16 @@ pub struct Mesh {
 
     vertex_buffer: wgpu::Buffer,
     index_buffer: Option<wgpu::Buffer>,
+    /// Kept to rebuild the pipeline when the shader is reloaded
+    #[cfg(feature = "preview")]
+    pipeline_layout: wgpu::PipelineLayout,
+    #[cfg(feature = "preview")]
+    format: wgpu::TextureFormat,
     pipeline: wgpu::RenderPipeline,
 }
 
 impl Mesh {
-    pub fn new(renderer: &mut Renderer, vertices: Vec<Vertex>, indices: Option<Vec<u16>>, shader: Shader, bind_group_layouts: &[&wgpu::BindGroupLayout]) -> Self {
+    pub fn new(renderer: &mut Renderer, vertices: Vec<Vertex>, indices: Option<Vec<u16>>, shader: Shader, bind_group_layouts: &[&wgpu::BindGroupLayout]) -> Result<Self> {
         let device = renderer.wgpu_device();
         let config = renderer.wgpu_config();
 
@@ -89,50 +183,9 @@ impl Mesh {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader.module,
-                entry_point: "vs_main",
-                buffers: &[
-                    Vertex::desc(),
-                ],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader.module,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let pipeline = create_render_pipeline(device, &pipeline_layout, &shader, config.format, &[Vertex::desc()], true)?;
 
-        Self {
+        Ok(Self {
             vertices,
             len_vertices,
             indices,
@@ -140,11 +193,23 @@ impl Mesh {
             shader,
             vertex_buffer,
             index_buffer,
+            #[cfg(feature = "preview")]
+            pipeline_layout,
+            #[cfg(feature = "preview")]
+            format: config.format,
             pipeline,
-        }
+        })
+    }
+
+    /// Rebuilds the pipeline when the file of the shader changed, see
+    /// [`Shader::from_file`]. Effects call this before rendering, e.g. at
+    /// the start of [`EffectBackend::render`](crate::effect::EffectBackend::render)
+    #[cfg(feature = "preview")]
+    pub fn reload(&mut self, device: &wgpu::Device) -> bool {
+        hot_reload(device, &self.pipeline_layout, &mut self.shader, self.format, &[Vertex::desc()], true, &mut self.pipeline)
     }
 
-    pub fn render<'a>(&'a self, mut render_pass: MutexGuard<wgpu::RenderPass<'a>>, queue: &wgpu::Queue) {
+    pub fn render<'a>(&'a self, mut render_pass: MutexGuard<wgpu::RenderPass<'a>>, _queue: &wgpu::Queue) {
         if let Some(index_buffer) = self.index_buffer.as_ref() {
             render_pass.set_pipeline(&self.pipeline);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
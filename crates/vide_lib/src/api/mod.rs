@@ -1,8 +1,20 @@
 pub mod color;
 pub mod rect;
+pub mod font;
+pub mod text;
+pub mod text_animator;
+pub(crate) mod texture;
+pub mod image;
+pub mod image_sequence;
+pub mod video_source;
+pub mod audio;
+pub mod audio_analysis;
 pub mod mesh;
 pub mod instanced_mesh;
 pub mod shader;
+pub mod shader_effect;
 pub mod animation;
 pub mod transform;
-pub mod video;
\ No newline at end of file
+pub mod video;
+#[cfg(feature = "preview")]
+pub(crate) mod watch;
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::sync::MutexGuard;
 
-use crate::{register_effect, effect::{Effect, EffectBackend}};
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::Time, error::Result};
 
 use super::{mesh::{Vertex, VertexAttributeDescriptor}, shader::Shader, color::Color, animation::AnimatedProperty, transform::OPENGL_TO_WGPU_MATRIX, instanced_mesh::InstancedMesh};
 
@@ -79,18 +79,19 @@ pub struct RectBackend {
 impl EffectBackend for RectBackend {
     type Instance = Rect;
 
-    fn push(&mut self, instance: &Self::Instance, frame: u64) {
-        self.instances.push(RectInstance::from_rect(instance, frame));
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        self.instances.push(RectInstance::from_rect(instance, time.clip_frame));
     }
 
-    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) {
-        self.mesh.render(pass, device, queue, self.instances.drain(..).collect());
+    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        self.mesh.render(pass, device, queue, self.instances.drain(..).collect(), &[]);
+        Ok(())
     }
 }
 
 impl Effect for RectBackend {
-    fn new(renderer: &mut crate::render::Renderer) -> Self {
-        let shader = Shader::new(renderer, include_str!("rect.wgsl").into());
+    fn new(renderer: &mut crate::render::Renderer) -> Result<Self> {
+        let shader = Shader::new(renderer, "rect.wgsl", include_str!("rect.wgsl").into())?;
         let mesh = InstancedMesh::new(
             renderer,
             vec![
@@ -104,11 +105,12 @@ impl Effect for RectBackend {
                 2, 1, 3,
             ]),
             shader,
-        );
+            &[],
+        )?;
 
-        Self {
+        Ok(Self {
             mesh,
             instances: Vec::new(),
-        }
+        })
     }
 }
\ No newline at end of file
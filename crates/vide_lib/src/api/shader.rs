@@ -1,6 +1,11 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{borrow::Cow, collections::HashMap, path::Path};
+#[cfg(feature = "preview")]
+use std::{path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc}};
 
-use crate::render::Renderer;
+#[cfg(feature = "preview")]
+use log::{error, info};
+
+use crate::{render::Renderer, error::{Error, Result, ShaderError}};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ShaderValue {
@@ -199,17 +204,101 @@ impl_from! { f32, Float32 }
 impl_from! { u32, Uint32 }
 impl_from! { i32, Sint32 }
 
+/// The stage a [`ShaderPass`] runs in, parameters are only visible in the
+/// stages of the passes that declared them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ShaderStage {
+    /// Runs in the vertex shader after the model and screen matrices are
+    /// applied, snippets can modify `clip_position` and `uv`.
     Vertex,
+    /// Runs in the fragment shader, snippets can modify `color`, `uv` and
+    /// read `frag_coord`.
     Fragment,
+    /// Runs in the vertex shader before the model and screen matrices are
+    /// applied, snippets can modify `position` (object space) and `uv`.
     Mesh,
 }
 
-pub struct VertexPass {}
+/// A named piece of WGSL that is stitched into a generated shader by [`ShaderGenerator`]
+#[derive(Debug, Clone)]
+pub struct ShaderSnippet {
+    /// Unique name of this snippet, used for ordering and error messages
+    pub name: String,
+    /// Statements inserted into the shader's entry point, they are wrapped in
+    /// their own block so local variables don't clash with other snippets.
+    pub source: String,
+    /// Module-level declarations (helper functions, constants) needed by
+    /// `source`. Snippets with the same `functions` share them, other
+    /// snippets can't declare functions with the same names
+    pub functions: String,
+    /// Parameters available to `source` as `params.<name>`
+    pub parameters: Vec<(String, UniformType)>,
+    /// Names of snippets that have to run before this one
+    pub requires: Vec<String>,
+}
+
+impl ShaderSnippet {
+    pub fn new(name: impl ToString, source: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            source: source.to_string(),
+            functions: String::new(),
+            parameters: Vec::new(),
+            requires: Vec::new(),
+        }
+    }
+
+    pub fn functions(mut self, functions: impl ToString) -> Self {
+        self.functions = functions.to_string();
+        self
+    }
+
+    pub fn parameter(mut self, name: impl ToString, ty: UniformType) -> Self {
+        self.parameters.push((name.to_string(), ty));
+        self
+    }
+
+    pub fn requires(mut self, name: impl ToString) -> Self {
+        self.requires.push(name.to_string());
+        self
+    }
+}
+
+pub struct VertexPass(pub ShaderSnippet);
+
+pub struct FragmentPass(pub ShaderSnippet);
 
-pub struct FragmentPass {}
+pub struct MeshPass(pub ShaderSnippet);
+
+impl From<VertexPass> for ShaderPass {
+    fn from(pass: VertexPass) -> Self {
+        Self {
+            vertex_pass: Some(pass),
+            fragment_pass: None,
+            mesh_pass: None,
+        }
+    }
+}
 
-pub struct MeshPass {}
+impl From<FragmentPass> for ShaderPass {
+    fn from(pass: FragmentPass) -> Self {
+        Self {
+            vertex_pass: None,
+            fragment_pass: Some(pass),
+            mesh_pass: None,
+        }
+    }
+}
+
+impl From<MeshPass> for ShaderPass {
+    fn from(pass: MeshPass) -> Self {
+        Self {
+            vertex_pass: None,
+            fragment_pass: None,
+            mesh_pass: Some(pass),
+        }
+    }
+}
 
 pub struct ShaderPass {
     pub vertex_pass: Option<VertexPass>,
@@ -217,15 +306,103 @@ pub struct ShaderPass {
     pub mesh_pass: Option<MeshPass>,
 }
 
+impl ShaderPass {
+    /// Multiplies the output color by `params.tint`
+    pub fn tint() -> Self {
+        FragmentPass(
+            ShaderSnippet::new("tint", "color = color * params.tint;")
+                .parameter("tint", UniformType::Float32x4),
+        )
+        .into()
+    }
+
+    /// Offsets the texture coordinates with a sine wave, controlled by
+    /// `params.distortion_strength` and `params.distortion_frequency`
+    pub fn uv_distortion() -> Self {
+        FragmentPass(
+            ShaderSnippet::new(
+                "uv_distortion",
+                "uv = uv + params.distortion_strength * vec2<f32>(\
+                    sin(uv.y * params.distortion_frequency + params.time), \
+                    cos(uv.x * params.distortion_frequency + params.time));",
+            )
+            .parameter("distortion_strength", UniformType::Float32)
+            .parameter("distortion_frequency", UniformType::Float32),
+        )
+        .into()
+    }
+
+    /// Moves vertices up and down over time, controlled by
+    /// `params.wobble_amplitude` and `params.wobble_frequency`
+    pub fn wobble() -> Self {
+        MeshPass(
+            ShaderSnippet::new(
+                "wobble",
+                "position.y = position.y + params.wobble_amplitude * sin(position.x * params.wobble_frequency + params.time);",
+            )
+            .parameter("wobble_amplitude", UniformType::Float32)
+            .parameter("wobble_frequency", UniformType::Float32),
+        )
+        .into()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ShaderParameter {
-    stages: Vec<ShaderStage>,
+    pub ty: UniformType,
+    pub stages: Vec<ShaderStage>,
+}
+
+/// Parameters available to every generated shader
+const BUILTIN_PARAMETERS: [(&str, UniformType); 3] = [
+    ("resolution", UniformType::Float32x2),
+    ("time", UniformType::Float32),
+    ("progress", UniformType::Float32),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderGeneratorError {
+    /// Two snippets share the same name
+    DuplicatePass(String),
+    /// `pass` requires `requires`, which was never pushed
+    MissingDependency { pass: String, requires: String },
+    /// `pass` requires `requires`, which was pushed but runs after `pass`
+    WrongOrder { pass: String, requires: String },
+    /// `name` was declared with two different types
+    ConflictingParameter { name: String, first: UniformType, second: UniformType },
+    /// `name` clashes with a builtin parameter
+    ReservedParameter(String),
+    /// The `functions` of passes `first` and `second` both declare a different function `name`
+    ConflictingFunction { name: String, first: String, second: String },
 }
 
+impl std::fmt::Display for ShaderGeneratorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderGeneratorError::DuplicatePass(name) => write!(f, "shader pass {} was pushed more than once", name),
+            ShaderGeneratorError::MissingDependency { pass, requires } => write!(f, "shader pass {} requires {}, which was never pushed", pass, requires),
+            ShaderGeneratorError::WrongOrder { pass, requires } => write!(f, "shader pass {} requires {} to run before it", pass, requires),
+            ShaderGeneratorError::ConflictingParameter { name, first, second } => write!(f, "shader parameter {} is declared as both {} and {}", name, first.wgsl(), second.wgsl()),
+            ShaderGeneratorError::ReservedParameter(name) => write!(f, "shader parameter name {} is reserved", name),
+            ShaderGeneratorError::ConflictingFunction { name, first, second } => write!(f, "shader passes {} and {} both declare a different function {}", first, second, name),
+        }
+    }
+}
+
+impl std::error::Error for ShaderGeneratorError {}
+
 pub struct ShaderGenerator {
     pub parameters: HashMap<String, ShaderParameter>,
     pub vertex_passes: Vec<VertexPass>,
     pub fragment_passes: Vec<FragmentPass>,
     pub mesh_passes: Vec<MeshPass>,
+    errors: Vec<ShaderGeneratorError>,
+}
+
+impl Default for ShaderGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ShaderGenerator {
@@ -235,46 +412,768 @@ impl ShaderGenerator {
             vertex_passes: Vec::new(),
             fragment_passes: Vec::new(),
             mesh_passes: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn declare_parameters(&mut self, snippet: &ShaderSnippet, stage: ShaderStage) {
+        for (name, ty) in snippet.parameters.iter() {
+            if name == "model_matrix" || BUILTIN_PARAMETERS.iter().any(|(builtin, _)| builtin == name) {
+                self.errors.push(ShaderGeneratorError::ReservedParameter(name.clone()));
+                continue;
+            }
+
+            let parameter = self.parameters.entry(name.clone()).or_insert_with(|| ShaderParameter {
+                ty: *ty,
+                stages: Vec::new(),
+            });
+
+            if parameter.ty != *ty {
+                self.errors.push(ShaderGeneratorError::ConflictingParameter {
+                    name: name.clone(),
+                    first: parameter.ty,
+                    second: *ty,
+                });
+            } else if !parameter.stages.contains(&stage) {
+                parameter.stages.push(stage);
+            }
         }
     }
 
-    pub fn push<'a>(&mut self, pass: impl Into<ShaderPass>) -> &mut Self {
+    pub fn push(&mut self, pass: impl Into<ShaderPass>) -> &mut Self {
         let pass = pass.into();
 
         if let Some(vertex_pass) = pass.vertex_pass {
+            self.declare_parameters(&vertex_pass.0, ShaderStage::Vertex);
             self.vertex_passes.push(vertex_pass);
         }
 
         if let Some(fragment_pass) = pass.fragment_pass {
+            self.declare_parameters(&fragment_pass.0, ShaderStage::Fragment);
             self.fragment_passes.push(fragment_pass);
         }
 
         if let Some(mesh_pass) = pass.mesh_pass {
+            self.declare_parameters(&mesh_pass.0, ShaderStage::Mesh);
             self.mesh_passes.push(mesh_pass);
         }
 
         self
     }
 
-    pub fn generate(mut self, renderer: &mut Renderer) -> Shader {
-        todo!()
+    /// All snippets in the order they run in
+    fn snippets(&self) -> Vec<(ShaderStage, &ShaderSnippet)> {
+        self.mesh_passes.iter().map(|p| (ShaderStage::Mesh, &p.0))
+            .chain(self.vertex_passes.iter().map(|p| (ShaderStage::Vertex, &p.0)))
+            .chain(self.fragment_passes.iter().map(|p| (ShaderStage::Fragment, &p.0)))
+            .collect()
+    }
+
+    /// Checks for duplicate names and makes sure every snippet runs after the snippets it requires
+    fn validate(&self) -> Result<(), ShaderGeneratorError> {
+        if let Some(error) = self.errors.first() {
+            return Err(error.clone());
+        }
+
+        let snippets = self.snippets();
+        for (i, (_, snippet)) in snippets.iter().enumerate() {
+            if snippets[..i].iter().any(|(_, other)| other.name == snippet.name) {
+                return Err(ShaderGeneratorError::DuplicatePass(snippet.name.clone()));
+            }
+
+            // Identical `functions` are only declared once, see `generate`
+            for name in function_names(&snippet.functions) {
+                if let Some((_, other)) = snippets[..i].iter().find(|(_, other)| other.functions != snippet.functions && function_names(&other.functions).contains(&name)) {
+                    return Err(ShaderGeneratorError::ConflictingFunction {
+                        name: name.to_string(),
+                        first: other.name.clone(),
+                        second: snippet.name.clone(),
+                    });
+                }
+            }
+
+            for requires in snippet.requires.iter() {
+                match snippets.iter().position(|(_, other)| &other.name == requires) {
+                    None => return Err(ShaderGeneratorError::MissingDependency {
+                        pass: snippet.name.clone(),
+                        requires: requires.clone(),
+                    }),
+                    Some(position) if position > i => return Err(ShaderGeneratorError::WrongOrder {
+                        pass: snippet.name.clone(),
+                        requires: requires.clone(),
+                    }),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the uniform layout for the parameters visible in `stages`
+    fn layout(&self, stages: &[ShaderStage]) -> UniformLayout {
+        let mut parameters = self
+            .parameters
+            .iter()
+            .filter(|(_, parameter)| parameter.stages.iter().any(|stage| stages.contains(stage)))
+            .map(|(name, parameter)| (name.clone(), parameter.ty))
+            .collect::<Vec<_>>();
+        parameters.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let matrix = stages
+            .contains(&ShaderStage::Vertex)
+            .then(|| ("model_matrix".to_string(), UniformType::Mat4x4));
+
+        UniformLayout::new(
+            matrix
+                .into_iter()
+                .chain(BUILTIN_PARAMETERS.iter().map(|(name, ty)| (name.to_string(), *ty)))
+                .chain(parameters),
+        )
+    }
+
+    /// Stitches all passes into a single WGSL module, see [`ShaderGenerator::generate`]
+    fn source(&self, vertex_layout: &UniformLayout, fragment_layout: &UniformLayout) -> String {
+        let snippets = self.snippets();
+        let mut functions: Vec<&str> = Vec::new();
+        for (_, snippet) in snippets.iter() {
+            if !functions.contains(&snippet.functions.as_str()) {
+                functions.push(&snippet.functions);
+            }
+        }
+        let stitch = |stage: ShaderStage| {
+            snippets
+                .iter()
+                .filter(|(s, _)| *s == stage)
+                .map(|(_, snippet)| format!("    // {}\n    {{\n        {}\n    }}\n", snippet.name, snippet.source))
+                .collect::<String>()
+        };
+
+        include_str!("shader_generator.wgsl")
+            .replace("{{vertex_parameters}}", &vertex_layout.wgsl_struct("VertexParameters"))
+            .replace("{{fragment_parameters}}", &fragment_layout.wgsl_struct("FragmentParameters"))
+            .replace("{{functions}}", &functions.join("\n"))
+            .replace("{{mesh_passes}}", &stitch(ShaderStage::Mesh))
+            .replace("{{vertex_passes}}", &stitch(ShaderStage::Vertex))
+            .replace("{{fragment_passes}}", &stitch(ShaderStage::Fragment))
+    }
+
+    /// Stitches all passes into a single WGSL module. The generated shader
+    /// uses [`Vertex`](super::mesh::Vertex) as its vertex input, expects the
+    /// transform bind group at index 0, and the parameter bind group created
+    /// by [`GeneratedShader::create_parameter_buffers`] at index 1.
+    pub fn generate(self, renderer: &mut Renderer) -> Result<GeneratedShader> {
+        self.validate()?;
+
+        let vertex_layout = self.layout(&[ShaderStage::Mesh, ShaderStage::Vertex]);
+        let fragment_layout = self.layout(&[ShaderStage::Fragment]);
+        let source = self.source(&vertex_layout, &fragment_layout);
+
+        let device = renderer.wgpu_device();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Generated Shader Parameter Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        Ok(GeneratedShader {
+            shader: Shader::new(renderer, "Generated Shader", Cow::Borrowed(&source))?,
+            source,
+            vertex_layout,
+            fragment_layout,
+            bind_group_layout,
+        })
+    }
+}
+
+/// Output of [`ShaderGenerator::generate`]
+#[derive(Debug)]
+pub struct GeneratedShader {
+    pub shader: Shader,
+    /// The generated WGSL source, useful for debugging
+    pub source: String,
+    pub vertex_layout: UniformLayout,
+    pub fragment_layout: UniformLayout,
+    /// Pass this to [`Mesh::new`](super::mesh::Mesh::new) as an extra bind group layout
+    pub bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GeneratedShader {
+    pub fn create_parameter_buffers(&self, device: &wgpu::Device) -> ShaderParameterBuffers {
+        let create_buffer = |label, layout: &UniformLayout| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: layout.size(),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let vertex_buffer = create_buffer("Generated Shader Vertex Parameter Buffer", &self.vertex_layout);
+        let fragment_buffer = create_buffer("Generated Shader Fragment Parameter Buffer", &self.fragment_layout);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Generated Shader Parameter Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: fragment_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        ShaderParameterBuffers {
+            vertex_layout: self.vertex_layout.clone(),
+            fragment_layout: self.fragment_layout.clone(),
+            vertex_data: vec![0; self.vertex_layout.size() as usize],
+            fragment_data: vec![0; self.fragment_layout.size() as usize],
+            vertex_buffer,
+            fragment_buffer,
+            bind_group,
+        }
+    }
+}
+
+/// Uniform buffers holding the parameters of a [`GeneratedShader`]
+#[derive(Debug)]
+pub struct ShaderParameterBuffers {
+    vertex_layout: UniformLayout,
+    fragment_layout: UniformLayout,
+    vertex_data: Vec<u8>,
+    fragment_data: Vec<u8>,
+    vertex_buffer: wgpu::Buffer,
+    fragment_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShaderParameterBuffers {
+    /// Sets parameter `name` in every stage it is visible in, call
+    /// [`ShaderParameterBuffers::upload`] to send the changes to the GPU
+    pub fn set(&mut self, name: &str, value: &[f32]) -> Result<()> {
+        if self.vertex_layout.field(name).is_some() {
+            self.vertex_layout.write(&mut self.vertex_data, name, value)?;
+        }
+
+        if self.fragment_layout.field(name).is_some() {
+            self.fragment_layout.write(&mut self.fragment_data, name, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.vertex_buffer, 0, &self.vertex_data);
+        queue.write_buffer(&self.fragment_buffer, 0, &self.fragment_data);
+    }
+
+    #[inline]
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Types that can be stored in a uniform buffer generated by [`UniformLayout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UniformType {
+    /// One single-precision float (f32). `f32` in shaders.
+    Float32,
+    /// Two single-precision floats (f32). `vec2<f32>` in shaders.
+    Float32x2,
+    /// Three single-precision floats (f32). `vec3<f32>` in shaders.
+    Float32x3,
+    /// Four single-precision floats (f32). `vec4<f32>` in shaders.
+    Float32x4,
+    /// 4x4 column-major matrix of single-precision floats. `mat4x4<f32>` in shaders.
+    Mat4x4,
+}
+
+impl UniformType {
+    pub fn wgsl(&self) -> &'static str {
+        match self {
+            UniformType::Float32 => "f32",
+            UniformType::Float32x2 => "vec2<f32>",
+            UniformType::Float32x3 => "vec3<f32>",
+            UniformType::Float32x4 => "vec4<f32>",
+            UniformType::Mat4x4 => "mat4x4<f32>",
+        }
+    }
+
+    /// Size in bytes as defined by the WGSL memory layout rules
+    pub fn size(&self) -> u64 {
+        match self {
+            UniformType::Float32 => 4,
+            UniformType::Float32x2 => 8,
+            UniformType::Float32x3 => 12,
+            UniformType::Float32x4 => 16,
+            UniformType::Mat4x4 => 64,
+        }
+    }
+
+    /// Alignment in bytes as defined by the WGSL memory layout rules
+    pub fn align(&self) -> u64 {
+        match self {
+            UniformType::Float32 => 4,
+            UniformType::Float32x2 => 8,
+            UniformType::Float32x3 | UniformType::Float32x4 | UniformType::Mat4x4 => 16,
+        }
+    }
+
+    /// Amount of `f32`s needed to represent this type
+    pub fn components(&self) -> usize {
+        self.size() as usize / 4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UniformField {
+    pub name: String,
+    pub ty: UniformType,
+    pub offset: u64,
+}
+
+/// Packs named values into a single uniform buffer following the WGSL
+/// alignment rules, and generates the matching WGSL struct.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UniformLayout {
+    fields: Vec<UniformField>,
+    size: u64,
+}
+
+impl UniformLayout {
+    /// Lays out `fields` in the given order
+    pub fn new(fields: impl IntoIterator<Item = (String, UniformType)>) -> Self {
+        let mut offset = 0;
+        let fields = fields
+            .into_iter()
+            .map(|(name, ty)| {
+                let field_offset = offset + (ty.align() - offset % ty.align()) % ty.align();
+                offset = field_offset + ty.size();
+                UniformField {
+                    name,
+                    ty,
+                    offset: field_offset,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Uniform structs have to be a multiple of 16 bytes in size
+        let size = offset.div_ceil(16) * 16;
+
+        Self {
+            fields,
+            size: size.max(16),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn fields(&self) -> &[UniformField] {
+        &self.fields
+    }
+
+    pub fn field(&self, name: &str) -> Option<&UniformField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Generates a WGSL struct declaration called `name` describing this layout
+    pub fn wgsl_struct(&self, name: &str) -> String {
+        let mut source = format!("struct {} {{\n", name);
+        for field in self.fields.iter() {
+            source.push_str(&format!("    {}: {},\n", field.name, field.ty.wgsl()));
+        }
+        source.push_str("};\n");
+        source
+    }
+
+    /// Writes `value` to the field called `name` in `buffer`, `buffer` should be at least [`UniformLayout::size`] bytes long
+    pub fn write(&self, buffer: &mut [u8], name: &str, value: &[f32]) -> Result<()> {
+        let field = self
+            .field(name)
+            .ok_or_else(|| Error::Uniform(format!("layout has no field called {}", name)))?;
+        if value.len() != field.ty.components() {
+            return Err(Error::Uniform(format!(
+                "field {} is a {} and takes {} components, got {}",
+                name,
+                field.ty.wgsl(),
+                field.ty.components(),
+                value.len()
+            )));
+        }
+
+        let offset = field.offset as usize;
+        buffer[offset..offset + field.ty.size() as usize].copy_from_slice(bytemuck::cast_slice(value));
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub struct Shader {
     pub module: wgpu::ShaderModule,
+    /// Label used in error messages, usually the file name of the shader
+    label: String,
+    /// File this shader was loaded from and a flag that is set when it changes
+    #[cfg(feature = "preview")]
+    file: Option<(PathBuf, Arc<AtomicBool>)>,
 }
 
 impl Shader {
-    pub fn new(renderer: &mut Renderer, source: Cow<str>) -> Self {
-        let module = renderer
-            .wgpu_device()
-            .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("Shader Module"),
+    pub fn new(renderer: &mut Renderer, label: impl ToString, source: Cow<str>) -> Result<Self> {
+        Self::compile(renderer.wgpu_device(), label.to_string(), source)
+    }
+
+    /// Loads a WGSL shader from `path`. When the `preview` feature is
+    /// enabled, the file is watched and meshes using this shader are rebuilt
+    /// whenever it changes, see [`Mesh::reload`](super::mesh::Mesh::reload).
+    /// Shaders compiled from strings, like those of
+    /// [`ShaderGenerator`] or built-in effects, aren't reloaded
+    pub fn from_file(renderer: &mut Renderer, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Ok(Self {
+            #[cfg(feature = "preview")]
+            file: super::watch::watch_file(path).map(|changed| (path.to_path_buf(), changed)),
+            ..Self::compile(renderer.wgpu_device(), path.display().to_string(), source.into())?
+        })
+    }
+
+    /// Validates `source` with naga before handing it to wgpu, so errors
+    /// come with a line and column instead of a validation panic
+    pub(crate) fn compile(device: &wgpu::Device, label: String, source: Cow<str>) -> Result<Self> {
+        validate_wgsl(&label, &source)?;
+
+        let module = capture_validation_errors(device, || {
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&label),
                 source: wgpu::ShaderSource::Wgsl(source),
-            });
+            })
+        })
+        .map_err(|err| ShaderError {
+            label: label.clone(),
+            line: None,
+            column: None,
+            message: err.to_string(),
+        })?;
+
+        Ok(Self {
+            module,
+            label,
+            #[cfg(feature = "preview")]
+            file: None,
+        })
+    }
+
+    /// Compiles `source`, which contains `embedded` (code written by the
+    /// user) starting at line `first_line`. Errors in that code are reported
+    /// with lines counted from its start
+    pub(crate) fn compile_embedded(device: &wgpu::Device, label: String, source: Cow<str>, (first_line, embedded): (u32, &str)) -> Result<Self> {
+        Self::compile(device, label, source).map_err(|err| match err {
+            Error::Shader(err) => Error::Shader(relocate_error(err, first_line, embedded)),
+            err => err,
+        })
+    }
+
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Recompiles the shader if the file it was loaded from has changed
+    /// since it was last compiled. Returns `true` when the module was
+    /// replaced, compile errors are logged and the previous module is kept.
+    #[cfg(feature = "preview")]
+    pub fn reload_if_changed(&mut self, device: &wgpu::Device) -> bool {
+        let (path, changed) = match self.file.as_ref() {
+            Some(file) => file,
+            None => return false,
+        };
+        if !changed.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                error!("Unable to reload shader {}: {}", path.display(), err);
+                return false;
+            }
+        };
+
+        match Self::compile(device, self.label.clone(), source.into()) {
+            Ok(shader) => {
+                info!("Reloaded shader {}", path.display());
+                self.module = shader.module;
+                true
+            }
+            Err(err) => {
+                error!("{}\nKeeping the previous version of {}", err, path.display());
+                false
+            }
+        }
+    }
+}
+
+/// Names of the functions declared in module-level WGSL
+fn function_names(source: &str) -> Vec<&str> {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut after_fn = false;
+
+    for line in source.lines() {
+        let code = line.split("//").next().unwrap_or_default();
+        let mut chars = code.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                c if c.is_whitespace() => continue,
+                c if is_identifier(c) => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(index, c)) = chars.peek().filter(|(_, c)| is_identifier(*c)) {
+                        end = index + c.len_utf8();
+                        chars.next();
+                    }
+
+                    let word = &code[start..end];
+                    if after_fn && depth == 0 {
+                        names.push(word);
+                    }
+                    after_fn = word == "fn";
+                    continue;
+                }
+                _ => (),
+            }
+            after_fn = false;
+        }
+    }
+    names
+}
+
+/// Moves the location of `err` into `embedded`, which starts at line
+/// `first_line` of the compiled shader. Errors outside of it are left as they are
+pub(crate) fn relocate_error(mut err: ShaderError, first_line: u32, embedded: &str) -> ShaderError {
+    let line = match err.line {
+        Some(line) if line >= first_line && line - first_line < embedded.lines().count() as u32 => line - first_line + 1,
+        _ => return err,
+    };
+
+    // The diagnostic quotes the whole compiled shader, only its summary is
+    // kept and the line is quoted from the embedded code instead
+    let summary = err.message.lines().next().unwrap_or_default().to_string();
+    let text = embedded.lines().nth(line as usize - 1).unwrap_or_default();
+    let caret = err.column.map_or(String::new(), |column| format!("{}^", " ".repeat(column as usize - 1)));
+
+    err.line = Some(line);
+    err.message = format!("{}\n{}\n{}", summary, text, caret);
+    err
+}
+
+/// Parses and validates WGSL with naga, returning the diagnostic and its location on failure
+pub(crate) fn validate_wgsl(label: &str, source: &str) -> Result<(), ShaderError> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| {
+        let location = err.location(source);
+        ShaderError {
+            label: label.to_string(),
+            line: location.as_ref().map(|l| l.line_number),
+            column: location.as_ref().map(|l| l.line_position),
+            message: err.emit_to_string(source),
+        }
+    })?;
+
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+        .validate(&module)
+        .map_err(|err| {
+            let location = err.location(source);
+            ShaderError {
+                label: label.to_string(),
+                line: location.as_ref().map(|l| l.line_number),
+                column: location.as_ref().map(|l| l.line_position),
+                message: err.into_inner().to_string(),
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Runs `f` inside a wgpu validation error scope, returning the error
+/// instead of letting wgpu panic
+pub(crate) fn capture_validation_errors<T>(device: &wgpu::Device, f: impl FnOnce() -> T) -> Result<T, wgpu::Error> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = f();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(err) => Err(err),
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        function_names, relocate_error, validate_wgsl, FragmentPass, MeshPass, ShaderGenerator, ShaderGeneratorError, ShaderPass,
+        ShaderSnippet, ShaderStage, UniformType, VertexPass,
+    };
+
+    const LUMA: &str = "fn luma(color: vec3<f32>) -> f32 {\n    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));\n}\n";
+
+    /// Generates the source of `generator` and makes sure naga accepts it
+    fn generate(generator: &ShaderGenerator) -> String {
+        generator.validate().unwrap();
+        let source = generator.source(&generator.layout(&[ShaderStage::Mesh, ShaderStage::Vertex]), &generator.layout(&[ShaderStage::Fragment]));
+        if let Err(err) = validate_wgsl("generated", &source) {
+            panic!("{}\n{}", err.message, source);
+        }
+        source
+    }
+
+    /// Offsets naga computed for the struct bound at `@group(1) @binding(binding)`
+    fn bound_struct(source: &str, binding: u32) -> Vec<(String, u32)> {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        let (_, variable) = module
+            .global_variables
+            .iter()
+            .find(|(_, variable)| variable.binding == Some(naga::ResourceBinding { group: 1, binding }))
+            .unwrap();
+        match &module.types[variable.ty].inner {
+            naga::TypeInner::Struct { members, .. } => members.iter().map(|member| (member.name.clone().unwrap(), member.offset)).collect(),
+            other => panic!("binding {} is a {:?}", binding, other),
+        }
+    }
+
+    #[test]
+    fn generated_shaders_are_valid_wgsl() {
+        generate(&ShaderGenerator::new());
+
+        let mut generator = ShaderGenerator::new();
+        generator.push(ShaderPass::tint()).push(ShaderPass::uv_distortion());
+        generate(&generator);
+
+        let mut generator = ShaderGenerator::new();
+        generator.push(ShaderPass::wobble()).push(ShaderPass::tint());
+        generate(&generator);
+
+        let mut generator = ShaderGenerator::new();
+        generator
+            .push(ShaderPass::wobble())
+            .push(VertexPass(ShaderSnippet::new("shake", "clip_position.x = clip_position.x + params.shake * sin(params.time);").parameter("shake", UniformType::Float32)))
+            .push(FragmentPass(ShaderSnippet::new("grayscale", "color = vec4<f32>(vec3<f32>(luma(color.rgb)), color.a);").functions(LUMA)))
+            .push(FragmentPass(ShaderSnippet::new("threshold", "color = vec4<f32>(vec3<f32>(step(params.shake, luma(color.rgb))), color.a);").functions(LUMA).parameter("shake", UniformType::Float32).requires("grayscale")))
+            .push(ShaderPass::uv_distortion());
+        let source = generate(&generator);
+        assert_eq!(source.matches("fn luma").count(), 1);
+    }
+
+    #[test]
+    fn parameters_are_laid_out_per_stage() {
+        let mut generator = ShaderGenerator::new();
+        generator
+            .push(MeshPass(ShaderSnippet::new("grow", "position = position * params.scale;").parameter("scale", UniformType::Float32x2)))
+            .push(VertexPass(ShaderSnippet::new("fade_in", "clip_position.w = clip_position.w / params.strength;").parameter("strength", UniformType::Float32)))
+            .push(FragmentPass(ShaderSnippet::new("fade", "color.a = color.a * params.strength;").parameter("strength", UniformType::Float32)))
+            .push(ShaderPass::tint());
+        let source = generate(&generator);
+
+        let vertex_layout = generator.layout(&[ShaderStage::Mesh, ShaderStage::Vertex]);
+        let vertex_fields = vertex_layout.fields().iter().map(|field| (field.name.clone(), field.offset as u32)).collect::<Vec<_>>();
+        assert_eq!(
+            vertex_fields,
+            [("model_matrix", 0), ("resolution", 64), ("time", 72), ("progress", 76), ("scale", 80), ("strength", 88)].map(|(name, offset)| (name.to_string(), offset))
+        );
+        assert_eq!(vertex_layout.size(), 96);
+        assert_eq!(bound_struct(&source, 0), vertex_fields);
+
+        let fragment_layout = generator.layout(&[ShaderStage::Fragment]);
+        let fragment_fields = fragment_layout.fields().iter().map(|field| (field.name.clone(), field.offset as u32)).collect::<Vec<_>>();
+        assert_eq!(
+            fragment_fields,
+            [("resolution", 0), ("time", 8), ("progress", 12), ("strength", 16), ("tint", 32)].map(|(name, offset)| (name.to_string(), offset))
+        );
+        assert_eq!(fragment_layout.size(), 48);
+        assert_eq!(bound_struct(&source, 1), fragment_fields);
+    }
+
+    #[test]
+    fn finds_module_level_functions() {
+        let source = "// fn commented(x: f32)\nfn a(x: f32) -> f32 {\n    let f = 1.0;\n    return x * f;\n}\n\nlet fnord = 1.0;\nfn b_2() {}\n";
+        assert_eq!(function_names(source), vec!["a", "b_2"]);
+    }
+
+    #[test]
+    fn identical_functions_are_shared() {
+        let mut generator = ShaderGenerator::new();
+        generator
+            .push(FragmentPass(ShaderSnippet::new("grayscale", "color = vec4<f32>(vec3<f32>(luma(color.rgb)), color.a);").functions(LUMA)))
+            .push(FragmentPass(ShaderSnippet::new("threshold", "color = vec4<f32>(vec3<f32>(step(0.5, luma(color.rgb))), color.a);").functions(LUMA)));
+        assert_eq!(generator.validate(), Ok(()));
+    }
+
+    #[test]
+    fn different_functions_with_the_same_name_clash() {
+        let mut generator = ShaderGenerator::new();
+        generator
+            .push(FragmentPass(ShaderSnippet::new("grayscale", "").functions(LUMA)))
+            .push(VertexPass(ShaderSnippet::new("flat", "").functions("fn luma(color: vec3<f32>) -> f32 {\n    return color.g;\n}\n")));
+        assert_eq!(
+            generator.validate(),
+            Err(ShaderGeneratorError::ConflictingFunction {
+                name: "luma".to_string(),
+                first: "flat".to_string(),
+                second: "grayscale".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn errors_in_embedded_code_use_its_lines() {
+        let embedded = "fn fragment() -> f32 {\n    return 1.0 +;\n}\n";
+        let source = format!("struct A {{\n    a: f32,\n}};\n\n{}", embedded);
+
+        let err = validate_wgsl("effect", &source).unwrap_err();
+        assert_eq!(err.line, Some(6));
+
+        let err = relocate_error(err, 5, embedded);
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("    return 1.0 +;"), "{}", err.message);
+    }
+
+    #[test]
+    fn errors_outside_embedded_code_are_kept() {
+        let source = "struct A {\n    a: f33,\n};\n\nfn fragment() -> f32 {\n    return 1.0;\n}\n";
 
-        Self { module }
+        let err = validate_wgsl("effect", source).unwrap_err();
+        let message = err.message.clone();
+        let err = relocate_error(err, 5, "fn fragment() -> f32 {\n    return 1.0;\n}\n");
+        assert_eq!(err.line, Some(2));
+        assert_eq!(err.message, message);
     }
 }
@@ -0,0 +1,494 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc, MutexGuard}};
+#[cfg(feature = "preview")]
+use std::sync::atomic::AtomicBool;
+
+#[cfg(feature = "preview")]
+use log::{error, info};
+use wgpu::util::DeviceExt;
+
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::{Renderer, Time}, error::{Error, Result}};
+
+use super::{mesh::{create_render_pipeline, Vertex, VertexAttributeDescriptor}, shader::{Shader, UniformLayout, UniformType}, color::Color, animation::AnimatedProperty, transform::OPENGL_TO_WGPU_MATRIX, video::VideoSettings};
+#[cfg(feature = "preview")]
+use super::shader::{relocate_error, validate_wgsl};
+
+register_effect!(ShaderEffectBackend, ShaderEffect);
+
+/// Names of the parameters that are always available to a [`ShaderEffect`]
+const BUILTIN_PARAMETERS: [&str; 4] = ["model_matrix", "resolution", "time", "progress"];
+
+static SOURCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// WGSL source of a [`ShaderEffect`], cheap to clone. Effects sharing a
+/// source (and parameter types) share a pipeline
+#[derive(Clone)]
+pub struct ShaderEffectSource {
+    id: usize,
+    source: Arc<str>,
+    /// File `source` was read from, watched in preview mode
+    file: Option<PathBuf>,
+}
+
+impl ShaderEffectSource {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            id: SOURCE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            source: source.into().into(),
+            file: None,
+        }
+    }
+
+    /// Reads the source from `path`, in preview mode the file is reloaded
+    /// whenever it changes. Versions that don't compile are logged and the
+    /// last one that did keeps rendering
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        Ok(Self {
+            file: Some(path.to_path_buf()),
+            ..Self::new(source)
+        })
+    }
+
+    #[inline]
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+impl std::fmt::Debug for ShaderEffectSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShaderEffectSource").field("id", &self.id).field("file", &self.file).finish()
+    }
+}
+
+/// A parameter of a [`ShaderEffect`], available in WGSL as `params.<name>`
+pub enum ShaderEffectParameter {
+    /// `f32` in shaders
+    Float(AnimatedProperty<f32>),
+    /// `vec2<f32>` in shaders
+    Vec2(AnimatedProperty<(f32, f32)>),
+    /// `vec3<f32>` in shaders
+    Vec3(AnimatedProperty<(f32, f32, f32)>),
+    /// `vec4<f32>` in shaders
+    Vec4(AnimatedProperty<(f32, f32, f32, f32)>),
+    /// `vec4<f32>` in shaders, holds the srgb components of the color
+    Color(AnimatedProperty<Color>),
+}
+
+impl ShaderEffectParameter {
+    fn uniform_type(&self) -> UniformType {
+        match self {
+            ShaderEffectParameter::Float(_) => UniformType::Float32,
+            ShaderEffectParameter::Vec2(_) => UniformType::Float32x2,
+            ShaderEffectParameter::Vec3(_) => UniformType::Float32x3,
+            ShaderEffectParameter::Vec4(_) | ShaderEffectParameter::Color(_) => UniformType::Float32x4,
+        }
+    }
+
+    fn evaluate(&self, frame: u64) -> Vec<f32> {
+        match self {
+            ShaderEffectParameter::Float(p) => vec![p.evaluate(frame)],
+            ShaderEffectParameter::Vec2(p) => {
+                let v = p.evaluate(frame);
+                vec![v.0, v.1]
+            }
+            ShaderEffectParameter::Vec3(p) => {
+                let v = p.evaluate(frame);
+                vec![v.0, v.1, v.2]
+            }
+            ShaderEffectParameter::Vec4(p) => {
+                let v = p.evaluate(frame);
+                vec![v.0, v.1, v.2, v.3]
+            }
+            ShaderEffectParameter::Color(p) => Into::<[f32; 4]>::into(p.evaluate(frame)).to_vec(),
+        }
+    }
+}
+
+impl From<AnimatedProperty<f32>> for ShaderEffectParameter {
+    fn from(property: AnimatedProperty<f32>) -> Self {
+        Self::Float(property)
+    }
+}
+
+impl From<AnimatedProperty<(f32, f32)>> for ShaderEffectParameter {
+    fn from(property: AnimatedProperty<(f32, f32)>) -> Self {
+        Self::Vec2(property)
+    }
+}
+
+impl From<AnimatedProperty<(f32, f32, f32)>> for ShaderEffectParameter {
+    fn from(property: AnimatedProperty<(f32, f32, f32)>) -> Self {
+        Self::Vec3(property)
+    }
+}
+
+impl From<AnimatedProperty<(f32, f32, f32, f32)>> for ShaderEffectParameter {
+    fn from(property: AnimatedProperty<(f32, f32, f32, f32)>) -> Self {
+        Self::Vec4(property)
+    }
+}
+
+impl From<AnimatedProperty<Color>> for ShaderEffectParameter {
+    fn from(property: AnimatedProperty<Color>) -> Self {
+        Self::Color(property)
+    }
+}
+
+/// Draws a rectangle shaded by a user-defined WGSL fragment function.
+///
+/// `source` has to define the following function:
+///
+/// ```wgsl
+/// fn fragment(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32> {
+///     return vec4<f32>(uv, 0.5 + 0.5 * sin(params.time), 1.0);
+/// }
+/// ```
+///
+/// `uv` ranges from `(0, 0)` in the top left to `(1, 1)` in the bottom right
+/// of the rectangle, `frag_coord` is the pixel position in the output (with
+/// the origin in the top left). Besides the entries in `parameters`, the
+/// `params` uniform always contains:
+///
+/// - `params.time`: clip time in seconds
+/// - `params.progress`: clip progress ranging from `0.0` to `1.0`
+/// - `params.resolution`: resolution of the video in pixels
+///
+/// Sources loaded with [`ShaderEffectSource::from_file`] are reloaded in
+/// preview mode whenever the file changes
+pub struct ShaderEffect {
+    /// Label of the shader, used in error messages
+    pub name: String,
+    pub source: ShaderEffectSource,
+    pub position: AnimatedProperty<(f32, f32)>,
+    pub size: AnimatedProperty<(f32, f32)>,
+    pub parameters: HashMap<String, ShaderEffectParameter>,
+}
+
+impl ShaderEffect {
+    /// Builds the uniform layout for this effect, parameters are sorted by
+    /// name so equal effects always end up with the same layout.
+    fn uniform_layout(&self) -> Result<UniformLayout> {
+        let mut parameters = self
+            .parameters
+            .iter()
+            .map(|(name, parameter)| match BUILTIN_PARAMETERS.contains(&name.as_str()) {
+                true => Err(Error::Uniform(format!("ShaderEffect parameter name {} is reserved", name))),
+                false => Ok((name.clone(), parameter.uniform_type())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        parameters.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(UniformLayout::new(
+            [
+                ("model_matrix".to_string(), UniformType::Mat4x4),
+                ("resolution".to_string(), UniformType::Float32x2),
+                ("time".to_string(), UniformType::Float32),
+                ("progress".to_string(), UniformType::Float32),
+            ]
+            .into_iter()
+            .chain(parameters),
+        ))
+    }
+}
+
+/// Identifies a pipeline, effects with the same source and parameters share one
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ShaderEffectKey {
+    /// [`ShaderEffectSource::id`]
+    source: usize,
+    layout: UniformLayout,
+}
+
+struct ShaderEffectInstance {
+    key: ShaderEffectKey,
+    /// Label and source the pipeline is built from when it isn't cached yet
+    name: String,
+    source: Arc<str>,
+    data: Vec<u8>,
+}
+
+struct ShaderEffectPipeline {
+    pipeline: wgpu::RenderPipeline,
+    /// Uniform buffers and their bind groups, one for every instance drawn in a frame
+    slots: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+}
+
+pub struct ShaderEffectBackend {
+    settings: VideoSettings,
+    format: wgpu::TextureFormat,
+
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    parameters_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+
+    pipelines: HashMap<ShaderEffectKey, ShaderEffectPipeline>,
+    instances: Vec<ShaderEffectInstance>,
+    /// First error of the instances pushed this frame, returned by `render`
+    error: Option<Error>,
+    /// Sources read from a file, by [`ShaderEffectSource::id`]
+    #[cfg(feature = "preview")]
+    files: HashMap<usize, WatchedSource>,
+}
+
+#[cfg(feature = "preview")]
+struct WatchedSource {
+    /// Set when the file changes, `None` when it can't be watched
+    changed: Option<Arc<AtomicBool>>,
+    /// Last version of the file that compiled, `None` until it changed
+    source: Option<Arc<str>>,
+}
+
+/// Source of the shader for `source` and the line `source` starts at
+fn shader_source(layout: &UniformLayout, source: &str) -> (String, u32) {
+    let template = include_str!("shader_effect.wgsl").replace("{{parameters}}", &layout.wgsl_struct("Parameters"));
+    let first_line = template[..template.find("{{fragment}}").unwrap()].lines().count() as u32 + 1;
+    (template.replace("{{fragment}}", source), first_line)
+}
+
+impl ShaderEffectBackend {
+    fn create_pipeline(&self, device: &wgpu::Device, instance: &ShaderEffectInstance) -> Result<ShaderEffectPipeline> {
+        // Errors in the source of the effect are reported with its own line numbers
+        let (source, first_line) = shader_source(&instance.key.layout, &instance.source);
+        let shader = Shader::compile_embedded(device, instance.name.clone(), source.into(), (first_line, &instance.source))?;
+
+        let pipeline = create_render_pipeline(device, &self.pipeline_layout, &shader, self.format, &[Vertex::desc()], false)?;
+
+        Ok(ShaderEffectPipeline {
+            pipeline,
+            slots: Vec::new(),
+        })
+    }
+
+    /// Source of `effect`, in preview mode the last version of its file
+    /// that compiled. Pipelines built from older versions are dropped
+    #[cfg(feature = "preview")]
+    fn source(&mut self, effect: &ShaderEffect, layout: &UniformLayout) -> Arc<str> {
+        let id = effect.source.id;
+        let path = match effect.source.file.as_ref() {
+            Some(path) => path,
+            None => return effect.source.source.clone(),
+        };
+        let watched = self.files.entry(id).or_insert_with(|| WatchedSource {
+            changed: super::watch::watch_file(path),
+            source: None,
+        });
+
+        if watched.changed.as_ref().is_some_and(|changed| changed.swap(false, Ordering::Relaxed)) {
+            match std::fs::read_to_string(path) {
+                Ok(source) => {
+                    // Checked here, a pipeline that fails to build would end the preview
+                    let (shader, first_line) = shader_source(layout, &source);
+                    match validate_wgsl(&effect.name, &shader) {
+                        Ok(()) => {
+                            info!("Reloaded shader {}", path.display());
+                            watched.source = Some(source.into());
+                            self.pipelines.retain(|key, _| key.source != id);
+                        }
+                        Err(err) => error!("{}\nKeeping the previous version of {}", relocate_error(err, first_line, &source), path.display()),
+                    }
+                }
+                Err(err) => error!("Unable to reload shader {}: {}", path.display(), err),
+            }
+        }
+
+        watched.source.clone().unwrap_or_else(|| effect.source.source.clone())
+    }
+
+    #[cfg(not(feature = "preview"))]
+    fn source(&mut self, effect: &ShaderEffect, _layout: &UniformLayout) -> Arc<str> {
+        effect.source.source.clone()
+    }
+
+    fn instance(&mut self, instance: &ShaderEffect, time: Time) -> Result<ShaderEffectInstance> {
+        let frame = time.clip_frame;
+        let layout = instance.uniform_layout()?;
+        let position = instance.position.evaluate(frame);
+        let size = instance.size.evaluate(frame);
+        let matrix: [[f32; 4]; 4] = (cgmath::Matrix4::from_translation(cgmath::Vector3::new(position.0, position.1, 0.0)) * cgmath::Matrix4::from_nonuniform_scale(size.0, size.1, 1.0) * OPENGL_TO_WGPU_MATRIX).into();
+
+        let mut data = vec![0u8; layout.size() as usize];
+        layout.write(&mut data, "model_matrix", bytemuck::cast_slice(&matrix))?;
+        layout.write(&mut data, "resolution", &[self.settings.resolution.0 as f32, self.settings.resolution.1 as f32])?;
+        layout.write(&mut data, "time", &[time.clip_time as f32])?;
+        layout.write(&mut data, "progress", &[time.clip_progress as f32])?;
+        for (name, parameter) in instance.parameters.iter() {
+            layout.write(&mut data, name, &parameter.evaluate(frame))?;
+        }
+
+        Ok(ShaderEffectInstance {
+            source: self.source(instance, &layout),
+            name: instance.name.clone(),
+            key: ShaderEffectKey {
+                source: instance.source.id,
+                layout,
+            },
+            data,
+        })
+    }
+}
+
+impl EffectBackend for ShaderEffectBackend {
+    type Instance = ShaderEffect;
+
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        match self.instance(instance, time) {
+            Ok(instance) => self.instances.push(instance),
+            Err(err) => {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    fn render<'a>(&'a mut self, mut pass: MutexGuard<'_, wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        if let Some(err) = self.error.take() {
+            self.instances.clear();
+            return Err(err);
+        }
+
+        let mut draws = Vec::with_capacity(self.instances.len());
+        let mut used_slots: HashMap<ShaderEffectKey, usize> = HashMap::new();
+
+        for instance in std::mem::take(&mut self.instances) {
+            if !self.pipelines.contains_key(&instance.key) {
+                let pipeline = self.create_pipeline(device, &instance)?;
+                self.pipelines.insert(instance.key.clone(), pipeline);
+            }
+
+            let pipeline = self.pipelines.get_mut(&instance.key).unwrap();
+            let slot = used_slots.entry(instance.key.clone()).or_insert(0);
+
+            if pipeline.slots.len() <= *slot {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shader Effect Parameter Buffer"),
+                    contents: &instance.data,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Shader Effect Parameter Bind Group"),
+                    layout: &self.parameters_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                });
+                pipeline.slots.push((buffer, bind_group));
+            } else {
+                queue.write_buffer(&pipeline.slots[*slot].0, 0, &instance.data);
+            }
+
+            draws.push((instance.key, *slot));
+            *slot += 1;
+        }
+
+        let this: &'a Self = self;
+        pass.set_vertex_buffer(0, this.vertex_buffer.slice(..));
+        pass.set_index_buffer(this.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        for (key, slot) in draws {
+            let pipeline = &this.pipelines[&key];
+            pass.set_pipeline(&pipeline.pipeline);
+            pass.set_bind_group(1, &pipeline.slots[slot].1, &[]);
+            pass.draw_indexed(0..6, 0, 0..1);
+        }
+
+        Ok(())
+    }
+}
+
+impl Effect for ShaderEffectBackend {
+    fn new(renderer: &mut Renderer) -> Result<Self> {
+        let device = renderer.wgpu_device();
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shader Effect Vertex Buffer"),
+            contents: bytemuck::cast_slice(&[
+                Vertex { position: [-0.5, -0.5], uv: [0.0, 1.0] },
+                Vertex { position: [ 0.5, -0.5], uv: [1.0, 1.0] },
+                Vertex { position: [-0.5,  0.5], uv: [0.0, 0.0] },
+                Vertex { position: [ 0.5,  0.5], uv: [1.0, 0.0] },
+            ]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shader Effect Index Buffer"),
+            contents: bytemuck::cast_slice::<u16, u8>(&[
+                0, 1, 2,
+                2, 1, 3,
+            ]),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let parameters_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shader Effect Parameter Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shader Effect Pipeline Layout"),
+            bind_group_layouts: &[renderer.wgpu_transform_bind_group_layout(), &parameters_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Ok(Self {
+            settings: renderer.settings,
+            format: renderer.wgpu_config().format,
+
+            vertex_buffer,
+            index_buffer,
+            parameters_bind_group_layout,
+            pipeline_layout,
+
+            pipelines: HashMap::new(),
+            instances: Vec::new(),
+            error: None,
+            #[cfg(feature = "preview")]
+            files: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shader_source, ShaderEffect, ShaderEffectSource};
+    use crate::api::{animation::AnimatedProperty, shader::validate_wgsl};
+
+    #[test]
+    fn sources_keep_their_id_when_cloned() {
+        let source = ShaderEffectSource::new("fn fragment(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32> { return vec4<f32>(1.0); }");
+        assert_eq!(source.clone().id, source.id);
+        assert_ne!(ShaderEffectSource::new(source.source()).id, source.id);
+    }
+
+    #[test]
+    fn effect_source_is_valid_wgsl() {
+        let mut effect = ShaderEffect {
+            name: "gradient".to_string(),
+            source: ShaderEffectSource::new("fn fragment(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32> {\n    return vec4<f32>(uv, params.strength * params.progress, 1.0);\n}\n"),
+            position: AnimatedProperty::new((0.0, 0.0), Vec::new()),
+            size: AnimatedProperty::new((1.0, 1.0), Vec::new()),
+            parameters: Default::default(),
+        };
+        effect.parameters.insert("strength".to_string(), AnimatedProperty::new(0.5, Vec::new()).into());
+
+        let (source, _) = shader_source(&effect.uniform_layout().unwrap(), effect.source.source());
+        if let Err(err) = validate_wgsl("gradient", &source) {
+            panic!("{}\n{}", err.message, source);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+struct TransformUniform {
+    transform_matrix: mat4x4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> transform_uniform: TransformUniform;
+
+{{parameters}}
+@group(1)
+@binding(0)
+var<uniform> params: Parameters;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    let transform_matrix = transform_uniform.transform_matrix;
+
+    var out: VertexOutput;
+    out.uv = model.uv;
+    out.clip_position = transform_matrix * params.model_matrix * vec4<f32>(model.position, 0.0, 1.0);
+    return out;
+}
+
+{{fragment}}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return fragment(in.uv, in.clip_position.xy);
+}
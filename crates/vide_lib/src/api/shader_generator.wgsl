@@ -0,0 +1,58 @@
+struct TransformUniform {
+    transform_matrix: mat4x4<f32>,
+};
+
+@group(0)
+@binding(0)
+var<uniform> transform_uniform: TransformUniform;
+
+{{vertex_parameters}}
+@group(1)
+@binding(0)
+var<uniform> vertex_params: VertexParameters;
+
+{{fragment_parameters}}
+@group(1)
+@binding(1)
+var<uniform> fragment_params: FragmentParameters;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+{{functions}}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    let params = vertex_params;
+    var position = model.position;
+    var uv = model.uv;
+
+{{mesh_passes}}
+    var clip_position = transform_uniform.transform_matrix * params.model_matrix * vec4<f32>(position, 0.0, 1.0);
+
+{{vertex_passes}}
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let params = fragment_params;
+    var uv = in.uv;
+    let frag_coord = in.clip_position.xy;
+    var color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+
+{{fragment_passes}}
+    return color;
+}
@@ -0,0 +1,584 @@
+use std::{ops::Range, sync::MutexGuard};
+
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::Time, error::{Error, Result}};
+
+use super::{
+    animation::AnimatedProperty,
+    color::Color,
+    font::{AtlasGlyph, Font, GlyphAtlas, ATLAS_SIZE, SDF_EM_SIZE},
+    instanced_mesh::InstancedMesh,
+    mesh::{Vertex, VertexAttributeDescriptor},
+    shader::Shader,
+    text_animator::{GlyphState, TextAnimator, TextUnit},
+    texture::{create_sampler, create_texture, texture_bind_group, texture_bind_group_layout, write_texture},
+    transform::OPENGL_TO_WGPU_MATRIX,
+};
+
+register_effect!(TextBackend, Text);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalAlign {
+    #[default]
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// How lines are broken and placed relative to [`Text::position`]
+#[derive(Debug, Clone, Copy)]
+pub struct TextLayout {
+    /// Which side of each line sits on the anchor
+    pub align: TextAlign,
+    /// Which side of the whole block sits on the anchor
+    pub vertical_align: VerticalAlign,
+    /// Lines are wrapped at word boundaries when they get wider than this (in
+    /// pixels), words that don't fit on a line of their own are broken between
+    /// characters
+    pub wrap_width: Option<f32>,
+    /// Distance between baselines as a multiple of the font's line height
+    pub line_height: f32,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        Self {
+            align: TextAlign::Left,
+            vertical_align: VerticalAlign::Top,
+            wrap_width: None,
+            line_height: 1.0,
+        }
+    }
+}
+
+pub struct Text {
+    pub content: AnimatedProperty<String>,
+    pub font: Font,
+    pub position: AnimatedProperty<(f32, f32)>,
+    /// Font size in pixels
+    pub size: AnimatedProperty<f32>,
+    pub color: AnimatedProperty<Color>,
+    /// Extra space between characters in thousandths of an em
+    pub tracking: AnimatedProperty<f32>,
+    pub layout: TextLayout,
+    /// Applied in order, see [`TextAnimator`]
+    pub animators: Vec<TextAnimator>,
+}
+
+/// A shaped glyph, relative to the anchor of its text in pixels with y pointing up
+#[derive(Debug, Clone, Copy)]
+pub struct PositionedGlyph {
+    pub glyph_id: u16,
+    /// Pen position on the baseline
+    pub x: f32,
+    pub y: f32,
+    pub advance: f32,
+    /// Index of the first character of the cluster this glyph was shaped from
+    pub char_index: usize,
+    /// Index of the whitespace separated word this glyph belongs to
+    pub word_index: usize,
+    pub line_index: usize,
+}
+
+/// Shapes `text` and breaks it into lines
+pub fn layout_text(face: &rustybuzz::Face, text: &str, size: f32, tracking: f32, layout: &TextLayout) -> Vec<PositionedGlyph> {
+    let scale = size / face.units_per_em() as f32;
+    let ascender = face.ascender() as f32 * scale;
+    let descender = face.descender() as f32 * scale;
+    let line_advance = (ascender - descender + face.line_gap() as f32 * scale) * layout.line_height;
+    let tracking = tracking / 1000.0 * size;
+
+    // Character and word index for every byte offset a cluster can start at
+    let mut char_indices = vec![0; text.len() + 1];
+    let mut word_indices = vec![0; text.len() + 1];
+    let mut word = 0;
+    let mut previous_whitespace = false;
+    for (i, (offset, c)) in text.char_indices().enumerate() {
+        if previous_whitespace && !c.is_whitespace() {
+            word += 1;
+        }
+        previous_whitespace = c.is_whitespace();
+        char_indices[offset] = i;
+        word_indices[offset] = word;
+    }
+
+    struct Line {
+        glyphs: Vec<(PositionedGlyph, bool)>,
+        pen: f32,
+    }
+
+    impl Line {
+        /// Width without trailing whitespace
+        fn width(&self) -> f32 {
+            self.glyphs
+                .iter()
+                .rev()
+                .find(|(_, whitespace)| !whitespace)
+                .map(|(glyph, _)| glyph.x + glyph.advance)
+                .unwrap_or(0.0)
+        }
+    }
+
+    let mut lines = Vec::new();
+    // Also breaks at `\r\n`, a trailing line break doesn't add an empty line
+    for paragraph in text.lines() {
+        let paragraph_start = paragraph.as_ptr() as usize - text.as_ptr() as usize;
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(paragraph);
+        buffer.guess_segment_properties();
+        let shaped = rustybuzz::shape(face, &[], buffer);
+
+        let mut line = Line { glyphs: Vec::new(), pen: 0.0 };
+        // Index into `line.glyphs` right after the last whitespace
+        let mut last_break = None;
+        let mut previous_cluster = None;
+
+        for (info, position) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+            let cluster = paragraph_start + info.cluster as usize;
+            let whitespace = text[cluster..].chars().next().is_some_and(char::is_whitespace);
+            let advance = position.x_advance as f32 * scale + tracking;
+
+            // Glyphs shaped from the same cluster (like a letter and its accents) stay together
+            let new_cluster = previous_cluster != Some(cluster);
+            previous_cluster = Some(cluster);
+
+            if let Some(wrap_width) = layout.wrap_width {
+                // Without whitespace to break at, the word is too long for a line and breaks right here
+                let at = last_break.or((new_cluster && !line.glyphs.is_empty()).then_some(line.glyphs.len()));
+                if let (false, true, Some(at)) = (whitespace, line.pen + advance > wrap_width, at) {
+                    let mut glyphs = line.glyphs.split_off(at);
+                    let shift = glyphs.first().map_or(0.0, |(glyph, _)| glyph.x);
+                    for (glyph, _) in glyphs.iter_mut() {
+                        glyph.x -= shift;
+                    }
+                    lines.push(line);
+                    line = Line { pen: glyphs.iter().map(|(glyph, _)| glyph.advance).sum(), glyphs };
+                    last_break = None;
+                }
+            }
+
+            line.glyphs.push((
+                PositionedGlyph {
+                    glyph_id: info.glyph_id as u16,
+                    x: line.pen + position.x_offset as f32 * scale,
+                    y: position.y_offset as f32 * scale,
+                    advance,
+                    char_index: char_indices[cluster],
+                    word_index: word_indices[cluster],
+                    line_index: 0,
+                },
+                whitespace,
+            ));
+            line.pen += advance;
+
+            if whitespace {
+                last_break = Some(line.glyphs.len());
+            }
+        }
+
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    let height = ascender - descender + line_advance * (lines.len() - 1) as f32;
+    let first_baseline = match layout.vertical_align {
+        VerticalAlign::Top => -ascender,
+        VerticalAlign::Middle => height / 2.0 - ascender,
+        VerticalAlign::Bottom => height - ascender,
+    };
+
+    let mut glyphs = Vec::new();
+    for (line_index, line) in lines.into_iter().enumerate() {
+        let x = match layout.align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => -line.width() / 2.0,
+            TextAlign::Right => -line.width(),
+        };
+        let y = first_baseline - line_advance * line_index as f32;
+
+        glyphs.extend(line.glyphs.into_iter().map(|(glyph, _)| PositionedGlyph {
+            x: glyph.x + x,
+            y: glyph.y + y,
+            line_index,
+            ..glyph
+        }));
+    }
+
+    glyphs
+}
+
+/// Number of characters, words and lines a [`RangeSelector`](super::text_animator::RangeSelector) sweeps over
+#[derive(Debug, PartialEq, Eq)]
+struct UnitCounts {
+    characters: usize,
+    words: usize,
+    lines: usize,
+}
+
+impl UnitCounts {
+    fn new(content: &str, glyphs: &[PositionedGlyph]) -> Self {
+        Self {
+            characters: content.chars().count(),
+            words: glyphs.iter().map(|glyph| glyph.word_index + 1).max().unwrap_or(0),
+            lines: glyphs.iter().map(|glyph| glyph.line_index + 1).max().unwrap_or(0),
+        }
+    }
+
+    /// Index of the `unit` `glyph` belongs to and the number of those units
+    fn unit(&self, unit: TextUnit, glyph: &PositionedGlyph) -> (usize, usize) {
+        match unit {
+            TextUnit::Characters => (glyph.char_index, self.characters),
+            TextUnit::Words => (glyph.word_index, self.words),
+            TextUnit::Lines => (glyph.line_index, self.lines),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct TextInstance {
+    matrix: [[f32; 4]; 4],
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+impl TextInstance {
+    /// Places the glyph's quad, `transform` is applied around the middle of the glyph's baseline
+    fn new(glyph: &PositionedGlyph, atlas_glyph: &AtlasGlyph, size: f32, position: (f32, f32), transform: cgmath::Matrix4<f32>, color: Color) -> Self {
+        let scale = size / SDF_EM_SIZE;
+        let (width, height) = (atlas_glyph.size.0 * scale, atlas_glyph.size.1 * scale);
+        let center = (atlas_glyph.offset.0 * scale + width / 2.0 - glyph.advance / 2.0, atlas_glyph.offset.1 * scale - height / 2.0);
+
+        Self {
+            matrix: (cgmath::Matrix4::from_translation(cgmath::Vector3::new(position.0 + glyph.x + glyph.advance / 2.0, position.1 + glyph.y, 0.0))
+                * transform
+                * cgmath::Matrix4::from_translation(cgmath::Vector3::new(center.0, center.1, 0.0))
+                * cgmath::Matrix4::from_nonuniform_scale(width, height, 1.0)
+                * OPENGL_TO_WGPU_MATRIX)
+                .into(),
+            uv_rect: atlas_glyph.uv,
+            color: color.into(),
+        }
+    }
+}
+
+impl VertexAttributeDescriptor for TextInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TextInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                },
+            ],
+        }
+    }
+}
+
+unsafe impl bytemuck::Pod for TextInstance {}
+unsafe impl bytemuck::Zeroable for TextInstance {}
+
+pub struct TextBackend {
+    mesh: InstancedMesh<TextInstance>,
+    atlas: GlyphAtlas,
+    /// Texture and bind group for every page of `atlas`, created when the page is first uploaded
+    atlas_pages: Vec<(wgpu::Texture, wgpu::BindGroup)>,
+    atlas_sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Instances with the atlas page their glyph is on
+    instances: Vec<(usize, TextInstance)>,
+    /// First error hit while pushing, returned from the next render
+    error: Option<Error>,
+}
+
+impl EffectBackend for TextBackend {
+    type Instance = Text;
+
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        let frame = time.clip_frame;
+        let content = instance.content.evaluate(frame);
+        let position = instance.position.evaluate(frame);
+        let size = instance.size.evaluate(frame);
+        let color = instance.color.evaluate(frame);
+        let tracking = instance.tracking.evaluate(frame);
+
+        let face = instance.font.face();
+        let glyphs = layout_text(&face, &content, size, tracking, &instance.layout);
+
+        let selectors = instance.animators.iter().map(|animator| animator.selector.evaluate(frame)).collect::<Vec<_>>();
+        let counts = UnitCounts::new(&content, &glyphs);
+
+        for glyph in glyphs {
+            let mut state = GlyphState::new(color);
+            for (animator, selector) in instance.animators.iter().zip(selectors.iter()) {
+                let (index, count) = counts.unit(selector.unit(), &glyph);
+                state.apply(animator, selector.weight(index, count));
+            }
+
+            match self.atlas.glyph(&instance.font, &face, glyph.glyph_id) {
+                Ok(Some(atlas_glyph)) => self.instances.push((atlas_glyph.page, TextInstance::new(&glyph, &atlas_glyph, size, position, state.transform(), state.color))),
+                Ok(None) => (),
+                Err(err) => {
+                    self.error.get_or_insert(err);
+                }
+            }
+        }
+    }
+
+    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        if let Some(err) = self.error.take() {
+            self.instances.clear();
+            return Err(err);
+        }
+
+        for (index, page) in self.atlas.pages.iter_mut().enumerate() {
+            if index == self.atlas_pages.len() {
+                let texture = create_texture(device, ATLAS_SIZE, ATLAS_SIZE, wgpu::TextureFormat::R8Unorm, "Glyph Atlas");
+                let bind_group = texture_bind_group(device, &self.bind_group_layout, &texture, &self.atlas_sampler, "Glyph Atlas Bind Group");
+                self.atlas_pages.push((texture, bind_group));
+            }
+            if page.dirty {
+                page.dirty = false;
+                write_texture(queue, &self.atlas_pages[index].0, ATLAS_SIZE, ATLAS_SIZE, 1, &page.data);
+            }
+        }
+        self.atlas.end_frame();
+
+        // Glyphs are drawn in order, a batch ends wherever the page changes
+        let mut batches: Vec<(Range<u32>, Vec<&wgpu::BindGroup>)> = Vec::new();
+        let mut batch_page = None;
+        for (index, &(page, _)) in self.instances.iter().enumerate() {
+            let index = index as u32;
+            match batches.last_mut() {
+                Some((range, _)) if batch_page == Some(page) => range.end = index + 1,
+                _ => batches.push((index..index + 1, vec![&self.atlas_pages[page].1])),
+            }
+            batch_page = Some(page);
+        }
+
+        let instances = self.instances.drain(..).map(|(_, instance)| instance).collect();
+        self.mesh.render_batches(pass, device, queue, instances, &batches);
+        Ok(())
+    }
+}
+
+impl Effect for TextBackend {
+    fn new(renderer: &mut crate::render::Renderer) -> Result<Self> {
+        let device = renderer.wgpu_device();
+
+        let atlas_sampler = create_sampler(device, wgpu::FilterMode::Linear, "Glyph Atlas Sampler");
+        let bind_group_layout = texture_bind_group_layout(device, "Glyph Atlas Bind Group Layout");
+
+        let shader = Shader::new(renderer, "text.wgsl", include_str!("text.wgsl").into())?;
+        let mesh = InstancedMesh::new(
+            renderer,
+            vec![
+                Vertex { position: [-0.5, -0.5], uv: [0.0, 1.0] },
+                Vertex { position: [ 0.5, -0.5], uv: [1.0, 1.0] },
+                Vertex { position: [-0.5,  0.5], uv: [0.0, 0.0] },
+                Vertex { position: [ 0.5,  0.5], uv: [1.0, 0.0] },
+            ],
+            Some(vec![
+                0, 1, 2,
+                2, 1, 3,
+            ]),
+            shader,
+            &[&bind_group_layout],
+        )?;
+
+        Ok(Self {
+            mesh,
+            atlas: GlyphAtlas::new(),
+            atlas_pages: Vec::new(),
+            atlas_sampler,
+            bind_group_layout,
+            instances: Vec::new(),
+            error: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{layout_text, PositionedGlyph, TextAlign, TextLayout, TextUnit, UnitCounts, VerticalAlign};
+
+    /// Builds a font without outlines: 1000 units per em, an ascender of 800
+    /// and a descender of -200. Spaces advance 250 units and `A` to `Z` 500,
+    /// except for `A` followed by `V` which is kerned by -100
+    fn test_font() -> Vec<u8> {
+        fn table(fields: &[(u32, usize)]) -> Vec<u8> {
+            fields.iter().flat_map(|&(value, size)| value.to_be_bytes()[4 - size..].to_vec()).collect()
+        }
+        let glyph = |c: char| c as u32 - 'A' as u32 + 2;
+
+        let mut head = table(&[(0x0001_0000, 4), (0, 4), (0, 4), (0x5F0F_3CF5, 4), (0, 2), (1000, 2)]);
+        head.resize(54, 0);
+        let mut hhea = table(&[(0x0001_0000, 4), (800, 2), (-200i16 as u16 as u32, 2), (0, 2)]);
+        hhea.resize(34, 0);
+        hhea.extend(table(&[(28, 2)]));
+        let maxp = table(&[(0x0000_5000, 4), (28, 2)]);
+        let mut hmtx = table(&[(500, 2), (0, 2), (250, 2), (0, 2)]);
+        for _ in 'A'..='Z' {
+            hmtx.extend(table(&[(500, 2), (0, 2)]));
+        }
+        let cmap = table(&[
+            (0, 2), (1, 2), (3, 2), (10, 2), (12, 4),
+            (12, 2), (0, 2), (40, 4), (0, 4), (2, 4),
+            (' ' as u32, 4), (' ' as u32, 4), (1, 4),
+            ('A' as u32, 4), ('Z' as u32, 4), (2, 4),
+        ]);
+        let kern = table(&[
+            (0, 2), (1, 2),
+            (0, 2), (20, 2), (0, 1), (1, 1),
+            (1, 2), (6, 2), (0, 2), (0, 2),
+            (glyph('A'), 2), (glyph('V'), 2), (-100i16 as u16 as u32, 2),
+        ]);
+
+        let tables = [(b"cmap", cmap), (b"head", head), (b"hhea", hhea), (b"hmtx", hmtx), (b"kern", kern), (b"maxp", maxp)];
+        let mut font = table(&[(0x0001_0000, 4), (tables.len() as u32, 2), (0, 2), (0, 2), (0, 2)]);
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, data) in &tables {
+            font.extend(tag.iter());
+            font.extend(table(&[(0, 4), (offset as u32, 4), (data.len() as u32, 4)]));
+            offset += data.len().next_multiple_of(4);
+        }
+        for (_, mut data) in tables {
+            data.resize(data.len().next_multiple_of(4), 0);
+            font.extend(data);
+        }
+        font
+    }
+
+    /// Lays out `text` at 100 pixels, where a letter is 50 pixels wide and a space 25
+    fn layout(text: &str, layout: TextLayout) -> Vec<PositionedGlyph> {
+        let font = test_font();
+        let face = rustybuzz::Face::from_slice(&font, 0).unwrap();
+        layout_text(&face, text, 100.0, 0.0, &layout)
+    }
+
+    fn positions(glyphs: &[PositionedGlyph]) -> Vec<(f32, f32)> {
+        glyphs.iter().map(|glyph| (glyph.x, glyph.y)).collect()
+    }
+
+    #[test]
+    fn glyphs_follow_their_advance() {
+        let glyphs = layout("AB C", TextLayout::default());
+        assert_eq!(positions(&glyphs), [(0.0, -80.0), (50.0, -80.0), (100.0, -80.0), (125.0, -80.0)]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.word_index).collect::<Vec<_>>(), [0, 0, 0, 1]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.char_index).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn kerning_and_tracking_are_applied() {
+        let glyphs = layout("AVA", TextLayout::default());
+        assert_eq!(glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(), [0.0, 40.0, 90.0]);
+
+        let font = test_font();
+        let face = rustybuzz::Face::from_slice(&font, 0).unwrap();
+        // 100 thousandths of an em at 100 pixels add 10 pixels after every glyph
+        let glyphs = layout_text(&face, "AB", 100.0, 100.0, &TextLayout::default());
+        assert_eq!(glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(), [0.0, 60.0]);
+    }
+
+    #[test]
+    fn newlines_start_new_lines() {
+        let glyphs = layout("A\nB\r\n\nC\n", TextLayout::default());
+        assert_eq!(positions(&glyphs), [(0.0, -80.0), (0.0, -180.0), (0.0, -380.0)]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.line_index).collect::<Vec<_>>(), [0, 1, 3]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.char_index).collect::<Vec<_>>(), [0, 2, 6]);
+
+        let glyphs = layout("A\nB", TextLayout { line_height: 1.5, ..Default::default() });
+        assert_eq!(positions(&glyphs), [(0.0, -80.0), (0.0, -230.0)]);
+        assert!(layout("", TextLayout::default()).is_empty());
+    }
+
+    #[test]
+    fn lines_are_aligned_without_trailing_whitespace() {
+        let text = "AB \nA";
+        let x = |align| layout(text, TextLayout { align, ..Default::default() }).iter().map(|glyph| glyph.x).collect::<Vec<_>>();
+        assert_eq!(x(TextAlign::Left), [0.0, 50.0, 100.0, 0.0]);
+        assert_eq!(x(TextAlign::Center), [-50.0, 0.0, 50.0, -25.0]);
+        assert_eq!(x(TextAlign::Right), [-100.0, -50.0, 0.0, -50.0]);
+
+        // Two lines are 200 pixels tall
+        let y = |vertical_align| layout("A\nA", TextLayout { vertical_align, ..Default::default() }).iter().map(|glyph| glyph.y).collect::<Vec<_>>();
+        assert_eq!(y(VerticalAlign::Top), [-80.0, -180.0]);
+        assert_eq!(y(VerticalAlign::Middle), [20.0, -80.0]);
+        assert_eq!(y(VerticalAlign::Bottom), [120.0, 20.0]);
+    }
+
+    #[test]
+    fn lines_wrap_at_whitespace() {
+        let wrapped = TextLayout { wrap_width: Some(160.0), ..Default::default() };
+        // "AB CD" is 225 pixels wide, trailing whitespace may hang over the edge
+        let glyphs = layout("AB CD EF", wrapped);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.line_index).collect::<Vec<_>>(), [0, 0, 0, 1, 1, 1, 2, 2]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(), [0.0, 50.0, 100.0, 0.0, 50.0, 100.0, 0.0, 50.0]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.word_index).collect::<Vec<_>>(), [0, 0, 0, 1, 1, 1, 2, 2]);
+
+        let glyphs = layout("AB CD", TextLayout { align: TextAlign::Right, ..wrapped });
+        assert_eq!(glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(), [-100.0, -50.0, 0.0, -100.0, -50.0]);
+    }
+
+    #[test]
+    fn long_words_are_broken() {
+        let glyphs = layout("A BCDEFG", TextLayout { wrap_width: Some(120.0), ..Default::default() });
+        assert_eq!(glyphs.iter().map(|glyph| glyph.line_index).collect::<Vec<_>>(), [0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(), [0.0, 50.0, 0.0, 50.0, 0.0, 50.0, 0.0, 50.0]);
+
+        // A line always gets at least one glyph
+        let glyphs = layout("AB", TextLayout { wrap_width: Some(10.0), ..Default::default() });
+        assert_eq!(positions(&glyphs), [(0.0, -80.0), (0.0, -180.0)]);
+    }
+
+    #[test]
+    fn units_are_counted_from_the_layout() {
+        let text = "AB CD\nE  F\n";
+        let glyphs = layout(text, TextLayout { wrap_width: Some(110.0), ..Default::default() });
+        let counts = UnitCounts::new(text, &glyphs);
+        // Whitespace and line breaks are characters too, both paragraphs wrap
+        assert_eq!(counts, UnitCounts { characters: 11, words: 4, lines: 4 });
+
+        let units = |unit| glyphs.iter().map(|glyph| counts.unit(unit, glyph).0).collect::<Vec<_>>();
+        assert_eq!(units(TextUnit::Characters), [0, 1, 2, 3, 4, 6, 7, 8, 9]);
+        assert_eq!(units(TextUnit::Words), [0, 0, 0, 1, 1, 2, 2, 2, 3]);
+        assert_eq!(units(TextUnit::Lines), [0, 0, 0, 1, 1, 2, 2, 2, 3]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::f32::consts::PI;
+
+use crate::unanimated;
+
+use super::{
+    animation::{ease::{EasingFunction, LINEAR}, AnimatedProperty, Interpolate},
+    color::Color,
+};
+
+/// What a [`RangeSelector`] counts when sweeping over text
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextUnit {
+    #[default]
+    Characters,
+    /// Runs of characters separated by whitespace
+    Words,
+    Lines,
+}
+
+/// How the weight of a unit depends on its position inside the selected
+/// range, ranges with `end <= start` select nothing except for the ramps
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SelectorShape {
+    /// Everything inside the range is fully selected
+    #[default]
+    Square,
+    /// Goes from 0 at the start of the range to 1 at its end, units after the range are fully selected
+    RampUp,
+    /// Goes from 1 at the start of the range to 0 at its end, units before the range are fully selected
+    RampDown,
+    /// Peaks linearly in the middle of the range
+    Triangle,
+    /// Peaks in the middle of the range along a semicircle
+    Round,
+    /// Peaks in the middle of the range along a cosine, nice for waves
+    Smooth,
+}
+
+/// Decides how strongly each character, word or line is affected by a [`TextAnimator`]
+pub struct RangeSelector {
+    pub unit: TextUnit,
+    pub shape: SelectorShape,
+    /// Start of the range, `0.0` is before the first unit and `1.0` after the last one
+    pub start: AnimatedProperty<f32>,
+    /// End of the range, `0.0` is before the first unit and `1.0` after the last one
+    pub end: AnimatedProperty<f32>,
+    /// Moves both ends of the range
+    pub offset: AnimatedProperty<f32>,
+    /// Number of units the edges of a [`SelectorShape::Square`] range are
+    /// blended over. With `0.0` units switch one after another, larger
+    /// values let neighbouring units animate at the same time. The range is
+    /// widened so `0.0` to `1.0` still selects every unit fully
+    pub stagger: f32,
+    /// Applied to the weight of every unit
+    pub easing: EasingFunction,
+}
+
+impl Default for RangeSelector {
+    fn default() -> Self {
+        Self {
+            unit: TextUnit::Characters,
+            shape: SelectorShape::Square,
+            start: unanimated!(0.0),
+            end: unanimated!(1.0),
+            offset: unanimated!(0.0),
+            stagger: 0.0,
+            easing: LINEAR,
+        }
+    }
+}
+
+impl RangeSelector {
+    pub(crate) fn evaluate(&self, frame: u64) -> EvaluatedSelector<'_> {
+        let offset = self.offset.evaluate(frame);
+        EvaluatedSelector {
+            selector: self,
+            start: self.start.evaluate(frame) + offset,
+            end: self.end.evaluate(frame) + offset,
+        }
+    }
+}
+
+/// A [`RangeSelector`] with its range evaluated for one frame
+pub(crate) struct EvaluatedSelector<'a> {
+    selector: &'a RangeSelector,
+    start: f32,
+    end: f32,
+}
+
+impl EvaluatedSelector<'_> {
+    pub fn unit(&self) -> TextUnit {
+        self.selector.unit
+    }
+
+    /// How strongly unit `index` out of `count` is affected, from `0.0` to `1.0`
+    pub fn weight(&self, index: usize, count: usize) -> f32 {
+        let (start, end) = (self.start * count as f32, self.end * count as f32);
+        let x = index as f32 + 0.5;
+        let inside = x >= start && x <= end;
+        // Empty ranges turn the ramps into a step at `start`
+        let t = if end > start { (x - start) / (end - start) } else if x > start { 1.0 } else { 0.0 };
+
+        let stagger = self.selector.stagger;
+        let weight = match self.selector.shape {
+            SelectorShape::Square if end <= start => 0.0,
+            SelectorShape::Square if stagger > 0.0 => {
+                // Each edge is blended over `stagger` units, centered on the edge
+                let widen = |edge: f32| edge * (count as f32 + stagger) - stagger / 2.0;
+                let edge = |distance: f32| (distance / stagger + 0.5).clamp(0.0, 1.0);
+                edge(x - widen(self.start)).min(edge(widen(self.end) - x))
+            }
+            SelectorShape::Square if x > start && x <= end => 1.0,
+            SelectorShape::RampUp => t.clamp(0.0, 1.0),
+            SelectorShape::RampDown => 1.0 - t.clamp(0.0, 1.0),
+            SelectorShape::Triangle if inside => 1.0 - (2.0 * t - 1.0).abs(),
+            SelectorShape::Round if inside => (1.0 - (2.0 * t - 1.0).powi(2)).max(0.0).sqrt(),
+            SelectorShape::Smooth if inside => (1.0 - (2.0 * PI * t).cos()) / 2.0,
+            _ => 0.0,
+        };
+
+        (self.selector.easing)(weight as f64) as f32
+    }
+}
+
+/// Offsets, rotates, scales, fades or recolors the glyphs picked by its
+/// [`RangeSelector`], every property is blended in by the glyph's weight
+///
+/// ```ignore
+/// // Typewriter, characters after the start of the range are hidden
+/// TextAnimator::builder()
+///     .start(Animation::new(60.0)
+///         .keyframe(Abs(0.0), ease::LINEAR, 0.0)
+///         .keyframe(Abs(2.0), ease::LINEAR, 1.0)
+///         .build())
+///     .opacity(0.0)
+///     .build()
+///
+/// // Words fade in one after another while sliding up
+/// TextAnimator::builder()
+///     .unit(TextUnit::Words)
+///     .stagger(2.0)
+///     .start(Animation::new(60.0)
+///         .keyframe(Abs(0.0), ease::LINEAR, 0.0)
+///         .keyframe(Abs(1.5), ease::LINEAR, 1.0)
+///         .build())
+///     .offset((0.0, -30.0))
+///     .opacity(0.0)
+///     .build()
+///
+/// // A wave travelling through the text
+/// TextAnimator::builder()
+///     .shape(SelectorShape::Smooth)
+///     .range(unanimated!(0.0), unanimated!(0.3))
+///     .range_offset(Animation::new(60.0)
+///         .keyframe(Abs(0.0), ease::LINEAR, -0.3)
+///         .keyframe(Abs(1.0), ease::LINEAR, 1.0)
+///         .build())
+///     .offset((0.0, 20.0))
+///     .build()
+/// ```
+#[derive(Default)]
+pub struct TextAnimator {
+    pub selector: RangeSelector,
+    /// Moves glyphs in pixels
+    pub offset: Option<(f32, f32)>,
+    /// Rotates glyphs counter-clockwise in degrees around the middle of their baseline
+    pub rotation: Option<f32>,
+    /// Scales glyphs around the middle of their baseline
+    pub scale: Option<(f32, f32)>,
+    /// Multiplied with the text's alpha
+    pub opacity: Option<f32>,
+    /// Replaces the text's color
+    pub color: Option<Color>,
+}
+
+impl TextAnimator {
+    pub fn builder() -> TextAnimatorBuilder {
+        TextAnimatorBuilder {
+            animator: TextAnimator::default(),
+        }
+    }
+}
+
+/// Accumulated effect of all animators on a single glyph
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GlyphState {
+    pub offset: (f32, f32),
+    pub rotation: f32,
+    pub scale: (f32, f32),
+    pub color: Color,
+}
+
+impl GlyphState {
+    pub fn new(color: Color) -> Self {
+        Self {
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            scale: (1.0, 1.0),
+            color,
+        }
+    }
+
+    pub fn apply(&mut self, animator: &TextAnimator, weight: f32) {
+        if weight <= 0.0 {
+            return;
+        }
+
+        if let Some(offset) = animator.offset {
+            self.offset.0 += offset.0 * weight;
+            self.offset.1 += offset.1 * weight;
+        }
+        if let Some(rotation) = animator.rotation {
+            self.rotation += rotation * weight;
+        }
+        if let Some(scale) = animator.scale {
+            self.scale.0 *= f32::interpolate(1.0, scale.0, weight as f64);
+            self.scale.1 *= f32::interpolate(1.0, scale.1, weight as f64);
+        }
+        if let Some(color) = animator.color {
+            let alpha = self.color.a;
+            self.color = Color::interpolate(self.color, Color { a: alpha, ..color }, weight as f64);
+        }
+        if let Some(opacity) = animator.opacity {
+            self.color.a *= f64::interpolate(1.0, opacity as f64, weight as f64);
+        }
+    }
+
+    pub fn transform(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(cgmath::Vector3::new(self.offset.0, self.offset.1, 0.0))
+            * cgmath::Matrix4::from_angle_z(cgmath::Deg(self.rotation))
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.0, self.scale.1, 1.0)
+    }
+}
+
+pub struct TextAnimatorBuilder {
+    animator: TextAnimator,
+}
+
+impl TextAnimatorBuilder {
+    pub fn unit(&mut self, unit: TextUnit) -> &mut Self {
+        self.animator.selector.unit = unit;
+        self
+    }
+
+    pub fn shape(&mut self, shape: SelectorShape) -> &mut Self {
+        self.animator.selector.shape = shape;
+        self
+    }
+
+    pub fn start(&mut self, start: AnimatedProperty<f32>) -> &mut Self {
+        self.animator.selector.start = start;
+        self
+    }
+
+    pub fn end(&mut self, end: AnimatedProperty<f32>) -> &mut Self {
+        self.animator.selector.end = end;
+        self
+    }
+
+    pub fn range(&mut self, start: AnimatedProperty<f32>, end: AnimatedProperty<f32>) -> &mut Self {
+        self.start(start).end(end)
+    }
+
+    pub fn range_offset(&mut self, offset: AnimatedProperty<f32>) -> &mut Self {
+        self.animator.selector.offset = offset;
+        self
+    }
+
+    pub fn stagger(&mut self, stagger: f32) -> &mut Self {
+        self.animator.selector.stagger = stagger;
+        self
+    }
+
+    pub fn easing(&mut self, easing: EasingFunction) -> &mut Self {
+        self.animator.selector.easing = easing;
+        self
+    }
+
+    pub fn offset(&mut self, offset: (f32, f32)) -> &mut Self {
+        self.animator.offset = Some(offset);
+        self
+    }
+
+    pub fn rotation(&mut self, degrees: f32) -> &mut Self {
+        self.animator.rotation = Some(degrees);
+        self
+    }
+
+    pub fn scale(&mut self, scale: (f32, f32)) -> &mut Self {
+        self.animator.scale = Some(scale);
+        self
+    }
+
+    pub fn opacity(&mut self, opacity: f32) -> &mut Self {
+        self.animator.opacity = Some(opacity);
+        self
+    }
+
+    pub fn color(&mut self, color: impl Into<Color>) -> &mut Self {
+        self.animator.color = Some(color.into());
+        self
+    }
+
+    pub fn build(&mut self) -> TextAnimator {
+        std::mem::take(&mut self.animator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RangeSelector, SelectorShape, TextAnimator, TextUnit};
+    use crate::{
+        api::animation::{ease::LINEAR, AnimatedPropertyBuilder, KeyframeTiming::Abs},
+        unanimated,
+    };
+
+    fn selector(shape: SelectorShape, start: f32, end: f32) -> RangeSelector {
+        RangeSelector {
+            shape,
+            start: unanimated!(start),
+            end: unanimated!(end),
+            ..Default::default()
+        }
+    }
+
+    /// Weights of `count` units at `frame`, rounded to make comparisons readable
+    fn weights(selector: &RangeSelector, frame: u64, count: usize) -> Vec<f32> {
+        let selector = selector.evaluate(frame);
+        (0..count).map(|index| (selector.weight(index, count) * 1000.0).round() / 1000.0).collect()
+    }
+
+    #[test]
+    fn shapes() {
+        assert_eq!(weights(&selector(SelectorShape::Square, 0.0, 0.5), 0, 4), [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(weights(&selector(SelectorShape::Square, 0.25, 1.0), 0, 4), [0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(weights(&selector(SelectorShape::RampUp, 0.25, 0.75), 0, 4), [0.0, 0.25, 0.75, 1.0]);
+        assert_eq!(weights(&selector(SelectorShape::RampDown, 0.25, 0.75), 0, 4), [1.0, 0.75, 0.25, 0.0]);
+        assert_eq!(weights(&selector(SelectorShape::Triangle, 0.0, 1.0), 0, 4), [0.25, 0.75, 0.75, 0.25]);
+        assert_eq!(weights(&selector(SelectorShape::Round, 0.0, 1.0), 0, 4), [0.661, 0.968, 0.968, 0.661]);
+        assert_eq!(weights(&selector(SelectorShape::Smooth, 0.0, 1.0), 0, 4), [0.146, 0.854, 0.854, 0.146]);
+
+        // Peaks only cover the range
+        assert_eq!(weights(&selector(SelectorShape::Triangle, 0.0, 0.5), 0, 4), [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(weights(&selector(SelectorShape::Smooth, 0.5, 1.0), 0, 4), [0.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn offset_and_easing_apply_to_the_range() {
+        let mut selector = selector(SelectorShape::RampUp, 0.0, 0.5);
+        selector.offset = unanimated!(0.25);
+        assert_eq!(weights(&selector, 0, 4), [0.0, 0.25, 0.75, 1.0]);
+
+        selector.easing = |t| t * t;
+        assert_eq!(weights(&selector, 0, 4), [0.0, 0.063, 0.563, 1.0]);
+    }
+
+    #[test]
+    fn empty_ranges() {
+        for (start, end) in [(0.5, 0.5), (0.75, 0.25)] {
+            for shape in [SelectorShape::Square, SelectorShape::Triangle, SelectorShape::Round, SelectorShape::Smooth] {
+                assert_eq!(weights(&selector(shape, start, end), 0, 4), [0.0; 4], "{:?} {}..{}", shape, start, end);
+            }
+        }
+
+        assert_eq!(weights(&selector(SelectorShape::RampUp, 0.5, 0.5), 0, 4), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(weights(&selector(SelectorShape::RampDown, 0.5, 0.5), 0, 4), [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(weights(&selector(SelectorShape::Square, 0.0, 1.0), 0, 0), [0.0; 0]);
+    }
+
+    #[test]
+    fn stagger_blends_edges() {
+        let mut selector = selector(SelectorShape::Square, 0.5, 1.0);
+        selector.stagger = 2.0;
+        assert_eq!(weights(&selector, 0, 4), [0.0, 0.25, 0.75, 1.0]);
+
+        // The whole range still selects every unit fully, and nothing at its ends
+        for (start, end, weight) in [(0.0, 1.0, 1.0), (0.0, 0.0, 0.0), (1.0, 1.0, 0.0)] {
+            selector.start = unanimated!(start);
+            selector.end = unanimated!(end);
+            assert_eq!(weights(&selector, 0, 4), [weight; 4], "{}..{}", start, end);
+        }
+    }
+
+    #[test]
+    fn typewriter() {
+        let animator = TextAnimator::builder()
+            .start(AnimatedPropertyBuilder::new(60.0).keyframe(Abs(0.0), LINEAR, 0.0).keyframe(Abs(2.0), LINEAR, 1.0).build())
+            .opacity(0.0)
+            .build();
+
+        // Selected characters are hidden, and they're typed one after another
+        assert_eq!(weights(&animator.selector, 0, 4), [1.0; 4]);
+        assert_eq!(weights(&animator.selector, 30, 4), [0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(weights(&animator.selector, 60, 4), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(weights(&animator.selector, 120, 4), [0.0; 4]);
+    }
+
+    #[test]
+    fn cascading_word_fade() {
+        let animator = TextAnimator::builder()
+            .unit(TextUnit::Words)
+            .stagger(2.0)
+            .start(AnimatedPropertyBuilder::new(60.0).keyframe(Abs(0.0), LINEAR, 0.0).keyframe(Abs(1.5), LINEAR, 1.0).build())
+            .offset((0.0, -30.0))
+            .opacity(0.0)
+            .build();
+
+        assert_eq!(animator.selector.unit, TextUnit::Words);
+        assert_eq!(weights(&animator.selector, 0, 3), [1.0; 3]);
+        // Neighbouring words fade at the same time
+        assert_eq!(weights(&animator.selector, 45, 3), [0.0, 0.5, 1.0]);
+        assert_eq!(weights(&animator.selector, 90, 3), [0.0; 3]);
+    }
+
+    #[test]
+    fn wave() {
+        let animator = TextAnimator::builder()
+            .shape(SelectorShape::Smooth)
+            .range(unanimated!(0.0), unanimated!(0.3))
+            .range_offset(AnimatedPropertyBuilder::new(60.0).keyframe(Abs(0.0), LINEAR, -0.3).keyframe(Abs(1.0), LINEAR, 1.0).build())
+            .offset((0.0, 20.0))
+            .build();
+
+        assert_eq!(weights(&animator.selector, 0, 10), [0.0; 10]);
+        assert_eq!(weights(&animator.selector, 30, 10), [0.0, 0.0, 0.0, 0.0, 0.75, 0.75, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(weights(&animator.selector, 60, 10), [0.0; 10]);
+    }
+}
@@ -1,15 +1,54 @@
-use core::time::Duration;
+use core::{ops::Range, time::Duration};
+#[cfg(not(feature = "preview"))]
+use std::sync::mpsc::{Receiver, SyncSender};
 
-use crate::{render::{Renderer, Time}, io::Export, api::color::Color, rgb8, clip::Clip};
+use crate::{render::{Renderer, Time}, io::{ChunkedExport, Export, image_sequence::ImageSequenceExporter}, api::{color::Color, audio::AudioBuffer}, rgb8, clip::Clip, error::{Error, Result}};
 
 use log::info;
 
+/// How transparent pixels of exported frames are stored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Colors are stored as they are, what PNG, ProRes 4444 and VP9 expect
+    #[default]
+    Straight,
+    /// Colors are multiplied with their alpha, which some compositors prefer
+    Premultiplied,
+}
+
+/// Which adapter renders the video. [`Renderer::adapters`](crate::render::Renderer::adapters)
+/// lists the adapters of a machine
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterSelection {
+    /// The fastest GPU, or a software adapter when there is no GPU
+    #[default]
+    HighPerformance,
+    /// An integrated GPU when there is one
+    LowPower,
+    /// A software rasterizer like lavapipe or llvmpipe, for machines without a
+    /// GPU. Slow, but renders the same everywhere
+    Software,
+    /// The adapter at this index of [`Renderer::adapters`](crate::render::Renderer::adapters)
+    Index(usize),
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct VideoSettings {
     pub fps: f64,
     pub resolution: (u32, u32),
     pub duration: Duration,
+    /// Can be (partly) transparent, for overlays that are composited in other
+    /// editors. Export with a format that keeps alpha in that case
     pub background_color: Color,
+    /// How the alpha of exported frames is stored, only matters with a transparent background
+    pub alpha_mode: AlphaMode,
+    /// Sample rate of the mixed audio
+    pub audio_sample_rate: u32,
+    /// Channels of the mixed audio, `2` for stereo
+    pub audio_channels: u16,
+    /// Graphics APIs to look for adapters on, e.g. `wgpu::Backends::VULKAN`
+    pub backends: wgpu::Backends,
+    pub adapter: AdapterSelection,
 }
 
 impl Default for VideoSettings {
@@ -19,6 +58,35 @@ impl Default for VideoSettings {
             resolution: (1920, 1080),
             duration: Duration::from_secs(30),
             background_color: rgb8!(0x17, 0x17, 0x17),
+            alpha_mode: AlphaMode::Straight,
+            audio_sample_rate: 48000,
+            audio_channels: 2,
+            backends: wgpu::Backends::all(),
+            adapter: AdapterSelection::HighPerformance,
+        }
+    }
+}
+
+/// How far an export got, passed to the callback set with [`Video::on_progress`]
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// Frames rendered and encoded so far
+    pub frames_done: u64,
+    pub total_frames: u64,
+    /// Average frames per second since the export started
+    pub fps: f64,
+    pub elapsed: Duration,
+    /// Estimated time until the export is done, based on `fps`
+    pub eta: Duration,
+}
+
+impl Progress {
+    /// Between 0 and 1
+    pub fn fraction(&self) -> f64 {
+        if self.total_frames == 0 {
+            1.0
+        } else {
+            self.frames_done as f64 / self.total_frames as f64
         }
     }
 }
@@ -28,11 +96,21 @@ pub struct Video<'a> {
     #[cfg(feature = "preview")] window: winit::window::Window,
     renderer: Renderer,
     root: Clip<'a>,
+    progress: Option<Box<dyn FnMut(Progress) + 'a>>,
+    /// Encode frames on another thread while the next ones render
+    pipelined: bool,
     pub settings: VideoSettings,
 }
 
 impl<'a> Video<'a> {
+    /// Panics when no adapter can render the video, see [`Video::try_new`]
     pub fn new(settings: VideoSettings) -> Self {
+        Self::try_new(settings).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Fails when no adapter matches [`VideoSettings::adapter`], like on a
+    /// machine without a GPU or software rasterizer
+    pub fn try_new(settings: VideoSettings) -> Result<Self> {
         #[cfg(feature = "preview")]
         let (event_loop, window, renderer) = {
             let event_loop = winit::event_loop::EventLoop::new();
@@ -41,35 +119,166 @@ impl<'a> Video<'a> {
                 .with_resizable(false)
                 .build(&event_loop)
                 .unwrap();
-            let renderer = Renderer::new(settings, &window);
+            let renderer = Renderer::new(settings, &window)?;
 
             (event_loop, window, renderer)
         };
 
-        Self {
+        Ok(Self {
             #[cfg(feature = "preview")] event_loop,
             #[cfg(feature = "preview")] window,
             #[cfg(feature = "preview")] renderer,
-            #[cfg(not(feature = "preview"))] renderer: Renderer::new(settings),
+            #[cfg(not(feature = "preview"))] renderer: Renderer::new(settings)?,
             root: Clip::empty(settings.duration, settings.fps),
+            progress: None,
+            pipelined: true,
             settings,
-        }
+        })
     }
 
     pub fn root(&mut self) -> &mut Clip<'a> {
         &mut self.root
     }
 
+    /// Calls `callback` after every exported frame. To show progress on
+    /// another thread, send the [`Progress`] through a channel from here
+    pub fn on_progress(&mut self, callback: impl FnMut(Progress) + 'a) -> &mut Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Turns off rendering the next frames while earlier ones are read back and
+    /// encoded, which is on by default. Every frame is then rendered, read and
+    /// encoded one after another on the calling thread, to compare the speed
+    pub fn pipelined(&mut self, pipelined: bool) -> &mut Self {
+        self.pipelined = pipelined;
+        self
+    }
+
+    /// Mixes the audio of all clips, `None` when no clip has audio
+    pub fn mixdown(&self) -> Option<AudioBuffer> {
+        self.root.has_audio().then(|| self.mix())
+    }
+
+    /// Writes the mixed audio of all clips to a WAV file, to review the mix
+    /// without rendering the video
+    pub fn export_wav(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        self.mix().write_wav(path)
+    }
+
+    fn mix(&self) -> AudioBuffer {
+        let duration = self.settings.duration.as_secs_f64();
+        let mut output = AudioBuffer::silent(self.settings.audio_sample_rate, self.settings.audio_channels, duration);
+
+        // Sidechain audio is mixed first, so the level of everything ducked under it is known
+        self.root.mix_audio(&mut output, &Some, duration, &|audio| audio.sidechain, None);
+        let sidechain = output.clone();
+        self.root.mix_audio(&mut output, &Some, duration, &|audio| !audio.sidechain, Some(&sidechain));
+
+        output
+    }
+
+    /// Renders the video to `exporter`, or opens a preview window when the
+    /// `preview` feature is enabled. Returns the first error any effect or the
+    /// exporter encountered, the exporter removes its partial output in that case.
+    pub fn render(self, exporter: impl Export) -> Result<()> where Self: 'static {
+        use crate::clip::IntoFrame;
+
+        let total_frames = self.settings.duration.into_frame(self.settings.fps);
+        self.render_range(0..total_frames, exporter)
+    }
+
+    /// Renders only `frames` of the video to `exporter`, like [`Video::render`].
+    /// The exporter gets a video as long as the range, with the audio of that part.
+    /// Returns [`Error::FrameRange`] when `frames` is empty or ends after the video
     #[allow(unused_variables)]
-    pub fn render(mut self, exporter: impl Export) where Self: 'static {
-        self.renderer.register_effects(self.root.get_registration_packets());
+    pub fn render_range(mut self, frames: Range<u64>, exporter: impl Export) -> Result<()> where Self: 'static {
+        check_range(&frames, self.settings)?;
+        self.renderer.register_effects(self.root.get_registration_packets())?;
 
         #[cfg(feature = "preview")] self.preview();
-        #[cfg(not(feature = "preview"))] self.export(exporter);
+        #[cfg(not(feature = "preview"))] self.export(frames, exporter)
+    }
+
+    /// Renders a single frame to an image, like a thumbnail. The format follows
+    /// the extension of `path`, see [`SequenceFormat::from_path`](crate::io::image_sequence::SequenceFormat::from_path)
+    pub fn render_still(self, frame: u64, path: impl AsRef<std::path::Path>) -> Result<()> where Self: 'static {
+        // Without a frame number pattern the exporter writes to the path as it is
+        let exporter = ImageSequenceExporter::new(path.as_ref().to_string_lossy()).threads(1);
+        self.render_range(frame..frame + 1, exporter)
+    }
+
+    /// Renders the video in `workers` chunks at the same time, each on its own
+    /// thread with its own renderer. Every chunk is written as a segment by
+    /// `exporter`, which joins them in the end.
+    ///
+    /// `build` creates the scene. It is called once for every worker and once
+    /// for the settings and audio of the video, so it has to build the same
+    /// scene every time. Opens a preview window when the `preview` feature is enabled
+    ///
+    /// ```ignore
+    /// Video::render_parallel(build_scene, 8, FFmpegExporter::new("output.mp4", "mp4", "libx264", Some("aac".to_string())))
+    /// ```
+    #[allow(unused_variables)]
+    pub fn render_parallel<F>(build: F, workers: usize, exporter: impl ChunkedExport) -> Result<()>
+    where
+        F: Fn() -> Video<'static> + Sync,
+    {
+        #[cfg(feature = "preview")]
+        {
+            let mut video = build();
+            video.renderer.register_effects(video.root.get_registration_packets())?;
+            video.preview();
+        }
+
+        #[cfg(not(feature = "preview"))]
+        {
+            use crate::clip::IntoFrame;
+
+            let video = build();
+            let (settings, audio) = (video.settings, video.mixdown());
+            drop(video);
+
+            let total_frames = settings.duration.into_frame(settings.fps);
+            let workers = (workers.max(1) as u64).min(total_frames.max(1));
+            let segments = (0..workers)
+                .map(|worker| total_frames * worker / workers..total_frames * (worker + 1) / workers)
+                .collect::<Vec<_>>();
+
+            info!("Rendering {} segments at once...", segments.len());
+            let start_time = std::time::Instant::now();
+
+            let results = std::thread::scope(|scope| {
+                let workers = segments
+                    .iter()
+                    .enumerate()
+                    .map(|(index, frames)| {
+                        let (build, frames, segment) = (&build, frames.clone(), exporter.segment(index, frames.clone()));
+                        scope.spawn(move || build().render_range(frames, segment))
+                    })
+                    .collect::<Vec<_>>();
+
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                    .collect::<Vec<_>>()
+            });
+
+            if let Some(err) = results.into_iter().find_map(|result| result.err()) {
+                exporter.abort_segments(&segments);
+                return Err(err);
+            }
+
+            info!("Joining segments...");
+            exporter.concat(settings, audio, &segments)?;
+
+            info!("Done! Rendering took {:0.05}s", start_time.elapsed().as_secs_f32());
+            Ok(())
+        }
     }
 
     #[cfg(feature = "preview")]
-    fn preview(self) where Self: 'static {
+    fn preview(self) -> ! where Self: 'static {
         let Self {
             settings,
             window,
@@ -89,7 +298,10 @@ impl<'a> Video<'a> {
                 _ => (),
             },
             winit::event::Event::RedrawRequested(window_id) if window_id == window.id() => {
-                render_frame(frame, &mut renderer, &mut root);
+                if let Err(err) = render_frame(frame, &mut renderer, &mut root) {
+                    log::error!("{}", err);
+                    *control_flow = winit::event_loop::ControlFlow::Exit;
+                }
                 frame = (frame + 1) % (settings.duration.as_secs_f64() * settings.fps) as u64;
             },
             winit::event::Event::MainEventsCleared => {
@@ -100,28 +312,166 @@ impl<'a> Video<'a> {
     }
 
     #[cfg(not(feature = "preview"))]
-    fn export(&mut self, mut exporter: impl Export) {
-        use crate::clip::IntoFrame;
-
+    fn export(&mut self, frames: Range<u64>, mut exporter: impl Export) -> Result<()> {
         info!("Starting render...");
         let start_time = std::time::Instant::now();
 
-        exporter.begin(self.settings);
-
-        for frame in 0..self.settings.duration.into_frame(self.settings.fps) {
-            info!("Encoding frame...");
-            exporter.push_frame(true, &render_frame(frame, &mut self.renderer, &mut self.root).unwrap()[..]);
+        if let Err(err) = self.encode(frames, &mut exporter, start_time) {
+            exporter.abort();
+            return Err(err);
         }
 
         info!("Finalizing encoding...");
 
-        exporter.end();
+        exporter.end()?;
 
         info!("Done! Rendering took {:0.05}s", (std::time::Instant::now() - start_time).as_secs_f32());
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "preview"))]
+    fn encode(&mut self, frames: Range<u64>, exporter: &mut impl Export, start_time: std::time::Instant) -> Result<()> {
+        begin_range(exporter, self.settings, self.mixdown(), &frames)?;
+
+        let total_frames = frames.end.saturating_sub(frames.start);
+        let skipped = (0..total_frames).map(|index| exporter.skip_frame(index)).collect::<Vec<_>>();
+
+        self.renderer.set_pipelined(self.pipelined);
+        if !self.pipelined {
+            let mut frame = Vec::new();
+            return self.render_frames(frames, &skipped, start_time, |renderer| {
+                if !renderer.read_frame(&mut frame)? {
+                    return Ok(false);
+                }
+                exporter.push_frame(true, &frame)?;
+                Ok(true)
+            });
+        }
+
+        // Frames are encoded on another thread while the next ones render,
+        // their buffers are sent back to be reused
+        let (frame_sender, frame_receiver) = std::sync::mpsc::sync_channel::<Vec<u8>>(1);
+        let (buffer_sender, buffer_receiver) = std::sync::mpsc::channel::<Vec<u8>>();
+
+        std::thread::scope(|scope| {
+            let encoder = scope.spawn(move || -> Result<()> {
+                for frame in frame_receiver {
+                    exporter.push_frame(true, &frame)?;
+                    // Rendering might be done already
+                    let _ = buffer_sender.send(frame);
+                }
+                Ok(())
+            });
+
+            let rendered = self.render_frames(frames, &skipped, start_time, |renderer| send_frame(renderer, &frame_sender, &buffer_receiver));
+            drop(frame_sender);
+            let encoded = encoder.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+            // Rendering stops once the encoder failed, so its error comes first
+            encoded.and(rendered)
+        })
+    }
+
+    #[cfg(not(feature = "preview"))]
+    /// Renders `frames` and passes each finished one to `deliver`, which
+    /// returns `false` once no frame is in flight or the encoder stopped
+    fn render_frames(
+        &mut self,
+        frames: Range<u64>,
+        skipped: &[bool],
+        start_time: std::time::Instant,
+        mut deliver: impl FnMut(&mut Renderer) -> Result<bool>,
+    ) -> Result<()> {
+        let total_frames = skipped.len() as u64;
+        let mut frames_done = 0;
+        // Skipped frames don't count towards the speed
+        let mut rendered = 0;
+
+        for (index, frame) in frames.enumerate() {
+            if skipped[index] {
+                info!("Skipping frame {}...", frame);
+                frames_done += 1;
+                self.report_progress(frames_done, rendered, total_frames, start_time);
+                continue;
+            }
+
+            if !self.renderer.can_render() {
+                if !deliver(&mut self.renderer)? {
+                    return Ok(());
+                }
+                frames_done += 1;
+                rendered += 1;
+                self.report_progress(frames_done, rendered, total_frames, start_time);
+            }
+
+            info!("Encoding frame...");
+            render_frame(frame, &mut self.renderer, &mut self.root)?;
+        }
+
+        while deliver(&mut self.renderer)? {
+            frames_done += 1;
+            rendered += 1;
+            self.report_progress(frames_done, rendered, total_frames, start_time);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "preview"))]
+    fn report_progress(&mut self, frames_done: u64, rendered: u64, total_frames: u64, start_time: std::time::Instant) {
+        if let Some(callback) = self.progress.as_mut() {
+            let elapsed = start_time.elapsed();
+            let fps = rendered as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+            callback(Progress {
+                frames_done,
+                total_frames,
+                fps,
+                elapsed,
+                eta: Duration::from_secs_f64(match rendered {
+                    0 => 0.0,
+                    _ => (total_frames - frames_done) as f64 / fps,
+                }),
+            });
+        }
+    }
+}
+
+/// Fails with [`Error::FrameRange`] when `frames` is empty or ends after the video
+fn check_range(frames: &Range<u64>, settings: VideoSettings) -> Result<()> {
+    use crate::clip::IntoFrame;
+
+    let total = settings.duration.into_frame(settings.fps);
+    if frames.start >= frames.end || frames.end > total {
+        return Err(Error::FrameRange { frames: frames.clone(), total });
     }
+    Ok(())
 }
 
-fn render_frame(frame: u64, renderer: &mut Renderer, clip: &mut Clip<'_>) -> Option<Vec<u8>> {
+/// Begins `exporter` with a video as long as `frames`, and the part of
+/// `audio` (of the whole video) that plays during them
+#[cfg(not(feature = "preview"))]
+fn begin_range(exporter: &mut impl Export, settings: VideoSettings, audio: Option<AudioBuffer>, frames: &Range<u64>) -> Result<()> {
+    let (start, end) = (frames.start as f64 / settings.fps, frames.end.max(frames.start) as f64 / settings.fps);
+    let settings = VideoSettings {
+        duration: Duration::from_secs_f64(end - start),
+        ..settings
+    };
+    exporter.begin(settings, audio.map(|audio| audio.slice(start, end)))
+}
+
+/// Reads the oldest rendered frame and hands it to the encoder thread, `false`
+/// when no frame is in flight or the encoder stopped
+#[cfg(not(feature = "preview"))]
+fn send_frame(renderer: &mut Renderer, sender: &SyncSender<Vec<u8>>, buffers: &Receiver<Vec<u8>>) -> Result<bool> {
+    let mut frame = buffers.try_recv().unwrap_or_default();
+    if !renderer.read_frame(&mut frame)? {
+        return Ok(false);
+    }
+    Ok(sender.send(frame).is_ok())
+}
+
+fn render_frame(frame: u64, renderer: &mut Renderer, clip: &mut Clip<'_>) -> Result<()> {
     let time = frame as f64 / renderer.fps();
     let progress = time / renderer.duration().as_secs_f64();
 
@@ -142,4 +492,166 @@ fn render_frame(frame: u64, renderer: &mut Renderer, clip: &mut Clip<'_>) -> Opt
         renderer.last_frame(),
         renderer.screen_matrix,
     ))
-}
\ No newline at end of file
+}
+#[cfg(all(test, not(feature = "preview")))]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        api::{
+            animation::{ease, AnimatedPropertyBuilder, KeyframeTiming::Abs},
+            audio::{Audio, AudioBuffer, AudioSource},
+            rect::Rect,
+        },
+        error::{Error, Result},
+        io::Export,
+        rgb8, unanimated,
+    };
+
+    use super::{begin_range, check_range, AdapterSelection, Range, Video, VideoSettings};
+
+    /// What an exporter got, kept after the exporter moved into the video
+    #[derive(Default)]
+    struct Recording {
+        settings: Option<VideoSettings>,
+        audio: Option<AudioBuffer>,
+        /// First byte of every frame
+        frames: Vec<u8>,
+        ended: bool,
+    }
+
+    struct Recorder(Arc<Mutex<Recording>>);
+
+    impl Export for Recorder {
+        fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()> {
+            let mut recording = self.0.lock().unwrap();
+            recording.settings = Some(settings);
+            recording.audio = audio;
+            Ok(())
+        }
+
+        fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+            self.0.lock().unwrap().frames.push(frame[0]);
+            Ok(())
+        }
+
+        fn end(self) -> Result<()> {
+            self.0.lock().unwrap().ended = true;
+            Ok(())
+        }
+    }
+
+    /// 10 frames at 10 fps, with mono audio at 100 Hz so every frame has 10 samples
+    fn settings() -> VideoSettings {
+        VideoSettings {
+            fps: 10.0,
+            resolution: (8, 8),
+            duration: Duration::from_secs(1),
+            audio_sample_rate: 100,
+            audio_channels: 1,
+            adapter: AdapterSelection::Software,
+            ..Default::default()
+        }
+    }
+
+    /// Sample `i` is `i / 100`, so a slice shows where it was taken from
+    fn ramp() -> AudioBuffer {
+        AudioBuffer::new(100, 1, (0..100).map(|i| i as f32 / 100.0).collect())
+    }
+
+    #[test]
+    fn empty_ranges_are_rejected() {
+        // Ranges that end before they start are empty as well
+        for frames in [3..3, Range { start: 5, end: 2 }] {
+            let err = check_range(&frames, settings()).unwrap_err();
+            assert!(matches!(err, Error::FrameRange { frames: ref range, total: 10 } if *range == frames), "{}", err);
+        }
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_rejected() {
+        assert!(matches!(check_range(&(5..11), settings()), Err(Error::FrameRange { total: 10, .. })));
+        assert!(matches!(check_range(&(10..11), settings()), Err(Error::FrameRange { total: 10, .. })));
+        check_range(&(0..10), settings()).unwrap();
+        check_range(&(9..10), settings()).unwrap();
+    }
+
+    #[test]
+    fn ranges_begin_with_their_part_of_the_audio() {
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        begin_range(&mut Recorder(recording.clone()), settings(), Some(ramp()), &(3..7)).unwrap();
+
+        let recording = recording.lock().unwrap();
+        let settings = recording.settings.unwrap();
+        assert!((settings.duration.as_secs_f64() - 0.4).abs() < 1e-9, "{:?}", settings.duration);
+        assert_eq!((settings.fps, settings.resolution), (10.0, (8, 8)));
+        assert_eq!(recording.audio.as_ref().unwrap().samples, ramp().samples[30..70]);
+    }
+
+    #[test]
+    fn ranges_without_audio_begin_without_audio() {
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        begin_range(&mut Recorder(recording.clone()), settings(), None, &(0..10)).unwrap();
+        assert!(recording.lock().unwrap().audio.is_none());
+    }
+
+    /// A rect over the whole frame that gets brighter every frame, with the
+    /// ramp playing. `None` on machines without an adapter
+    fn video() -> Option<Video<'static>> {
+        let mut video = match Video::try_new(settings()) {
+            Ok(video) => video,
+            Err(err) => {
+                eprintln!("skipping, no adapter to render with: {}", err);
+                return None;
+            }
+        };
+
+        let root = video.root();
+        root.effect(Rect {
+            position: unanimated!((0.0, 0.0)),
+            size: unanimated!((16.0, 16.0)),
+            color: AnimatedPropertyBuilder::new(10.0)
+                .keyframe(Abs(0), ease::LINEAR, rgb8!(0x00, 0x00, 0x00))
+                .keyframe(Abs(10), ease::LINEAR, rgb8!(0xff, 0xff, 0xff))
+                .build(),
+        });
+        root.audio(Audio::new(AudioSource::from_buffer(ramp())));
+        Some(video)
+    }
+
+    fn render(frames: Range<u64>) -> Option<(Result<()>, Recording)> {
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        let result = video()?.render_range(frames, Recorder(recording.clone()));
+        let recording = Arc::into_inner(recording).unwrap().into_inner().unwrap();
+        Some((result, recording))
+    }
+
+    #[test]
+    fn render_range_exports_only_the_range() {
+        let Some((result, full)) = render(0..10) else { return };
+        result.unwrap();
+        assert_eq!(full.frames.len(), 10);
+        assert!(full.frames.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", full.frames);
+
+        let (result, part) = render(3..7).unwrap();
+        result.unwrap();
+        assert!(part.ended);
+        assert_eq!(part.frames, full.frames[3..7]);
+        assert!((part.settings.unwrap().duration.as_secs_f64() - 0.4).abs() < 1e-9);
+
+        let (full_audio, part_audio) = (full.audio.unwrap(), part.audio.unwrap());
+        assert_eq!(part_audio.samples, full_audio.samples[30..70]);
+    }
+
+    #[test]
+    fn render_range_rejects_invalid_ranges_before_exporting() {
+        for frames in [4..4, 5..11] {
+            let Some((result, recording)) = render(frames) else { return };
+            assert!(matches!(result, Err(Error::FrameRange { total: 10, .. })));
+            assert!(recording.settings.is_none() && recording.frames.is_empty() && !recording.ended);
+        }
+    }
+}
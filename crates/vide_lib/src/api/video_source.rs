@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use crate::{register_effect, effect::{Effect, EffectBackend}, render::Time, error::{Error, Result}, io::{Import, VideoDecoder}};
+
+use super::{
+    animation::AnimatedProperty,
+    color::Color,
+    image::{fit_image, image_mesh, ImageFilter, ImageFit, ImageInstance},
+    instanced_mesh::InstancedMesh,
+    texture::{create_sampler, create_texture, texture_bind_group, texture_bind_group_layout, write_texture},
+};
+
+register_effect!(VideoSourceBackend, VideoSource);
+
+static FOOTAGE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An opened video file, cheap to clone. Clones share the decoder, so
+/// playing the same footage in many places at once means seeking between them
+#[derive(Clone)]
+pub struct Footage {
+    id: usize,
+    decoder: Arc<Mutex<Box<dyn VideoDecoder>>>,
+    resolution: (u32, u32),
+}
+
+impl Footage {
+    /// Opens a video file with an importer like `vide_ffmpeg::FFmpegImporter`
+    pub fn open<I: Import>(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(I::open(path)?))
+    }
+
+    pub fn new(decoder: impl VideoDecoder + 'static) -> Self {
+        Self {
+            id: FOOTAGE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            resolution: decoder.resolution(),
+            decoder: Arc::new(Mutex::new(Box::new(decoder))),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.resolution.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.resolution.1
+    }
+}
+
+impl std::fmt::Debug for Footage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Footage")
+            .field("id", &self.id)
+            .field("resolution", &self.resolution)
+            .finish()
+    }
+}
+
+/// Plays [`Footage`] following the time of the clip it is in, frames are
+/// picked by timestamp so sources with any frame rate line up with the video
+pub struct VideoSource {
+    pub footage: Footage,
+    /// Time (in seconds) into the footage shown at the start of the clip
+    pub start: f64,
+    pub position: AnimatedProperty<(f32, f32)>,
+    /// Area the footage is fitted into, see [`VideoSource::fit`]
+    pub size: AnimatedProperty<(f32, f32)>,
+    /// Counter-clockwise rotation in degrees
+    pub rotation: AnimatedProperty<f32>,
+    /// Multiplied with every pixel, [`Color::WHITE`] leaves the footage unchanged
+    pub tint: AnimatedProperty<Color>,
+    pub opacity: AnimatedProperty<f32>,
+    pub fit: ImageFit,
+    /// Part of the footage to show as `(x, y, width, height)` in pixels, `None` shows all of it
+    pub crop: Option<(f32, f32, f32, f32)>,
+    pub filter: ImageFilter,
+}
+
+/// Texture showing one instance of a footage, a footage used twice in the same frame gets two
+struct FrameSlot {
+    texture: wgpu::Texture,
+    bind_groups: HashMap<ImageFilter, wgpu::BindGroup>,
+    /// Timestamp of the frame in the texture
+    timestamp: Option<f64>,
+}
+
+struct PendingFrame {
+    /// Footage id and how many times the footage was pushed before in this frame
+    slot: (usize, usize),
+    resolution: (u32, u32),
+    /// New frame data, `None` when the texture already shows the right frame
+    upload: Option<(f64, Vec<u8>)>,
+    filter: ImageFilter,
+    instance: ImageInstance,
+}
+
+pub struct VideoSourceBackend {
+    mesh: InstancedMesh<ImageInstance>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    samplers: HashMap<ImageFilter, wgpu::Sampler>,
+    slots: HashMap<(usize, usize), FrameSlot>,
+    pending: Vec<PendingFrame>,
+    /// First error hit while decoding, returned from the next render
+    error: Option<Error>,
+}
+
+impl EffectBackend for VideoSourceBackend {
+    type Instance = VideoSource;
+
+    fn push(&mut self, instance: &Self::Instance, time: Time) {
+        let frame = time.clip_frame;
+        let footage = &instance.footage;
+        let slot = (footage.id, self.pending.iter().filter(|pending| pending.slot.0 == footage.id).count());
+
+        let upload = {
+            let mut decoder = footage.decoder.lock().unwrap();
+            match decoder.frame_at(instance.start + time.clip_time) {
+                Ok(Some(decoded)) => {
+                    let shown = self.slots.get(&slot).and_then(|slot| slot.timestamp);
+                    (shown != Some(decoded.timestamp)).then(|| (decoded.timestamp, decoded.data.to_vec()))
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    self.error.get_or_insert(err);
+                    return;
+                }
+            }
+        };
+
+        let (size, uv_rect) = fit_image(footage.resolution, instance.crop, instance.fit, instance.size.evaluate(frame));
+        self.pending.push(PendingFrame {
+            slot,
+            resolution: footage.resolution,
+            upload,
+            filter: instance.filter,
+            instance: ImageInstance::new(
+                instance.position.evaluate(frame),
+                size,
+                instance.rotation.evaluate(frame),
+                uv_rect,
+                instance.tint.evaluate(frame),
+                instance.opacity.evaluate(frame),
+            ),
+        });
+    }
+
+    fn render<'a>(&'a mut self, pass: MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        for frame in pending.iter() {
+            let (width, height) = frame.resolution;
+            let slot = self.slots.entry(frame.slot).or_insert_with(|| FrameSlot {
+                texture: create_texture(device, width, height, wgpu::TextureFormat::Rgba8UnormSrgb, "Video Source Texture"),
+                bind_groups: HashMap::new(),
+                timestamp: None,
+            });
+
+            if let Some((timestamp, data)) = frame.upload.as_ref() {
+                write_texture(queue, &slot.texture, width, height, 4, data);
+                slot.timestamp = Some(*timestamp);
+            }
+
+            let sampler = &self.samplers[&frame.filter];
+            let layout = &self.bind_group_layout;
+            let texture = &slot.texture;
+            slot.bind_groups
+                .entry(frame.filter)
+                .or_insert_with(|| texture_bind_group(device, layout, texture, sampler, "Video Source Bind Group"));
+        }
+
+        let batches = pending
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| (i as u32..i as u32 + 1, vec![&self.slots[&frame.slot].bind_groups[&frame.filter]]))
+            .collect::<Vec<_>>();
+
+        let instances = pending.iter().map(|frame| frame.instance).collect();
+        self.mesh.render_batches(pass, device, queue, instances, &batches);
+        Ok(())
+    }
+}
+
+impl Effect for VideoSourceBackend {
+    fn new(renderer: &mut crate::render::Renderer) -> Result<Self> {
+        let device = renderer.wgpu_device();
+        let bind_group_layout = texture_bind_group_layout(device, "Video Source Bind Group Layout");
+        let samplers = [ImageFilter::Linear, ImageFilter::Nearest]
+            .into_iter()
+            .map(|filter| (filter, create_sampler(device, filter.into(), "Video Source Sampler")))
+            .collect();
+
+        Ok(Self {
+            mesh: image_mesh(renderer, &bind_group_layout)?,
+            bind_group_layout,
+            samplers,
+            slots: HashMap::new(),
+            pending: Vec::new(),
+            error: None,
+        })
+    }
+}
@@ -0,0 +1,92 @@
+//! Watches the files shaders are loaded from in preview mode, so they are only
+//! read again after they changed
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+};
+
+use log::error;
+use notify::{RecursiveMode, Watcher};
+
+/// Files are watched through their directory, editors often save by
+/// replacing the file, which ends watches on the file itself
+struct FileWatcher {
+    watcher: notify::RecommendedWatcher,
+    directories: HashSet<PathBuf>,
+    /// Flags of everything loaded from a file, set when the file changes
+    files: Arc<Mutex<HashMap<PathBuf, Vec<Weak<AtomicBool>>>>>,
+}
+
+impl FileWatcher {
+    fn new() -> notify::Result<Self> {
+        let files: Arc<Mutex<HashMap<PathBuf, Vec<Weak<AtomicBool>>>>> = Default::default();
+        let changed_files = files.clone();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => event,
+                Ok(_) => return,
+                Err(err) => {
+                    error!("Shader file watcher failed: {}", err);
+                    return;
+                }
+            };
+
+            let mut files = changed_files.lock().unwrap();
+            for path in event.paths {
+                if let Some(flags) = files.get_mut(&path) {
+                    // Flags of dropped shaders are removed along the way
+                    flags.retain(|flag| flag.upgrade().map(|flag| flag.store(true, Ordering::Relaxed)).is_some());
+                }
+            }
+        })?;
+
+        Ok(Self {
+            watcher,
+            directories: HashSet::new(),
+            files,
+        })
+    }
+
+    fn watch(&mut self, path: &Path) -> notify::Result<Arc<AtomicBool>> {
+        // Events name files by their canonical path
+        let path = path.canonicalize().map_err(notify::Error::io)?;
+        if let Some(directory) = path.parent() {
+            if !self.directories.contains(directory) {
+                self.watcher.watch(directory, RecursiveMode::NonRecursive)?;
+                self.directories.insert(directory.to_path_buf());
+            }
+        }
+
+        let flag = Arc::new(AtomicBool::new(false));
+        self.files.lock().unwrap().entry(path).or_default().push(Arc::downgrade(&flag));
+        Ok(flag)
+    }
+}
+
+/// Returns a flag that is set whenever the file at `path` changes. Errors are
+/// logged and return `None`, the file is never reloaded then
+pub(crate) fn watch_file(path: &Path) -> Option<Arc<AtomicBool>> {
+    static WATCHER: OnceLock<Option<Mutex<FileWatcher>>> = OnceLock::new();
+
+    let watcher = WATCHER.get_or_init(|| match FileWatcher::new() {
+        Ok(watcher) => Some(Mutex::new(watcher)),
+        Err(err) => {
+            error!("Unable to watch shader files, they won't be reloaded: {}", err);
+            None
+        }
+    });
+
+    match watcher.as_ref()?.lock().unwrap().watch(path) {
+        Ok(flag) => Some(flag),
+        Err(err) => {
+            error!("Unable to watch {}, it won't be reloaded: {}", path.display(), err);
+            None
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use core::time::Duration;
 use std::{ops::{Range, RangeBounds, Bound}, marker::PhantomData};
 
-use crate::{render::{Time, RenderEvent}, effect::{EffectData, RegisteredEffectData, EffectRegistrationPacket}, api::transform::{Transform, OPENGL_TO_WGPU_MATRIX}};
+use crate::{lerp, render::{Time, RenderEvent}, effect::{EffectData, RegisteredEffectData, EffectRegistrationPacket}, api::{transform::{Transform, OPENGL_TO_WGPU_MATRIX}, animation::AnimatedProperty, audio::{Audio, AudioBuffer}}};
 
 pub trait IntoFrame {
     fn into_frame(self, fps: f64) -> u64;
@@ -29,6 +29,7 @@ pub struct Clip<'a> {
     children: Vec<Clip<'a>>,
     transform: Transform,
     effects: Vec<EffectData>,
+    audio: Vec<Audio>,
     /// Effect emit an EffectRegistrationPacket when their backend hasn't been
     /// initialized yet.
     effect_registration_packets: Option<Vec<EffectRegistrationPacket>>,
@@ -36,6 +37,11 @@ pub struct Clip<'a> {
     start: Option<u64>,
     /// When `None`, the clip will play until the end of its parent sequence
     end: Option<u64>,
+    /// Multiplies the time seen by effects and children of this clip
+    speed: f64,
+    /// Maps the frame relative to the clip start to the time (in seconds) seen
+    /// by effects and children of this clip, overrides `speed`
+    time_remap: Option<AnimatedProperty<f64>>,
     fps: f64,
     /// Prevent unused lifetime error
     _phantom: PhantomData<&'a ()>,
@@ -47,9 +53,12 @@ impl<'a> Clip<'a> {
             children: Vec::new(),
             transform: Transform::default(),
             effects: Vec::new(),
+            audio: Vec::new(),
             effect_registration_packets: Some(Vec::new()),
             start: Some(0),
             end: Some(duration.into_frame(fps)),
+            speed: 1.0,
+            time_remap: None,
             fps,
             _phantom: PhantomData::default(),
         }
@@ -68,6 +77,31 @@ impl<'a> Clip<'a> {
         && self.end.map(|e|e>frame).unwrap_or(true)
     }
 
+    /// Applies `speed` or `time_remap` to the time relative to the clip start
+    fn remap(&self, clip_frame: u64, clip_time: f64) -> (u64, f64) {
+        let time = match self.time_remap.as_ref() {
+            Some(time_remap) => time_remap.evaluate(clip_frame),
+            None if self.speed != 1.0 => clip_time * self.speed,
+            None => return (clip_frame, clip_time),
+        }
+        .max(0.0);
+
+        (time.into_frame(self.fps), time)
+    }
+
+    /// Like [`Clip::remap`], but interpolates between frames so audio doesn't step
+    fn remap_time(&self, time: f64) -> f64 {
+        match self.time_remap.as_ref() {
+            Some(time_remap) => {
+                let position = time.max(0.0) * self.fps;
+                let frame = position.floor();
+                lerp!(time_remap.evaluate(frame as u64), time_remap.evaluate(frame as u64 + 1), position - frame)
+            }
+            None => time * self.speed,
+        }
+        .max(0.0)
+    }
+
     fn progress(&self, frame: u64, parent_end: u64) -> f64 {
         let start = self.start();
         let end = self.end(parent_end);
@@ -93,11 +127,27 @@ impl<'a> Clip<'a> {
         self
     }
 
+    /// Plays the contents of this clip faster (`> 1.0`) or slower (`< 1.0`)
+    #[inline]
+    pub fn speed(&mut self, speed: f64) -> &mut Clip<'a> {
+        self.speed = speed;
+        self
+    }
+
+    /// Decides which point in time (in seconds) effects and children of this
+    /// clip show at every frame, for freeze frames, ramps or playing backwards
+    #[inline]
+    pub fn time_remap(&mut self, time_remap: AnimatedProperty<f64>) -> &mut Clip<'a> {
+        self.time_remap = Some(time_remap);
+        self
+    }
+
     pub fn new_clip(&mut self, time_range: Range<impl IntoFrame + Copy>) -> &mut Clip<'a> {
         self.children.push(Clip::<'a> {
             children: Vec::new(),
             transform: Transform::default(),
             effects: Vec::new(),
+            audio: Vec::new(),
             effect_registration_packets: Some(Vec::new()),
             start: match time_range.start_bound() {
                 Bound::Included(n) => Some(n.into_frame(self.fps)),
@@ -109,6 +159,8 @@ impl<'a> Clip<'a> {
                 Bound::Excluded(n) => Some(n.into_frame(self.fps) - 1),
                 Bound::Unbounded => None,
             },
+            speed: 1.0,
+            time_remap: None,
             fps: self.fps,
             _phantom: PhantomData::default(),
         });
@@ -117,9 +169,12 @@ impl<'a> Clip<'a> {
     }
 
     pub fn effect<E: 'static + RegisteredEffectData>(&mut self, effect: E) -> &mut Clip<'a> {
-        if unsafe { !E::is_registered() } {
-            self.effect_registration_packets.as_mut().unwrap().push(EffectRegistrationPacket {
-                id: unsafe { E::get_id() },
+        let id = E::get_id();
+        // Every video registers the effects it uses with its own renderer, which skips duplicates
+        let packets = self.effect_registration_packets.as_mut().unwrap();
+        if packets.iter().all(|packet| packet.id != id) {
+            packets.push(EffectRegistrationPacket {
+                id,
                 push_function: E::_push,
                 render_function: E::_render,
                 init_function: E::_new,
@@ -127,13 +182,53 @@ impl<'a> Clip<'a> {
         }
 
         self.effects.push(EffectData {
-            id: unsafe { E::get_id() },
+            id,
             params: Box::new(effect),
         });
 
         self
     }
 
+    /// Plays `audio` while this clip is active
+    pub fn audio(&mut self, audio: Audio) -> &mut Clip<'a> {
+        self.audio.push(audio);
+        self
+    }
+
+    pub(crate) fn has_audio(&self) -> bool {
+        !self.audio.is_empty() || self.children.iter().any(|child| child.has_audio())
+    }
+
+    /// Adds the audio of this clip and its children to `output`. `local_time`
+    /// maps a time of the output to the time seen by this clip, `length` is
+    /// the time this clip stops at. Only audio `include` returns true for is
+    /// mixed, ducked audio follows the level of `sidechain`
+    pub(crate) fn mix_audio(
+        &self,
+        output: &mut AudioBuffer,
+        local_time: &dyn Fn(f64) -> Option<f64>,
+        length: f64,
+        include: &dyn Fn(&Audio) -> bool,
+        sidechain: Option<&AudioBuffer>,
+    ) {
+        for audio in self.audio.iter().filter(|audio| include(audio)) {
+            audio.mix_into(output, local_time, length, self.fps, sidechain);
+        }
+
+        for child in self.children.iter() {
+            let child_time = |time: f64| {
+                let time = local_time(time)?;
+                child
+                    .in_time_frame((time * self.fps).floor() as u64)
+                    .then(|| child.remap_time(time - child.start() as f64 / self.fps))
+            };
+            let end = child.end.map_or(length, |end| end as f64 / self.fps);
+            let child_length = child.remap_time(end - child.start() as f64 / self.fps);
+
+            child.mix_audio(output, &child_time, child_length, include, sidechain);
+        }
+    }
+
     pub(crate) fn get_registration_packets(&mut self) -> Vec<EffectRegistrationPacket> {
         let mut packets = self.effect_registration_packets.take().unwrap();
         packets.extend(self.children.iter_mut().flat_map(|child| child.get_registration_packets()));
@@ -149,6 +244,7 @@ impl<'a> Clip<'a> {
                 let clip_frame = time.clip_frame - clip.start();
                 let clip_time = time.clip_time - clip.start() as f64 / self.fps;
                 let clip_progress = clip.progress(clip_frame, clip_end);
+                let (clip_frame, clip_time) = clip.remap(clip_frame, clip_time);
                 events.extend(clip.render(time.derive_clip(clip_frame, clip_time, clip_progress), clip.end(clip_end), matrix));
             }
         }
@@ -157,7 +253,7 @@ impl<'a> Clip<'a> {
         events.extend(self.effects.iter().map(|effect| RenderEvent::Effect {
             id: effect.id,
             params: &effect.params,
-            frame: time.clip_frame,
+            time,
         }));
 
         events
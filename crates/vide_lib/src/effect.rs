@@ -1,47 +1,42 @@
-use std::{any::Any, sync::MutexGuard};
+use std::{any::{Any, TypeId}, collections::BTreeMap, sync::{Mutex, MutexGuard}};
 
-use crate::render::{Renderer, PushFunction, RenderFunction};
+use crate::{render::{Renderer, PushFunction, RenderFunction, Time}, error::Result};
 
 #[macro_export] macro_rules! register_effect {
     ($name:ident, $dataname:ident) => {
-        $crate::paste::paste! {
-            #[allow(non_upper_case_globals)]
-            static mut [<$name _ID>]: usize = usize::MAX;
-            impl $crate::effect::RegisteredEffectData for $dataname {
-                unsafe fn is_registered() -> bool {
-                    [<$name _ID>] != usize::MAX
-                }
-
-                /// # Warning
-                /// 
-                /// Never use this function directly as the event may not function correctly afterwards
-                unsafe fn get_id() -> usize {
-                    if [<$name _ID>] == usize::MAX {
-                        [<$name _ID>] = $crate::effect::effect_counter();
-                    }
-                    [<$name  _ID>]
-                }
+        impl $crate::effect::RegisteredEffectData for $dataname {
+            fn get_id() -> usize {
+                $crate::effect::effect_id::<$name>()
+            }
 
-                fn _new(renderer: &mut $crate::render::Renderer) -> Box<dyn std::any::Any> {
-                    Box::new(<$name as $crate::effect::Effect>::new(renderer))
-                }
+            fn _new(renderer: &mut $crate::render::Renderer) -> $crate::error::Result<Box<dyn std::any::Any>> {
+                <$name as $crate::effect::Effect>::new(renderer)
+                    .map(|backend| Box::new(backend) as Box<dyn std::any::Any>)
+                    .map_err(|err| err.in_effect(stringify!($name)))
+            }
 
-                fn _push(backend: &mut Box<dyn std::any::Any>, params: &Box<dyn std::any::Any>, frame: u64) {
-                    <$name as $crate::effect::EffectBackend>::push(backend.as_mut().downcast_mut().unwrap(), params.as_ref().downcast_ref::<<$name as $crate::effect::EffectBackend>::Instance>().unwrap(), frame)
-                }
+            fn _push(backend: &mut Box<dyn std::any::Any>, params: &Box<dyn std::any::Any>, time: $crate::render::Time) {
+                <$name as $crate::effect::EffectBackend>::push(backend.as_mut().downcast_mut().unwrap(), params.as_ref().downcast_ref::<<$name as $crate::effect::EffectBackend>::Instance>().unwrap(), time)
+            }
 
-                fn _render<'a>(backend: &'a mut Box<dyn std::any::Any>, pass: std::sync::MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) {
-                    <$name as $crate::effect::EffectBackend>::render(backend.as_mut().downcast_mut().unwrap(), pass, device, queue)
-                }
+            fn _render<'a>(backend: &'a mut Box<dyn std::any::Any>, pass: std::sync::MutexGuard<wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> $crate::error::Result<()> {
+                <$name as $crate::effect::EffectBackend>::render(backend.as_mut().downcast_mut().unwrap(), pass, device, queue)
+                    .map_err(|err| err.in_effect(stringify!($name)))
             }
         }
     };
 }
 
-static mut COUNTER: usize = 0;
-pub unsafe fn effect_counter() -> usize {
-    COUNTER += 1;
-    COUNTER - 1
+/// Ids of the effect backends used so far, in the order they were first used.
+/// Effects render in the order of their ids, so scenes that are built the same
+/// way render the same on every thread and in every process
+static EFFECT_IDS: Mutex<BTreeMap<TypeId, usize>> = Mutex::new(BTreeMap::new());
+
+/// Id of the effect backend `E`, assigned the first time it is used
+pub fn effect_id<E: 'static>() -> usize {
+    let mut ids = EFFECT_IDS.lock().unwrap();
+    let next = ids.len();
+    *ids.entry(TypeId::of::<E>()).or_insert(next)
 }
 
 pub enum EffectParameter {
@@ -49,21 +44,20 @@ pub enum EffectParameter {
 }
 
 pub trait Effect {
-    fn new(renderer: &mut Renderer) -> Self;
+    fn new(renderer: &mut Renderer) -> Result<Self> where Self: Sized;
 }
 
 pub trait EffectBackend {
     type Instance;
-    fn push(&mut self, instance: &Self::Instance, frame: u64);
-    fn render<'a>(&'a mut self, pass: MutexGuard<'_, wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue);
+    fn push(&mut self, instance: &Self::Instance, time: Time);
+    fn render<'a>(&'a mut self, pass: MutexGuard<'_, wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()>;
 }
 
 pub trait RegisteredEffectData {
-    unsafe fn is_registered() -> bool;
-    unsafe fn get_id() -> usize;
-    fn _new(renderer: &mut Renderer) -> Box<dyn Any>;
-    fn _push(backend: &mut Box<dyn Any>, params: &Box<dyn Any>, frame: u64);
-    fn _render<'a>(backend: &'a mut Box<dyn Any>, pass: MutexGuard<'_, wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue);
+    fn get_id() -> usize;
+    fn _new(renderer: &mut Renderer) -> Result<Box<dyn Any>>;
+    fn _push(backend: &mut Box<dyn Any>, params: &Box<dyn Any>, time: Time);
+    fn _render<'a>(backend: &'a mut Box<dyn Any>, pass: MutexGuard<'_, wgpu::RenderPass<'a>>, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()>;
 }
 
 pub struct EffectData {
@@ -76,5 +70,5 @@ pub struct EffectRegistrationPacket {
     pub id: usize,
     pub push_function: PushFunction,
     pub render_function: RenderFunction,
-    pub init_function: fn(&mut Renderer)->Box<dyn Any>,
+    pub init_function: fn(&mut Renderer)->Result<Box<dyn Any>>,
 }
\ No newline at end of file
@@ -0,0 +1,136 @@
+use std::{fmt::Display, path::PathBuf};
+
+use crate::api::shader::ShaderGeneratorError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A WGSL shader that failed to parse or validate
+#[derive(Debug, Clone)]
+pub struct ShaderError {
+    /// Label of the shader, usually its file name
+    pub label: String,
+    /// Line of the first error, starting at 1
+    pub line: Option<u32>,
+    /// Column of the first error, starting at 1
+    pub column: Option<u32>,
+    /// Diagnostic reported by naga (or wgpu when naga accepted the shader)
+    pub message: String,
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "shader {}:{}:{} failed to compile:\n{}", self.label, line, column, self.message),
+            _ => write!(f, "shader {} failed to compile:\n{}", self.label, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A shader failed to parse or validate
+    Shader(ShaderError),
+    /// wgpu rejected a render pipeline
+    Pipeline {
+        /// Label of the shader the pipeline was created with
+        label: String,
+        message: String,
+    },
+    /// [`ShaderGenerator`](crate::api::shader::ShaderGenerator) was given an invalid combination of passes
+    ShaderGenerator(ShaderGeneratorError),
+    /// A value doesn't fit the uniform buffer it is written to
+    Uniform(String),
+    /// A font could not be parsed or its glyphs don't fit into the atlas
+    Font(String),
+    /// An image could not be decoded
+    Image(::image::ImageError),
+    /// No graphics adapter matches the settings of the video, or it could not be opened
+    Adapter(String),
+    /// A media file could not be decoded or encoded
+    Codec(String),
+    /// wgpu rejected the commands of a frame
+    Gpu(String),
+    /// A rendered frame could not be copied from the GPU
+    Readback(String),
+    /// A file could not be read or written
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Frames passed to [`Video::render_range`](crate::api::video::Video::render_range)
+    /// are empty or reach past the end of the video
+    FrameRange {
+        frames: std::ops::Range<u64>,
+        /// Number of frames of the video
+        total: u64,
+    },
+    /// An effect failed to initialize or render
+    Effect {
+        /// Name of the effect backend
+        effect: &'static str,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Attaches the name of the effect that caused this error
+    pub fn in_effect(self, effect: &'static str) -> Self {
+        Error::Effect {
+            effect,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Shader(err) => err.fmt(f),
+            Error::Pipeline { label, message } => write!(f, "unable to create pipeline for shader {}:\n{}", label, message),
+            Error::ShaderGenerator(err) => err.fmt(f),
+            Error::Uniform(message) => write!(f, "uniform error: {}", message),
+            Error::Font(message) => write!(f, "font error: {}", message),
+            Error::Image(err) => write!(f, "unable to decode image: {}", err),
+            Error::Adapter(message) => write!(f, "graphics adapter error: {}", message),
+            Error::Codec(message) => write!(f, "codec error: {}", message),
+            Error::Gpu(message) => write!(f, "GPU error while rendering: {}", message),
+            Error::Readback(message) => write!(f, "unable to read a rendered frame from the GPU: {}", message),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::FrameRange { frames, total } => write!(f, "invalid frame range {}..{}, the video has {} frames", frames.start, frames.end, total),
+            Error::Effect { effect, source } => write!(f, "effect {}: {}", effect, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Shader(err) => Some(err),
+            Error::ShaderGenerator(err) => Some(err),
+            Error::Image(err) => Some(err),
+            Error::Io { source, .. } => Some(source),
+            Error::Effect { source, .. } => Some(source.as_ref()),
+            Error::Pipeline { .. } | Error::Uniform(_) | Error::Font(_) | Error::Adapter(_) | Error::Codec(_) | Error::Gpu(_) | Error::Readback(_) | Error::FrameRange { .. } => None,
+        }
+    }
+}
+
+impl From<ShaderError> for Error {
+    fn from(err: ShaderError) -> Self {
+        Error::Shader(err)
+    }
+}
+
+impl From<ShaderGeneratorError> for Error {
+    fn from(err: ShaderGeneratorError) -> Self {
+        Error::ShaderGenerator(err)
+    }
+}
+
+impl From<::image::ImageError> for Error {
+    fn from(err: ::image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
@@ -1,12 +1,96 @@
-use crate::api::video::VideoSettings;
+use std::{ops::Range, path::Path};
+
+pub mod fan_out;
+pub mod gif;
+pub mod image_sequence;
+pub mod stream;
+#[cfg(feature = "webm")]
+pub mod webm;
+mod yuv;
+
+use crate::{api::{video::VideoSettings, audio::AudioBuffer}, error::Result};
+
+/// A decoded video frame
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedFrame<'a> {
+    /// Time (in seconds) the frame starts at in its source
+    pub timestamp: f64,
+    /// RGBA8 pixels in sRGB, rows are tightly packed
+    pub data: &'a [u8],
+}
+
+/// Decodes frames of a video file on demand
+pub trait VideoDecoder: Send {
+    fn resolution(&self) -> (u32, u32);
+    /// Returns the frame that is visible `time` seconds into the source, the
+    /// last frame once `time` is past the end and `None` when the source has no frames
+    fn frame_at(&mut self, time: f64) -> Result<Option<DecodedFrame<'_>>>;
+}
 
 pub trait Import {
+    type Decoder: VideoDecoder + 'static;
+
     fn supported_import_extensions() -> Vec<String>;
+    fn open(path: impl AsRef<Path>) -> Result<Self::Decoder>;
+    /// Decodes the first audio stream of a file completely
+    fn open_audio(path: impl AsRef<Path>) -> Result<AudioBuffer>;
 }
 
-pub trait Export {
-    fn begin(&mut self, settings: VideoSettings);
+/// Writes rendered frames somewhere. Errors stop the render, after which
+/// [`Export::abort`] is called instead of [`Export::end`]. Frames are pushed
+/// from another thread than the one that called `begin`, while the next
+/// frames render
+pub trait Export: Send {
+    /// `audio` holds the mixed audio of the whole video, `None` when it has no sound
+    fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()>;
     /// `frame` contains Rgba8UnormSrgb data as bytes (RGBA8)
-    fn push_frame(&mut self, keyframe: bool, frame: &[u8]);
-    fn end(self);
-}
\ No newline at end of file
+    fn push_frame(&mut self, keyframe: bool, frame: &[u8]) -> Result<()>;
+    /// Called for every frame `index` (counted from the first exported frame)
+    /// after `begin`, before anything renders. Returning `true` skips the frame,
+    /// for exporters that already have it from an earlier render. Skipped
+    /// frames aren't passed to [`Export::push_frame`]
+    fn skip_frame(&mut self, index: u64) -> bool {
+        let _ = index;
+        false
+    }
+    /// Finishes the output, removing it when that fails
+    fn end(self) -> Result<()>;
+    /// Removes everything written so far, after an error
+    fn abort(self) where Self: Sized {}
+}
+
+/// Output that can be written in segments at the same time and joined in the
+/// end, see [`Video::render_parallel`](crate::api::video::Video::render_parallel)
+pub trait ChunkedExport {
+    type Segment: Export;
+
+    /// Exporter for segment `index`, which holds `frames` of the video
+    fn segment(&self, index: usize, frames: Range<u64>) -> Self::Segment;
+    /// Joins the finished segments into the final output and removes them.
+    /// `settings` and `audio` are those of the whole video
+    fn concat(self, settings: VideoSettings, audio: Option<AudioBuffer>, segments: &[Range<u64>]) -> Result<()>;
+    /// Removes the segments after one of them failed
+    fn abort_segments(self, segments: &[Range<u64>]) where Self: Sized {
+        let _ = segments;
+    }
+}
+
+/// Removes a partially written file, the file not existing (yet) is fine
+pub fn remove_partial(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("unable to remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Frame rate as a fraction, NTSC rates like 29.97 become `30000:1001`
+pub(crate) fn frame_rate(fps: f64) -> (u64, u64) {
+    if fps.fract() == 0.0 {
+        (fps as u64, 1)
+    } else if ((fps * 1.001).round() - fps * 1.001).abs() < 0.001 {
+        ((fps * 1.001).round() as u64 * 1000, 1001)
+    } else {
+        ((fps * 1000.0).round() as u64, 1000)
+    }
+}
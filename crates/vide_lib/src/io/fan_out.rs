@@ -0,0 +1,306 @@
+use std::{collections::BTreeSet, ops::Range, time::Duration};
+
+use crate::{
+    api::{audio::AudioBuffer, video::VideoSettings},
+    error::{Error, Result},
+};
+
+use super::Export;
+
+/// What part of the video an output of a [`FanOutExporter`] gets
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutputOptions {
+    /// Frames are scaled to this resolution, `None` keeps the resolution of the video
+    pub resolution: Option<(u32, u32)>,
+    /// Frame rate of the output, at most the frame rate of the video. Frames in between are dropped
+    pub fps: Option<f64>,
+    /// Frames of the video (at its frame rate) the output gets, `None` for all of them
+    pub frames: Option<Range<u64>>,
+}
+
+/// [`Export`] with `end` and `abort` callable through a `Box`
+trait BoxedExport: Send {
+    fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()>;
+    fn push_frame(&mut self, keyframe: bool, frame: &[u8]) -> Result<()>;
+    fn skip_frame(&mut self, index: u64) -> bool;
+    fn end(self: Box<Self>) -> Result<()>;
+    fn abort(self: Box<Self>);
+}
+
+impl<E: Export> BoxedExport for E {
+    fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()> {
+        Export::begin(self, settings, audio)
+    }
+
+    fn push_frame(&mut self, keyframe: bool, frame: &[u8]) -> Result<()> {
+        Export::push_frame(self, keyframe, frame)
+    }
+
+    fn skip_frame(&mut self, index: u64) -> bool {
+        Export::skip_frame(self, index)
+    }
+
+    fn end(self: Box<Self>) -> Result<()> {
+        Export::end(*self)
+    }
+
+    fn abort(self: Box<Self>) {
+        Export::abort(*self)
+    }
+}
+
+struct Output {
+    exporter: Box<dyn BoxedExport>,
+    options: OutputOptions,
+    /// Resolution of the frames passed to `exporter`
+    resolution: (u32, u32),
+    frames: Range<u64>,
+    fps: f64,
+    /// Frames passed to `exporter` so far
+    written: u64,
+    /// Frames of the output its exporter already has
+    skipped: BTreeSet<u64>,
+}
+
+impl Output {
+    /// Frame of the output that starts at `video_frame`, `None` when the
+    /// output doesn't get that frame of the video
+    fn frame(&self, video_frame: u64, video_fps: f64) -> Option<u64> {
+        if !self.frames.contains(&video_frame) {
+            return None;
+        }
+
+        let index = |video_frame: u64| ((video_frame - self.frames.start) as f64 * self.fps / video_fps).floor() as u64;
+        let frame = index(video_frame);
+        // Frames in between frames of the output are dropped
+        (video_frame == self.frames.start || index(video_frame - 1) < frame).then_some(frame)
+    }
+}
+
+/// Passes every rendered frame to several exporters, so a master, a web proxy
+/// and thumbnails only need one render. Every output can be scaled, get a lower
+/// frame rate or only a part of the video, see [`OutputOptions`]
+///
+/// ```ignore
+/// video.render(
+///     FanOutExporter::new()
+///         .output(quick_export::to("master.mov").unwrap(), OutputOptions::default())
+///         .output(quick_export::to("proxy.mp4").unwrap(), OutputOptions {
+///             resolution: Some((960, 540)),
+///             ..Default::default()
+///         })
+///         .output(ImageSequenceExporter::new("thumbnails/%03d.png"), OutputOptions {
+///             resolution: Some((320, 180)),
+///             fps: Some(1.0),
+///             ..Default::default()
+///         }),
+/// )
+/// ```
+#[derive(Default)]
+pub struct FanOutExporter {
+    outputs: Vec<Output>,
+    video_resolution: (u32, u32),
+    video_fps: f64,
+    /// Index of the next pushed frame
+    frame: u64,
+    /// Frames every output that gets them already has
+    skipped: BTreeSet<u64>,
+}
+
+impl FanOutExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn output(mut self, exporter: impl Export + 'static, options: OutputOptions) -> Self {
+        self.outputs.push(Output {
+            exporter: Box::new(exporter),
+            options,
+            resolution: (0, 0),
+            frames: 0..0,
+            fps: 0.0,
+            written: 0,
+            skipped: BTreeSet::new(),
+        });
+        self
+    }
+}
+
+impl Export for FanOutExporter {
+    fn begin(&mut self, settings: VideoSettings, audio: Option<AudioBuffer>) -> Result<()> {
+        use crate::clip::IntoFrame;
+
+        self.video_resolution = settings.resolution;
+        self.video_fps = settings.fps;
+        let total_frames = settings.duration.into_frame(settings.fps);
+
+        for output in self.outputs.iter_mut() {
+            let frames = output.options.frames.clone().unwrap_or(0..total_frames);
+            output.frames = frames.start.min(total_frames)..frames.end.min(total_frames);
+            output.resolution = output.options.resolution.unwrap_or(settings.resolution);
+            output.fps = output.options.fps.map_or(settings.fps, |fps| fps.min(settings.fps));
+
+            let (start, end) = (output.frames.start as f64 / settings.fps, output.frames.end as f64 / settings.fps);
+            let output_settings = VideoSettings {
+                resolution: output.resolution,
+                fps: output.fps,
+                duration: Duration::from_secs_f64(end - start),
+                ..settings
+            };
+            let output_audio = audio.as_ref().map(|audio| audio.slice(start, end));
+
+            output.exporter.begin(output_settings, output_audio)?;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, keyframe: bool, frame: &[u8]) -> Result<()> {
+        let (width, height) = self.video_resolution;
+        if frame.len() != width as usize * height as usize * 4 {
+            return Err(Error::Codec(format!("frame has {} bytes, {}x{} RGBA needs {}", frame.len(), width, height, width as usize * height as usize * 4)));
+        }
+
+        while self.skipped.contains(&self.frame) {
+            self.frame += 1;
+        }
+        let video_frame = self.frame;
+        self.frame += 1;
+
+        for output in self.outputs.iter_mut() {
+            match output.frame(video_frame, self.video_fps) {
+                Some(index) if !output.skipped.contains(&index) => {}
+                _ => continue,
+            }
+
+            let keyframe = keyframe || output.written == 0;
+            output.written += 1;
+
+            if output.resolution == self.video_resolution {
+                output.exporter.push_frame(keyframe, frame)?;
+            } else {
+                // The length was checked above, so the frame always fits
+                let image = ::image::RgbaImage::from_raw(width, height, frame.to_vec()).unwrap();
+                let scaled = ::image::imageops::resize(&image, output.resolution.0, output.resolution.1, ::image::imageops::FilterType::Triangle);
+                output.exporter.push_frame(keyframe, &scaled)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks every output that gets the frame, which is only skipped when all
+    /// of them already have it. Outputs that have it don't get it again
+    fn skip_frame(&mut self, index: u64) -> bool {
+        let mut skip = true;
+        for output in self.outputs.iter_mut() {
+            if let Some(frame) = output.frame(index, self.video_fps) {
+                // Every output is asked, they keep track of what they skip
+                if output.exporter.skip_frame(frame) {
+                    output.skipped.insert(frame);
+                } else {
+                    skip = false;
+                }
+            }
+        }
+
+        if skip {
+            self.skipped.insert(index);
+        }
+        skip
+    }
+
+    /// Ends every output, even after one of them failed, and returns the first error
+    fn end(self) -> Result<()> {
+        let mut result = Ok(());
+        for output in self.outputs {
+            let ended = output.exporter.end();
+            if result.is_ok() {
+                result = ended;
+            }
+        }
+        result
+    }
+
+    fn abort(self) {
+        for output in self.outputs {
+            output.exporter.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        api::{audio::AudioBuffer, video::VideoSettings},
+        error::Result,
+        io::Export,
+    };
+
+    use super::{FanOutExporter, OutputOptions};
+
+    /// Records the first byte of every frame it gets, and already has the
+    /// frames in `existing`
+    struct Recorder {
+        existing: Vec<u64>,
+        frames: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Export for Recorder {
+        fn begin(&mut self, _settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+            Ok(())
+        }
+
+        fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+            self.frames.lock().unwrap().push(frame[0]);
+            Ok(())
+        }
+
+        fn skip_frame(&mut self, index: u64) -> bool {
+            self.existing.contains(&index)
+        }
+
+        fn end(self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn recorder(existing: Vec<u64>) -> (Recorder, Arc<Mutex<Vec<u8>>>) {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        (Recorder { existing, frames: frames.clone() }, frames)
+    }
+
+    #[test]
+    fn frames_are_skipped_when_every_output_has_them() {
+        let (full, full_frames) = recorder(vec![0, 1, 2]);
+        // Gets video frames 2, 4 and 6 as its frames 0, 1 and 2
+        let (part, part_frames) = recorder(vec![0, 2]);
+        let mut exporter = FanOutExporter::new().output(full, OutputOptions::default()).output(part, OutputOptions {
+            fps: Some(5.0),
+            frames: Some(2..8),
+            ..Default::default()
+        });
+
+        let settings = VideoSettings {
+            fps: 10.0,
+            resolution: (1, 1),
+            duration: Duration::from_secs_f64(0.8),
+            ..Default::default()
+        };
+        exporter.begin(settings, None).unwrap();
+
+        let skipped = (0..8).map(|index| exporter.skip_frame(index)).collect::<Vec<_>>();
+        assert_eq!(skipped, [true, true, true, false, false, false, false, false]);
+
+        for frame in 3..8u8 {
+            exporter.push_frame(false, &[frame, 0, 0, 255]).unwrap();
+        }
+        exporter.end().unwrap();
+
+        assert_eq!(*full_frames.lock().unwrap(), [3, 4, 5, 6, 7]);
+        assert_eq!(*part_frames.lock().unwrap(), [4]);
+    }
+}
@@ -0,0 +1,547 @@
+use std::{borrow::Cow, fs::File, path::PathBuf};
+
+use color_quant::NeuQuant;
+
+use crate::{api::{audio::AudioBuffer, video::VideoSettings}, error::{Error, Result}};
+
+use super::{remove_partial, Export};
+
+/// GIF delays are counted in hundredths of a second and browsers slow down
+/// anything shorter than 2, so GIFs can't play faster than this
+const MAX_GIF_FPS: f64 = 50.0;
+
+/// How the missing colors of a GIF's palette are faked
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Every pixel gets the closest color, gradients show bands
+    None,
+    /// Spreads the error of every pixel to its neighbours, looks best on still
+    /// frames but the noise moves between frames
+    #[default]
+    FloydSteinberg,
+    /// Regular 8x8 pattern, stable between frames and compresses better
+    Bayer,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GifPalette {
+    /// One palette for the whole GIF, built once all frames are known. Colors
+    /// stay stable between frames, but every frame is kept in memory until the end
+    #[default]
+    Global,
+    /// A palette for every frame, better colors when scenes change a lot
+    PerFrame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GifOptions {
+    /// Frames per second of the GIF, `None` keeps the fps of the video. Frames
+    /// are dropped to reach lower rates, at most 50 fps are possible
+    pub fps: Option<f64>,
+    /// Size of the GIF, `None` keeps the resolution of the video. When only
+    /// one side is given, the other one keeps the aspect ratio
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub palette: GifPalette,
+    pub dither: Dither,
+    /// How often the GIF plays, `None` loops forever
+    pub loop_count: Option<u16>,
+    /// Pixels with less alpha than this (out of 255) become transparent, `None` keeps all pixels opaque
+    pub transparency: Option<u8>,
+    /// Speed of the palette generation from 1 (best) to 30 (fastest)
+    pub quantizer_speed: i32,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            fps: Some(25.0),
+            width: None,
+            height: None,
+            palette: GifPalette::Global,
+            dither: Dither::FloydSteinberg,
+            loop_count: None,
+            transparency: None,
+            quantizer_speed: 10,
+        }
+    }
+}
+
+/// Exports an animated GIF, without needing ffmpeg. The audio of the video is left out
+///
+/// With the default [`GifPalette::Global`] every scaled frame is kept in
+/// memory until [`Export::end`] builds the palette, use
+/// [`GifPalette::PerFrame`] or a smaller `width` for long or large GIFs
+pub struct GifExporter {
+    path: PathBuf,
+    options: GifOptions,
+
+    encoder: Option<gif::Encoder<File>>,
+    /// Resolution of the rendered frames
+    source_size: (u32, u32),
+    /// Resolution of the GIF
+    size: (u32, u32),
+    video_fps: f64,
+    fps: f64,
+    /// Frames pushed so far
+    frame: u64,
+    /// Frames written (or buffered) so far
+    written: u64,
+    /// Scaled RGBA frames with their delay, waiting for the global palette
+    buffered: Vec<(Vec<u8>, u16)>,
+}
+
+impl GifExporter {
+    pub fn new(path: impl Into<PathBuf>, options: GifOptions) -> Self {
+        Self {
+            path: path.into(),
+            options,
+
+            encoder: None,
+            source_size: (0, 0),
+            size: (0, 0),
+            video_fps: 0.0,
+            fps: 0.0,
+            frame: 0,
+            written: 0,
+            buffered: Vec::new(),
+        }
+    }
+
+    /// Delay (in hundredths of a second) of the `index`th written frame, the
+    /// rounding error is carried over to the next frame so the GIF keeps time
+    fn delay(&self, index: u64) -> u16 {
+        let at = |index: u64| (index as f64 * 100.0 / self.fps).round() as u64;
+        (at(index + 1) - at(index)) as u16
+    }
+
+    fn scale(&self, frame: &[u8]) -> Result<Vec<u8>> {
+        let (width, height) = self.source_size;
+        if frame.len() != width as usize * height as usize * 4 {
+            return Err(Error::Codec(format!("frame has {} bytes, {}x{} RGBA needs {}", frame.len(), width, height, width as usize * height as usize * 4)));
+        }
+        if self.size == self.source_size {
+            return Ok(frame.to_vec());
+        }
+
+        // The length was checked above, so the frame always fits
+        let image = ::image::RgbaImage::from_raw(width, height, frame.to_vec()).unwrap();
+        Ok(::image::imageops::resize(&image, self.size.0, self.size.1, ::image::imageops::FilterType::Triangle).into_raw())
+    }
+
+    fn error(&self, err: gif::EncodingError) -> Error {
+        match err {
+            gif::EncodingError::Io(source) => Error::Io {
+                path: self.path.clone(),
+                source,
+            },
+            gif::EncodingError::Format(err) => Error::Codec(format!("unable to encode {}: {}", self.path.display(), err)),
+        }
+    }
+
+    fn write_frame(&mut self, quantizer: &Quantizer, pixels: &[u8], delay: u16, global: bool) -> Result<()> {
+        let (width, height) = self.size;
+        let indices = quantizer.map(pixels, width as usize, self.options.dither);
+
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            delay,
+            dispose: gif::DisposalMethod::Background,
+            transparent: quantizer.transparent,
+            palette: (!global).then(|| quantizer.palette.clone()),
+            buffer: Cow::Owned(indices),
+            ..Default::default()
+        };
+
+        self.encoder.as_mut().unwrap().write_frame(&frame).map_err(|err| self.error(err))
+    }
+
+    fn open(&mut self, global_palette: &[u8]) -> Result<()> {
+        let file = File::create(&self.path).map_err(|source| Error::Io {
+            path: self.path.clone(),
+            source,
+        })?;
+        let mut encoder = gif::Encoder::new(file, self.size.0 as u16, self.size.1 as u16, global_palette).map_err(|err| self.error(err))?;
+        encoder
+            .set_repeat(match self.options.loop_count {
+                Some(count) => gif::Repeat::Finite(count),
+                None => gif::Repeat::Infinite,
+            })
+            .map_err(|err| self.error(err))?;
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.options.palette == GifPalette::Global {
+            let frames = std::mem::take(&mut self.buffered);
+            let quantizer = Quantizer::new(&frames.iter().map(|(pixels, _)| &pixels[..]).collect::<Vec<_>>(), &self.options);
+
+            self.open(&quantizer.palette)?;
+            for (pixels, delay) in frames.iter() {
+                self.write_frame(&quantizer, pixels, *delay, true)?;
+            }
+        }
+
+        // Writes the trailer
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner().map(drop).map_err(|source| Error::Io {
+                path: self.path.clone(),
+                source,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Export for GifExporter {
+    fn begin(&mut self, settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+        let (width, height) = settings.resolution;
+        self.source_size = (width, height);
+        self.size = match (self.options.width, self.options.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, (height as f64 * w as f64 / width as f64).round() as u32),
+            (None, Some(h)) => ((width as f64 * h as f64 / height as f64).round() as u32, h),
+            (None, None) => (width, height),
+        };
+        self.size = (self.size.0.clamp(1, u16::MAX as u32), self.size.1.clamp(1, u16::MAX as u32));
+
+        self.video_fps = settings.fps;
+        self.fps = self.options.fps.unwrap_or(settings.fps).min(settings.fps).min(MAX_GIF_FPS);
+
+        if self.options.palette == GifPalette::PerFrame {
+            self.open(&[])?;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+        // Only frames that start a new frame of the GIF are kept
+        let index = (self.frame as f64 * self.fps / self.video_fps).floor() as u64;
+        self.frame += 1;
+        if index < self.written {
+            return Ok(());
+        }
+
+        let pixels = self.scale(frame)?;
+        let delay = self.delay(self.written);
+        self.written += 1;
+
+        match self.options.palette {
+            GifPalette::Global => self.buffered.push((pixels, delay)),
+            GifPalette::PerFrame => {
+                let quantizer = Quantizer::new(&[&pixels], &self.options);
+                self.write_frame(&quantizer, &pixels, delay, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<()> {
+        let result = self.finish();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+
+    fn abort(mut self) {
+        drop(self.encoder.take());
+        remove_partial(&self.path);
+    }
+}
+
+/// Maps RGBA pixels to the indices of a palette of at most 256 colors
+struct Quantizer {
+    quant: NeuQuant,
+    /// RGB triplets
+    palette: Vec<u8>,
+    /// Index of the transparent color, always the last one
+    transparent: Option<u8>,
+    /// Alpha below which pixels are transparent
+    threshold: Option<u8>,
+}
+
+impl Quantizer {
+    /// Limits how many pixels the palette is learned from, so long GIFs with a
+    /// global palette don't take forever
+    const MAX_SAMPLES: usize = 1 << 22;
+
+    fn new(frames: &[&[u8]], options: &GifOptions) -> Self {
+        let threshold = options.transparency;
+        let pixel_count = frames.iter().map(|frame| frame.len() / 4).sum::<usize>();
+        let step = (pixel_count / Self::MAX_SAMPLES).max(1);
+
+        let mut samples = frames
+            .iter()
+            .flat_map(|frame| frame.chunks_exact(4))
+            .step_by(step)
+            .filter(|pixel| threshold.is_none_or(|threshold| pixel[3] >= threshold))
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect::<Vec<_>>();
+        if samples.is_empty() {
+            samples.extend_from_slice(&[0, 0, 0, 255]);
+        }
+
+        let colors = if threshold.is_some() { 255 } else { 256 };
+        let quant = NeuQuant::new(options.quantizer_speed.clamp(1, 30), colors, &samples);
+        let mut palette = quant.color_map_rgb();
+        palette.resize(colors * 3, 0);
+
+        let transparent = threshold.map(|_| {
+            palette.extend_from_slice(&[0, 0, 0]);
+            255
+        });
+
+        Self {
+            quant,
+            palette,
+            transparent,
+            threshold,
+        }
+    }
+
+    fn closest(&self, rgb: [f32; 3]) -> u8 {
+        let pixel = rgb.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+        self.quant.index_of(&[pixel[0], pixel[1], pixel[2], 255]) as u8
+    }
+
+    fn color(&self, index: u8) -> [f32; 3] {
+        let i = index as usize * 3;
+        [self.palette[i] as f32, self.palette[i + 1] as f32, self.palette[i + 2] as f32]
+    }
+
+    fn map(&self, pixels: &[u8], width: usize, dither: Dither) -> Vec<u8> {
+        let height = pixels.len() / 4 / width;
+        let mut indices = Vec::with_capacity(width * height);
+        // Error carried to the current and the next row for Floyd-Steinberg
+        let mut errors = vec![[0.0f32; 3]; width * 2];
+
+        for y in 0..height {
+            if dither == Dither::FloydSteinberg {
+                errors.copy_within(width.., 0);
+                errors[width..].fill([0.0; 3]);
+            }
+
+            for x in 0..width {
+                let pixel = &pixels[(y * width + x) * 4..][..4];
+                if let (Some(transparent), Some(threshold)) = (self.transparent, self.threshold) {
+                    if pixel[3] < threshold {
+                        indices.push(transparent);
+                        continue;
+                    }
+                }
+
+                let mut rgb = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+                match dither {
+                    Dither::None => {}
+                    Dither::FloydSteinberg => {
+                        for (channel, error) in rgb.iter_mut().zip(errors[x]) {
+                            *channel += error;
+                        }
+                    }
+                    Dither::Bayer => {
+                        let offset = (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5;
+                        for channel in rgb.iter_mut() {
+                            *channel += offset * 32.0;
+                        }
+                    }
+                }
+
+                let index = self.closest(rgb);
+                indices.push(index);
+
+                if dither == Dither::FloydSteinberg {
+                    let color = self.color(index);
+                    let error = [rgb[0] - color[0], rgb[1] - color[1], rgb[2] - color[2]];
+                    let mut spread = |i: usize, weight: f32| {
+                        for (target, error) in errors[i].iter_mut().zip(error) {
+                            *target += error * weight;
+                        }
+                    };
+
+                    if x + 1 < width {
+                        spread(x + 1, 7.0 / 16.0);
+                        spread(width + x + 1, 1.0 / 16.0);
+                    }
+                    if x > 0 {
+                        spread(width + x - 1, 3.0 / 16.0);
+                    }
+                    spread(width + x, 5.0 / 16.0);
+                }
+            }
+        }
+
+        indices
+    }
+}
+
+const BAYER: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use crate::{api::video::VideoSettings, io::Export};
+
+    use super::{Dither, GifExporter, GifOptions, GifPalette, Quantizer};
+
+    fn settings(fps: f64, resolution: (u32, u32)) -> VideoSettings {
+        VideoSettings {
+            fps,
+            resolution,
+            duration: Duration::from_secs(1),
+            ..Default::default()
+        }
+    }
+
+    /// Frames of the GIF at `path` as RGBA
+    fn decode(path: &Path) -> Vec<gif::Frame<'static>> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(std::fs::File::open(path).unwrap()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.clone());
+        }
+        frames
+    }
+
+    fn gray(value: u8, pixels: usize) -> Vec<u8> {
+        [value, value, value, 255].repeat(pixels)
+    }
+
+    #[test]
+    fn delays_carry_their_rounding_error() {
+        let mut exporter = GifExporter::new("unused.gif", GifOptions::default());
+        exporter.fps = 30.0;
+        assert_eq!((0..6).map(|index| exporter.delay(index)).collect::<Vec<_>>(), [3, 4, 3, 3, 4, 3]);
+        assert_eq!((0..30).map(|index| exporter.delay(index) as u32).sum::<u32>(), 100);
+
+        exporter.fps = 25.0;
+        assert!((0..25).all(|index| exporter.delay(index) == 4));
+    }
+
+    #[test]
+    fn fps_is_limited_by_the_video_and_gif() {
+        let fps = |gif: Option<f64>, video: f64| {
+            let mut exporter = GifExporter::new("unused.gif", GifOptions { fps: gif, ..Default::default() });
+            exporter.begin(settings(video, (4, 4)), None).unwrap();
+            exporter.fps
+        };
+        assert_eq!(fps(Some(25.0), 60.0), 25.0);
+        assert_eq!(fps(Some(60.0), 30.0), 30.0);
+        assert_eq!(fps(None, 60.0), 50.0);
+    }
+
+    #[test]
+    fn size_keeps_the_aspect_ratio() {
+        let size = |width: Option<u32>, height: Option<u32>| {
+            let mut exporter = GifExporter::new("unused.gif", GifOptions { width, height, ..Default::default() });
+            exporter.begin(settings(30.0, (64, 48)), None).unwrap();
+            exporter.size
+        };
+        assert_eq!(size(None, None), (64, 48));
+        assert_eq!(size(Some(32), None), (32, 24));
+        assert_eq!(size(None, Some(12)), (16, 12));
+        assert_eq!(size(Some(10), Some(10)), (10, 10));
+        assert_eq!(size(Some(1), None), (1, 1));
+    }
+
+    #[test]
+    fn frames_are_dropped_to_reach_the_fps() {
+        let path = std::env::temp_dir().join(format!("vide_gif_frames_{}.gif", std::process::id()));
+        let options = GifOptions {
+            fps: Some(25.0),
+            width: Some(16),
+            palette: GifPalette::PerFrame,
+            dither: Dither::None,
+            ..Default::default()
+        };
+        let mut exporter = GifExporter::new(&path, options);
+        exporter.begin(settings(60.0, (32, 32)), None).unwrap();
+        for frame in 0..60 {
+            exporter.push_frame(false, &gray(frame * 4, 32 * 32)).unwrap();
+        }
+        exporter.end().unwrap();
+
+        let frames = decode(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 25);
+        assert!(frames.iter().all(|frame| (frame.width, frame.height, frame.delay) == (16, 16, 4)));
+
+        // Every GIF frame shows the first video frame that falls into it
+        let shown = frames.iter().map(|frame| frame.buffer[0]).collect::<Vec<_>>();
+        let expected = (0..25u32).map(|index| ((index * 60).div_ceil(25) * 4) as u8).collect::<Vec<_>>();
+        for (shown, expected) in shown.iter().zip(expected.iter()) {
+            assert!(shown.abs_diff(*expected) <= 2, "{:?} != {:?}", shown, expected);
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_use_the_last_index() {
+        let options = GifOptions { transparency: Some(128), ..Default::default() };
+        let pixels = [[200, 0, 0, 0], [200, 0, 0, 127], [200, 0, 0, 128], [0, 0, 200, 255]].concat().repeat(256);
+        let quantizer = Quantizer::new(&[&pixels], &options);
+
+        assert_eq!(quantizer.transparent, Some(255));
+        assert_eq!(quantizer.palette.len(), 256 * 3);
+        let indices = quantizer.map(&pixels, 4, Dither::None);
+        assert_eq!(indices[..2], [255, 255]);
+        assert!(indices[2..4].iter().all(|&index| index != 255));
+        assert_ne!(indices[2], indices[3]);
+
+        // Without a threshold alpha is ignored
+        let quantizer = Quantizer::new(&[&pixels], &GifOptions::default());
+        assert_eq!(quantizer.transparent, None);
+        let indices = quantizer.map(&pixels, 4, Dither::None);
+        assert_eq!(indices[..3], [indices[2]; 3]);
+    }
+
+    #[test]
+    fn dithering_mixes_colors_the_palette_lacks() {
+        // Learned from black and white only, which leaves gaps in the dark colors
+        let quantizer = Quantizer::new(&[&[[0, 0, 0, 255], [255, 255, 255, 255]].concat().repeat(2048)], &GifOptions::default());
+        let value = 11.0;
+        let nearest = (0..=255).map(|index| (quantizer.color(index)[0] - value).abs()).fold(f32::MAX, f32::min);
+        assert!(nearest >= 2.0, "the palette has {} within {}", value, nearest);
+
+        let (width, height) = (16, 16);
+        let pixels = gray(value as u8, width * height);
+        let mean = |indices: &[u8]| indices.iter().map(|&index| quantizer.color(index)[0]).sum::<f32>() / indices.len() as f32;
+        let distinct = |indices: &[u8]| {
+            let mut indices = indices.to_vec();
+            indices.sort();
+            indices.dedup();
+            indices.len()
+        };
+
+        let plain = quantizer.map(&pixels, width, Dither::None);
+        assert_eq!(distinct(&plain), 1);
+        assert!((mean(&plain) - value).abs() >= 2.0);
+
+        let floyd_steinberg = quantizer.map(&pixels, width, Dither::FloydSteinberg);
+        assert!(distinct(&floyd_steinberg) > 1);
+        assert!((mean(&floyd_steinberg) - value).abs() < 0.5, "{}", mean(&floyd_steinberg));
+
+        // Bayer repeats every 8 pixels in both directions
+        let bayer = quantizer.map(&pixels, width, Dither::Bayer);
+        assert!(distinct(&bayer) > 1);
+        assert!((mean(&bayer) - value).abs() < 2.0, "{}", mean(&bayer));
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(bayer[y * width + x], bayer[(y % 8) * width + x % 8]);
+            }
+        }
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    ops::Range,
+    thread::JoinHandle,
+};
+
+use crate::{
+    api::{audio::AudioBuffer, image_sequence::format_pattern, video::VideoSettings},
+    error::{Error, Result},
+};
+
+use super::{remove_partial, ChunkedExport, Export};
+
+/// File format of the images written by an [`ImageSequenceExporter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceFormat {
+    /// 8-bit RGBA PNG
+    Png,
+    /// 16-bit RGBA PNG. Frames are rendered with 8 bits per channel, which are
+    /// scaled up to 16 bits, so this only helps tools that expect 16-bit input
+    Png16,
+    /// 32-bit float RGBA OpenEXR with linear colors
+    Exr,
+}
+
+impl SequenceFormat {
+    /// EXR for `.exr` files, PNG for everything else
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("exr") => SequenceFormat::Exr,
+            _ => SequenceFormat::Png,
+        }
+    }
+}
+
+/// Writes every frame to its own numbered image file, without needing
+/// ffmpeg. Images are encoded on a pool of threads while the next frames
+/// render. The audio of the video is left out.
+///
+/// Frames are rendered with 8 bits per channel, [`SequenceFormat::Png16`] and
+/// [`SequenceFormat::Exr`] keep that precision but are easier to grade
+///
+/// Images only get their name once they are completely written, so an
+/// interrupted render never leaves a broken image behind and can be picked up
+/// again with [`ImageSequenceExporter::resume`]
+///
+/// ```ignore
+/// video.render(ImageSequenceExporter::new("frames/frame_%05d.png").start_number(1001))
+/// ```
+pub struct ImageSequenceExporter {
+    pattern: String,
+    format: SequenceFormat,
+    start_number: usize,
+    threads: usize,
+    resume: bool,
+
+    resolution: (u32, u32),
+    /// Index of the next image
+    frame: usize,
+    /// Images that already exist when resuming
+    skipped: BTreeSet<usize>,
+    sender: Option<SyncSender<(PathBuf, Vec<u8>)>>,
+    workers: Vec<JoinHandle<()>>,
+    /// First error hit by a worker
+    error: Arc<Mutex<Option<Error>>>,
+}
+
+impl ImageSequenceExporter {
+    /// `pattern` is the path of the images with a frame number placeholder
+    /// like `%05d`, the format follows its extension
+    pub fn new(pattern: impl ToString) -> Self {
+        let pattern = pattern.to_string();
+        Self {
+            format: SequenceFormat::from_path(&pattern),
+            pattern,
+            start_number: 0,
+            threads: std::thread::available_parallelism().map_or(4, |threads| threads.get()),
+            resume: false,
+
+            resolution: (0, 0),
+            frame: 0,
+            skipped: BTreeSet::new(),
+            sender: None,
+            workers: Vec::new(),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn format(mut self, format: SequenceFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Number of the first frame
+    pub fn start_number(mut self, start_number: usize) -> Self {
+        self.start_number = start_number;
+        self
+    }
+
+    /// Number of images encoded at the same time
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Skips frames whose image already exists, to continue a render that was
+    /// interrupted. Images are also kept when the render fails, so it can be
+    /// resumed again
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        PathBuf::from(format_pattern(&self.pattern, self.start_number + index))
+    }
+
+    /// Waits for the images that are still being written
+    fn join(&mut self) {
+        // Workers stop once the channel is empty and closed
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                self.error.lock().unwrap().get_or_insert(Error::Codec("an image sequence worker panicked".to_string()));
+            }
+        }
+    }
+}
+
+impl Export for ImageSequenceExporter {
+    fn begin(&mut self, settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+        self.resolution = settings.resolution;
+
+        if let Some(parent) = Path::new(&format_pattern(&self.pattern, self.start_number)).parent() {
+            std::fs::create_dir_all(parent).map_err(|source| Error::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+
+        // Frames waiting for a thread are kept in memory, the channel limits how many
+        let (sender, receiver) = sync_channel::<(PathBuf, Vec<u8>)>(self.threads * 2);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        self.workers = (0..self.threads)
+            .map(|_| {
+                let receiver = receiver.clone();
+                let error = self.error.clone();
+                let (format, resolution) = (self.format, self.resolution);
+                std::thread::spawn(move || work(&receiver, format, resolution, &error))
+            })
+            .collect();
+        self.sender = Some(sender);
+        Ok(())
+    }
+
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+        // Stops the render at the first image that couldn't be written, instead of after the last frame
+        if let Some(err) = self.error.lock().unwrap().take() {
+            return Err(err);
+        }
+
+        while self.skipped.contains(&self.frame) {
+            self.frame += 1;
+        }
+        let path = self.path(self.frame);
+        self.frame += 1;
+
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send((path, frame.to_vec()))
+            .map_err(|_| Error::Codec("all image sequence workers stopped".to_string()))
+    }
+
+    fn skip_frame(&mut self, index: u64) -> bool {
+        let skip = self.resume && self.path(index as usize).is_file();
+        if skip {
+            self.skipped.insert(index as usize);
+        }
+        skip
+    }
+
+    fn end(mut self) -> Result<()> {
+        self.join();
+
+        let result = match self.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        };
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+
+    fn abort(mut self) {
+        self.join();
+
+        if self.resume {
+            return;
+        }
+        for frame in 0..self.frame {
+            remove_partial(&self.path(frame));
+        }
+    }
+}
+
+impl ChunkedExport for ImageSequenceExporter {
+    type Segment = ImageSequenceExporter;
+
+    fn segment(&self, _index: usize, frames: Range<u64>) -> Self {
+        Self::new(self.pattern.clone())
+            .format(self.format)
+            .start_number(self.start_number + frames.start as usize)
+            .threads(self.threads)
+            .resume(self.resume)
+    }
+
+    /// The images of every segment already have their final names
+    fn concat(self, _settings: VideoSettings, _audio: Option<AudioBuffer>, _segments: &[Range<u64>]) -> Result<()> {
+        Ok(())
+    }
+
+    fn abort_segments(self, segments: &[Range<u64>]) {
+        if self.resume {
+            return;
+        }
+        for frame in segments.iter().flat_map(|frames| frames.clone()) {
+            remove_partial(&self.path(frame as usize));
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<(PathBuf, Vec<u8>)>>, format: SequenceFormat, resolution: (u32, u32), error: &Mutex<Option<Error>>) {
+    loop {
+        // The lock is only held while waiting for the next frame
+        let job = receiver.lock().unwrap().recv();
+        let (path, frame) = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        if let Err(err) = write_image(&path, format, resolution, frame) {
+            error.lock().unwrap().get_or_insert(err);
+        }
+    }
+}
+
+fn write_image(path: &Path, format: SequenceFormat, (width, height): (u32, u32), frame: Vec<u8>) -> Result<()> {
+    let length = frame.len();
+    let mismatch = || Error::Codec(format!("frame has {} bytes, {}x{} RGBA needs {}", length, width, height, width as usize * height as usize * 4));
+
+    let image = match format {
+        SequenceFormat::Png => ::image::DynamicImage::ImageRgba8(::image::RgbaImage::from_raw(width, height, frame).ok_or_else(mismatch)?),
+        SequenceFormat::Png16 => ::image::DynamicImage::ImageRgba16(
+            ::image::ImageBuffer::from_raw(width, height, frame.iter().map(|&value| value as u16 * 257).collect()).ok_or_else(mismatch)?,
+        ),
+        SequenceFormat::Exr => ::image::DynamicImage::ImageRgba32F(
+            ::image::Rgba32FImage::from_raw(
+                width,
+                height,
+                frame
+                    .chunks_exact(4)
+                    .flat_map(|pixel| [srgb_to_linear(pixel[0]), srgb_to_linear(pixel[1]), srgb_to_linear(pixel[2]), pixel[3] as f32 / 255.0])
+                    .collect(),
+            )
+            .ok_or_else(mismatch)?,
+        ),
+    };
+
+    let image_format = match format {
+        SequenceFormat::Png | SequenceFormat::Png16 => ::image::ImageFormat::Png,
+        SequenceFormat::Exr => ::image::ImageFormat::OpenExr,
+    };
+    // Written under another name first, a resumed render skips every image that has its name
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    if let Err(err) = image.save_with_format(&partial, image_format) {
+        remove_partial(&partial);
+        return Err(err.into());
+    }
+    std::fs::rename(&partial, path).map_err(|source| {
+        remove_partial(&partial);
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    })
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        api::video::VideoSettings,
+        error::Error,
+        io::{ChunkedExport, Export},
+    };
+
+    use super::ImageSequenceExporter;
+
+    /// Empty directory only used by the test called `name`
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vide_image_sequence_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// Value of the first channel of the image at `path`
+    fn pixel(path: &Path) -> u8 {
+        ::image::open(path).unwrap().to_rgba8().get_pixel(0, 0).0[0]
+    }
+
+    fn settings() -> VideoSettings {
+        VideoSettings {
+            resolution: (2, 2),
+            ..Default::default()
+        }
+    }
+
+    fn frame(value: u8) -> Vec<u8> {
+        [value, value, value, 255].repeat(4)
+    }
+
+    #[test]
+    fn images_are_numbered_from_start_number() {
+        let dir = temp_dir("numbered");
+        let mut exporter = ImageSequenceExporter::new(dir.join("frames/frame_%04d.png").display()).start_number(1001).threads(2);
+        exporter.begin(settings(), None).unwrap();
+        for value in [10, 20, 30] {
+            exporter.push_frame(false, &frame(value)).unwrap();
+        }
+        exporter.end().unwrap();
+
+        let frames = dir.join("frames");
+        assert_eq!(files(&frames), ["frame_1001.png", "frame_1002.png", "frame_1003.png"]);
+        assert_eq!(pixel(&frames.join("frame_1002.png")), 20);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resume_skips_existing_images() {
+        let dir = temp_dir("resume");
+        for existing in ["frame_1.png", "frame_3.png"] {
+            ::image::RgbaImage::from_pixel(2, 2, ::image::Rgba([9, 9, 9, 255])).save(dir.join(existing)).unwrap();
+        }
+
+        let mut exporter = ImageSequenceExporter::new(dir.join("frame_%d.png").display()).resume(true);
+        exporter.begin(settings(), None).unwrap();
+        let mut skipped = Vec::new();
+        for index in 0..5 {
+            let skip = exporter.skip_frame(index);
+            if !skip {
+                exporter.push_frame(false, &frame(10 + index as u8)).unwrap();
+            }
+            skipped.push(skip);
+        }
+        exporter.end().unwrap();
+
+        assert_eq!(skipped, [false, true, false, true, false]);
+        assert_eq!(files(&dir), ["frame_0.png", "frame_1.png", "frame_2.png", "frame_3.png", "frame_4.png"]);
+        let values = (0..5).map(|index| pixel(&dir.join(format!("frame_{}.png", index)))).collect::<Vec<_>>();
+        assert_eq!(values, [10, 9, 12, 9, 14]);
+
+        // Without resume every image is written again
+        let mut exporter = ImageSequenceExporter::new(dir.join("frame_%d.png").display());
+        exporter.begin(settings(), None).unwrap();
+        assert!(!exporter.skip_frame(1));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn images_are_renamed_once_written() {
+        let dir = temp_dir("partial");
+        // Blocks the final name of the second image
+        std::fs::create_dir_all(dir.join("frame_1.png/blocked")).unwrap();
+
+        let mut exporter = ImageSequenceExporter::new(dir.join("frame_%d.png").display()).threads(1).resume(true);
+        exporter.begin(settings(), None).unwrap();
+        exporter.push_frame(false, &frame(1)).unwrap();
+        exporter.push_frame(false, &frame(2)).unwrap();
+
+        assert!(matches!(exporter.end(), Err(Error::Io { path, .. }) if path == dir.join("frame_1.png")));
+        // The first image is kept for resuming, the second one is never left half written
+        assert_eq!(files(&dir), ["frame_0.png", "frame_1.png"]);
+        assert_eq!(pixel(&dir.join("frame_0.png")), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn abort_removes_the_images() {
+        let dir = temp_dir("abort");
+        let mut exporter = ImageSequenceExporter::new(dir.join("frame_%d.png").display());
+        exporter.begin(settings(), None).unwrap();
+        exporter.push_frame(false, &frame(1)).unwrap();
+        exporter.push_frame(false, &frame(2)).unwrap();
+        exporter.abort();
+        assert!(files(&dir).is_empty());
+
+        // Kept when resuming, the render can pick them up again
+        let mut exporter = ImageSequenceExporter::new(dir.join("frame_%d.png").display()).resume(true);
+        exporter.begin(settings(), None).unwrap();
+        exporter.push_frame(false, &frame(1)).unwrap();
+        exporter.abort();
+        assert_eq!(files(&dir), ["frame_0.png"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segments_write_their_own_frames() {
+        let dir = temp_dir("segments");
+        let exporter = ImageSequenceExporter::new(dir.join("frame_%03d.png").display()).start_number(10);
+        let segments = [0..2, 2..5];
+
+        for (index, frames) in segments.iter().enumerate() {
+            let mut segment = exporter.segment(index, frames.clone());
+            segment.begin(settings(), None).unwrap();
+            for frame_index in frames.clone() {
+                segment.push_frame(false, &frame(frame_index as u8)).unwrap();
+            }
+            segment.end().unwrap();
+        }
+        assert_eq!(files(&dir), ["frame_010.png", "frame_011.png", "frame_012.png", "frame_013.png", "frame_014.png"]);
+        assert_eq!(pixel(&dir.join("frame_013.png")), 3);
+
+        exporter.abort_segments(&segments);
+        assert!(files(&dir).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Stdout, Write},
+    path::{Path, PathBuf},
+};
+
+use log::info;
+
+use crate::{
+    api::{audio::AudioBuffer, video::VideoSettings},
+    error::{Error, Result},
+};
+
+use super::{frame_rate, remove_partial, yuv::rgba_to_yuv, Export};
+
+/// Pixel format of the frames written by a [`Y4mExporter`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Y4mFormat {
+    /// Chroma at half the width and height, what almost all video uses
+    #[default]
+    Yuv420,
+    /// Chroma at full resolution
+    Yuv444,
+    /// Chroma at full resolution and an alpha plane, only ffmpeg reads it
+    Yuva444,
+}
+
+/// Writes frames to a file, a named pipe, stdout or any other [`Write`]
+struct Stream<W: Write> {
+    output: W,
+    /// File the output was opened from, `None` for stdout and writers
+    path: Option<PathBuf>,
+}
+
+impl<W: Write> Stream<W> {
+    fn error(&self, source: std::io::Error) -> Error {
+        Error::Io {
+            path: self.path.clone().unwrap_or_else(|| PathBuf::from("-")),
+            source,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        match self.output.write_all(data) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.error(err)),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.output.flush() {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.error(err)),
+        }
+    }
+
+    /// Removes the output when it is a regular file, pipes and writers are left alone
+    fn abort(self) {
+        let Self { output, path } = self;
+        drop(output);
+
+        if let Some(path) = path {
+            if path.metadata().is_ok_and(|metadata| metadata.is_file()) {
+                remove_partial(&path);
+            }
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<Stream<BufWriter<File>>> {
+    // Named pipes that already exist are opened for writing instead of being replaced
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(!is_pipe(path))
+        .open(path)
+        .map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+    Ok(Stream {
+        output: BufWriter::new(file),
+        path: Some(path.to_path_buf()),
+    })
+}
+
+fn is_pipe(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        path.metadata().is_ok_and(|metadata| metadata.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// Writes the video as an uncompressed YUV4MPEG2 stream, which ffmpeg,
+/// gstreamer, x264 and most other video tools read from a pipe. The audio of
+/// the video is left out.
+///
+/// Colors are converted to BT.709 with limited range
+///
+/// ```ignore
+/// // vide-render | ffmpeg -i - output.mkv
+/// video.render(Y4mExporter::stdout())
+/// ```
+pub struct Y4mExporter<W: Write> {
+    stream: Stream<W>,
+    format: Y4mFormat,
+    resolution: (usize, usize),
+}
+
+impl<W: Write> Y4mExporter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            stream: Stream { output, path: None },
+            format: Y4mFormat::Yuv420,
+            resolution: (0, 0),
+        }
+    }
+
+    pub fn format(mut self, format: Y4mFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl Y4mExporter<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl Y4mExporter<BufWriter<File>> {
+    /// Writes to a file or a named pipe
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            stream: open(path.as_ref())?,
+            format: Y4mFormat::Yuv420,
+            resolution: (0, 0),
+        })
+    }
+}
+
+impl<W: Write + Send> Export for Y4mExporter<W> {
+    fn begin(&mut self, settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+        let (width, height) = settings.resolution;
+        self.resolution = (width as usize, height as usize);
+
+        let (numerator, denominator) = frame_rate(settings.fps);
+        let chroma = match self.format {
+            // Chroma samples sit between the luma samples they are averaged from
+            Y4mFormat::Yuv420 => "420jpeg",
+            Y4mFormat::Yuv444 => "444",
+            Y4mFormat::Yuva444 => "444alpha",
+        };
+        let header = format!("YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE=LIMITED\n", width, height, numerator, denominator, chroma);
+        self.stream.write(header.as_bytes())
+    }
+
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+        let mut data = b"FRAME\n".to_vec();
+        data.extend(rgba_to_yuv(frame, self.resolution, self.format == Y4mFormat::Yuv420));
+        if self.format == Y4mFormat::Yuva444 {
+            data.extend(frame.chunks_exact(4).map(|pixel| pixel[3]));
+        }
+
+        self.stream.write(&data)
+    }
+
+    fn end(mut self) -> Result<()> {
+        let result = self.stream.flush();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+
+    fn abort(self) {
+        self.stream.abort();
+    }
+}
+
+/// Writes the frames as they are rendered, RGBA8 in sRGB with tightly packed
+/// rows and nothing in between. The stream has no header, the reader has to
+/// be told the resolution and frame rate, which are logged when the export
+/// starts. The audio of the video is left out.
+///
+/// ```ignore
+/// // vide-render | ffmpeg -f rawvideo -pixel_format rgba -video_size 1920x1080 -framerate 60 -i - output.mkv
+/// video.render(RawExporter::stdout())
+/// ```
+pub struct RawExporter<W: Write> {
+    stream: Stream<W>,
+}
+
+impl<W: Write> RawExporter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            stream: Stream { output, path: None },
+        }
+    }
+}
+
+impl RawExporter<Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl RawExporter<BufWriter<File>> {
+    /// Writes to a file or a named pipe
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            stream: open(path.as_ref())?,
+        })
+    }
+}
+
+impl<W: Write + Send> Export for RawExporter<W> {
+    fn begin(&mut self, settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+        info!(
+            "Writing raw video, read it with: -f rawvideo -pixel_format rgba -video_size {}x{} -framerate {}",
+            settings.resolution.0, settings.resolution.1, settings.fps
+        );
+        Ok(())
+    }
+
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+        self.stream.write(frame)
+    }
+
+    fn end(mut self) -> Result<()> {
+        let result = self.stream.flush();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+
+    fn abort(self) {
+        self.stream.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{api::video::VideoSettings, io::Export};
+
+    use super::{frame_rate, Y4mExporter, Y4mFormat};
+
+    fn settings(fps: f64, resolution: (u32, u32)) -> VideoSettings {
+        VideoSettings {
+            fps,
+            resolution,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ntsc_rates_are_exact_fractions() {
+        assert_eq!(frame_rate(30.0), (30, 1));
+        assert_eq!(frame_rate(29.97), (30000, 1001));
+        assert_eq!(frame_rate(23.976), (24000, 1001));
+        assert_eq!(frame_rate(59.94), (60000, 1001));
+        assert_eq!(frame_rate(12.5), (12500, 1000));
+    }
+
+    #[test]
+    fn y4m_frames_hold_bt709_limited_range_planes() {
+        let mut output = Vec::new();
+        let mut exporter = Y4mExporter::new(&mut output);
+        exporter.begin(settings(29.97, (2, 2)), None).unwrap();
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255], [0, 0, 0]];
+        for [r, g, b] in colors {
+            exporter.push_frame(false, &[r, g, b, 255].repeat(4)).unwrap();
+        }
+        exporter.end().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H2 F30000:1001 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert_eq!(&output[..header.len()], header);
+
+        // Four luma samples and one sample of each chroma plane per frame
+        let frames = output[header.len()..].chunks(b"FRAME\n".len() + 6).collect::<Vec<_>>();
+        assert_eq!(frames.len(), colors.len());
+        assert!(frames.iter().all(|frame| frame.starts_with(b"FRAME\n")));
+        let planes = frames.iter().map(|frame| frame[6..].to_vec()).collect::<Vec<_>>();
+        assert_eq!(
+            planes,
+            [
+                [63, 63, 63, 63, 102, 240],
+                [173, 173, 173, 173, 42, 26],
+                [32, 32, 32, 32, 240, 118],
+                [235, 235, 235, 235, 128, 128],
+                [16, 16, 16, 16, 128, 128],
+            ]
+        );
+    }
+
+    #[test]
+    fn y4m_chroma_is_averaged_or_kept_with_alpha() {
+        let mut output = Vec::new();
+        let mut exporter = Y4mExporter::new(&mut output);
+        exporter.begin(settings(60.0, (2, 1)), None).unwrap();
+        exporter.push_frame(false, &[255, 0, 0, 255, 0, 0, 255, 255]).unwrap();
+        exporter.end().unwrap();
+        // Red and blue average to purple, the missing row repeats the last one
+        assert_eq!(output, [&b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\nFRAME\n"[..], &[63, 32, 171, 179]].concat());
+
+        let mut output = Vec::new();
+        let mut exporter = Y4mExporter::new(&mut output).format(Y4mFormat::Yuva444);
+        exporter.begin(settings(60.0, (2, 1)), None).unwrap();
+        exporter.push_frame(false, &[255, 0, 0, 255, 0, 0, 255, 0]).unwrap();
+        exporter.end().unwrap();
+        // Planes are Y, Cb, Cr and alpha
+        assert_eq!(output, [&b"YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444alpha XCOLORRANGE=LIMITED\nFRAME\n"[..], &[63, 32, 102, 240, 240, 118, 255, 0]].concat());
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::BufWriter,
+    mem::MaybeUninit,
+    path::PathBuf,
+    sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+    thread::JoinHandle,
+};
+
+use vpx_sys::*;
+use webm::mux::{Segment, Track, VideoCodecId, VideoTrack, Writer};
+
+use crate::{api::{audio::AudioBuffer, video::VideoSettings}, error::{Error, Result}};
+
+use super::{frame_rate, remove_partial, yuv::rgba_to_yuv, Export};
+
+/// Seconds between keyframes. Players can only seek to keyframes
+const KEYFRAME_INTERVAL: f64 = 2.0;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WebmCodec {
+    Vp8,
+    #[default]
+    Vp9,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebmOptions {
+    pub codec: WebmCodec,
+    /// Target bitrate in kilobits per second
+    pub bitrate: u32,
+}
+
+impl Default for WebmOptions {
+    fn default() -> Self {
+        Self {
+            codec: WebmCodec::Vp9,
+            bitrate: 5000,
+        }
+    }
+}
+
+/// Exports a WebM video through libvpx, without needing ffmpeg. The audio of
+/// the video is left out.
+///
+/// The alpha channel is dropped as well: transparent VP8/VP9 stores alpha in
+/// Matroska BlockAdditions, which the `webm` crate can't write. Overlays need
+/// vide_ffmpeg's `FFmpegExporter` with `EncoderOptions::vp9_alpha()`
+///
+/// libvpx is linked from the system, found through pkg-config or the
+/// `VPX_LIB_DIR` and `VPX_INCLUDE_DIR` environment variables. Setting
+/// `VPX_STATIC=1` links it statically, so the binary runs without it
+///
+/// Frames are encoded on a thread of their own, which owns the encoder
+pub struct WebmExporter {
+    path: PathBuf,
+    options: WebmOptions,
+
+    frames: Option<SyncSender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<()>>>,
+}
+
+impl WebmExporter {
+    pub fn new(path: impl Into<PathBuf>, options: WebmOptions) -> Self {
+        Self {
+            path: path.into(),
+            options,
+
+            frames: None,
+            worker: None,
+        }
+    }
+
+    /// Waits for the worker to write the frames it has, and the result of that
+    fn join(&mut self) -> Result<()> {
+        // The worker finishes the file once the channel is empty and closed
+        drop(self.frames.take());
+        match self.worker.take() {
+            Some(worker) => worker.join().unwrap_or_else(|_| Err(Error::Codec("the WebM encoder panicked".to_string()))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Export for WebmExporter {
+    fn begin(&mut self, settings: VideoSettings, _audio: Option<AudioBuffer>) -> Result<()> {
+        let (ready_sender, ready) = channel();
+        let (frames, receiver) = sync_channel(2);
+        let (path, options) = (self.path.clone(), self.options);
+        let (resolution, fps) = (settings.resolution, settings.fps);
+
+        self.worker = Some(std::thread::spawn(move || work(path, options, resolution, fps, ready_sender, receiver)));
+        self.frames = Some(frames);
+
+        // The worker only hangs up without being ready when it failed to start
+        match ready.recv() {
+            Ok(()) => Ok(()),
+            Err(_) => Err(self.join().err().unwrap_or_else(|| Error::Codec("the WebM encoder didn't start".to_string()))),
+        }
+    }
+
+    fn push_frame(&mut self, _keyframe: bool, frame: &[u8]) -> Result<()> {
+        let sent = self.frames.as_ref().is_some_and(|frames| frames.send(frame.to_vec()).is_ok());
+        if sent {
+            return Ok(());
+        }
+
+        // The worker only stops early after an error
+        Err(self.join().err().unwrap_or_else(|| Error::Codec("the WebM encoder stopped".to_string())))
+    }
+
+    fn end(mut self) -> Result<()> {
+        let result = self.join();
+        if result.is_err() {
+            remove_partial(&self.path);
+        }
+        result
+    }
+
+    fn abort(mut self) {
+        // The worker closes the file before it is removed
+        let _ = self.join();
+        remove_partial(&self.path);
+    }
+}
+
+fn work(path: PathBuf, options: WebmOptions, resolution: (u32, u32), fps: f64, ready: Sender<()>, frames: Receiver<Vec<u8>>) -> Result<()> {
+    let mut worker = Worker::new(path, options, resolution, fps)?;
+    let _ = ready.send(());
+
+    for frame in frames {
+        worker.push_frame(&frame)?;
+    }
+    worker.finish()
+}
+
+/// Encodes the frames and muxes them, on the thread of the exporter
+struct Worker {
+    path: PathBuf,
+    resolution: (u32, u32),
+    /// Frames per second as a fraction
+    frame_rate: (u64, u64),
+    /// Frames between keyframes
+    keyframe_interval: u64,
+    frame: u64,
+
+    encoder: Encoder,
+    segment: Segment<Writer<BufWriter<File>>>,
+    track: VideoTrack,
+}
+
+impl Worker {
+    fn new(path: PathBuf, options: WebmOptions, resolution: (u32, u32), fps: f64) -> Result<Self> {
+        let frame_rate = frame_rate(fps);
+        let encoder = Encoder::new(&options, resolution, frame_rate)?;
+
+        let file = File::create(&path).map_err(|source| Error::Io { path: path.clone(), source })?;
+        let mut segment = Segment::new(Writer::new(BufWriter::new(file))).ok_or_else(|| Error::Codec(format!("unable to start a WebM segment in {}", path.display())))?;
+        let codec = match options.codec {
+            WebmCodec::Vp8 => VideoCodecId::VP8,
+            WebmCodec::Vp9 => VideoCodecId::VP9,
+        };
+        let track = segment.add_video_track(resolution.0, resolution.1, None, codec);
+
+        Ok(Self {
+            path,
+            resolution,
+            frame_rate,
+            keyframe_interval: (fps * KEYFRAME_INTERVAL).round().max(1.0) as u64,
+            frame: 0,
+
+            encoder,
+            segment,
+            track,
+        })
+    }
+
+    fn push_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let (width, height) = (self.resolution.0 as usize, self.resolution.1 as usize);
+        if frame.len() != width * height * 4 {
+            return Err(Error::Codec(format!("frame has {} bytes, {}x{} RGBA needs {}", frame.len(), width, height, width * height * 4)));
+        }
+
+        let pts = self.frame as i64;
+        let keyframe = self.frame.is_multiple_of(self.keyframe_interval);
+        self.frame += 1;
+
+        let packets = self.encoder.encode(Some(&rgba_to_yuv(frame, (width, height), true)), pts, keyframe)?;
+        self.write_packets(packets)
+    }
+
+    fn write_packets(&mut self, packets: Vec<Packet>) -> Result<()> {
+        for packet in packets {
+            // Matroska timestamps are in nanoseconds
+            let (numerator, denominator) = self.frame_rate;
+            let timestamp = (packet.pts as u64 * 1_000_000_000 * denominator + numerator / 2) / numerator;
+            if !self.track.add_frame(&packet.data, timestamp, packet.keyframe) {
+                return Err(Error::Codec(format!("unable to write frame {} to {}", packet.pts, self.path.display())));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let packets = self.encoder.encode(None, -1, false)?;
+        self.write_packets(packets)?;
+
+        // libwebm takes the duration from the last frame
+        match self.segment.finalize(None) {
+            true => Ok(()),
+            false => Err(Error::Codec(format!("unable to finish {}", self.path.display()))),
+        }
+    }
+}
+
+/// An encoded frame
+struct Packet {
+    data: Vec<u8>,
+    /// Index of the frame
+    pts: i64,
+    keyframe: bool,
+}
+
+/// A libvpx encoder for I420 images
+struct Encoder {
+    context: vpx_codec_ctx_t,
+    resolution: (u32, u32),
+}
+
+impl Encoder {
+    fn new(options: &WebmOptions, (width, height): (u32, u32), (numerator, denominator): (u64, u64)) -> Result<Self> {
+        let interface = unsafe {
+            match options.codec {
+                WebmCodec::Vp8 => vpx_codec_vp8_cx(),
+                WebmCodec::Vp9 => vpx_codec_vp9_cx(),
+            }
+        };
+
+        let mut config = MaybeUninit::<vpx_codec_enc_cfg_t>::zeroed();
+        check(unsafe { vpx_codec_enc_config_default(interface, config.as_mut_ptr(), 0) })?;
+        let mut config = unsafe { config.assume_init() };
+        config.g_w = width;
+        config.g_h = height;
+        // Timestamps count frames, so a tick is the duration of one
+        config.g_timebase.num = denominator as i32;
+        config.g_timebase.den = numerator as i32;
+        config.rc_target_bitrate = options.bitrate;
+        config.g_threads = std::thread::available_parallelism().map_or(4, |threads| threads.get()) as u32;
+        // Keyframes are forced by `Worker::push_frame`, so players can seek
+        // in steps of `KEYFRAME_INTERVAL`
+        config.kf_mode = vpx_kf_mode::VPX_KF_DISABLED;
+        config.g_lag_in_frames = 0;
+        config.rc_dropframe_thresh = 0;
+
+        let mut context = MaybeUninit::<vpx_codec_ctx_t>::zeroed();
+        check(unsafe { vpx_codec_enc_init_ver(context.as_mut_ptr(), interface, &config, 0, VPX_ENCODER_ABI_VERSION as i32) })?;
+
+        Ok(Self {
+            context: unsafe { context.assume_init() },
+            resolution: (width, height),
+        })
+    }
+
+    /// Encodes an I420 image, `None` flushes the encoder. Returns the packets
+    /// that are ready
+    fn encode(&mut self, image: Option<&[u8]>, pts: i64, keyframe: bool) -> Result<Vec<Packet>> {
+        let mut wrapped = MaybeUninit::<vpx_image_t>::zeroed();
+        let image = match image {
+            Some(data) => {
+                let (width, height) = self.resolution;
+                // libvpx only reads the image, it just isn't declared const
+                let image = unsafe { vpx_img_wrap(wrapped.as_mut_ptr(), vpx_img_fmt::VPX_IMG_FMT_I420, width, height, 1, data.as_ptr() as *mut u8) };
+                if image.is_null() {
+                    return Err(Error::Codec(format!("libvpx can't encode {}x{} frames", width, height)));
+                }
+                image as *const vpx_image_t
+            }
+            None => std::ptr::null(),
+        };
+
+        let flags = if keyframe { VPX_EFLAG_FORCE_KF as vpx_enc_frame_flags_t } else { 0 };
+        check(unsafe { vpx_codec_encode(&mut self.context, image, pts, 1, flags, VPX_DL_GOOD_QUALITY as _) })?;
+
+        let mut packets = Vec::new();
+        let mut iterator = std::ptr::null();
+        loop {
+            let packet = unsafe { vpx_codec_get_cx_data(&mut self.context, &mut iterator) };
+            if packet.is_null() {
+                return Ok(packets);
+            }
+
+            let packet = unsafe { &*packet };
+            if packet.kind != vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT {
+                continue;
+            }
+            let frame = unsafe { &packet.data.frame };
+            packets.push(Packet {
+                data: unsafe { std::slice::from_raw_parts(frame.buf as *const u8, frame.sz as usize) }.to_vec(),
+                pts: frame.pts,
+                keyframe: frame.flags & VPX_FRAME_IS_KEY != 0,
+            });
+        }
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { vpx_codec_destroy(&mut self.context) };
+    }
+}
+
+fn check(result: vpx_codec_err_t) -> Result<()> {
+    if result == vpx_codec_err_t::VPX_CODEC_OK {
+        return Ok(());
+    }
+
+    let message = unsafe { CStr::from_ptr(vpx_codec_err_to_string(result)) };
+    Err(Error::Codec(format!("libvpx failed: {}", message.to_string_lossy())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use crate::{api::video::VideoSettings, io::Export};
+
+    use super::{WebmExporter, WebmOptions};
+
+    /// Runs ffprobe on the first video stream of `path`, returning its csv lines
+    fn probe(path: &std::path::Path, entries: &str) -> Vec<String> {
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0", "-count_frames", "-show_entries", entries, "-of", "csv=p=0"])
+            .arg(path)
+            .output()
+            .expect("ffprobe has to be installed to check the output");
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn ffprobe_decodes_every_frame() {
+        let path = std::env::temp_dir().join(format!("vide_webm_round_trip_{}.webm", std::process::id()));
+        let mut exporter = WebmExporter::new(&path, WebmOptions::default());
+
+        let settings = VideoSettings {
+            fps: 29.97,
+            resolution: (64, 48),
+            ..Default::default()
+        };
+        exporter.begin(settings, None).unwrap();
+        for frame in 0..150u32 {
+            let pixel = [(frame * 2) as u8, 0, 255 - (frame as u8), 255];
+            exporter.push_frame(false, &pixel.repeat(64 * 48)).unwrap();
+        }
+        exporter.end().unwrap();
+
+        let stream = probe(&path, "stream=codec_name,width,height,nb_read_frames");
+        // A keyframe every 60 frames, 1001/30000 seconds apart
+        let keyframes = probe(&path, "packet=pts_time,flags")
+            .iter()
+            .filter(|packet| packet.ends_with(",K_") || packet.ends_with(",K"))
+            .map(|packet| packet.split(',').next().unwrap().parse::<f64>().unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stream, ["vp9,64,48,150"]);
+        assert_eq!(keyframes, [0.0, 2.002, 4.004]);
+    }
+}
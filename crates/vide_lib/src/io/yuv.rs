@@ -0,0 +1,48 @@
+/// Converts RGBA frames to planar BT.709 limited range Y'CbCr. With
+/// `subsampled` the chroma planes have half the width and height (I420),
+/// otherwise they are as large as the luma plane
+pub(crate) fn rgba_to_yuv(frame: &[u8], (width, height): (usize, usize), subsampled: bool) -> Vec<u8> {
+    let (chroma_width, chroma_height) = match subsampled {
+        true => (width.div_ceil(2), height.div_ceil(2)),
+        false => (width, height),
+    };
+    let mut yuv = vec![0u8; width * height + chroma_width * chroma_height * 2];
+    let (luma, chroma) = yuv.split_at_mut(width * height);
+    let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
+
+    let pixel = |x: usize, y: usize| {
+        let i = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        [frame[i] as f32 / 255.0, frame[i + 1] as f32 / 255.0, frame[i + 2] as f32 / 255.0]
+    };
+    let luma_of = |[r, g, b]: [f32; 3]| 0.2126 * r + 0.7152 * g + 0.0722 * b;
+
+    for y in 0..height {
+        for x in 0..width {
+            luma[y * width + x] = (16.0 + 219.0 * luma_of(pixel(x, y))).round() as u8;
+        }
+    }
+
+    let samples: &[(usize, usize)] = match subsampled {
+        true => &[(0, 0), (1, 0), (0, 1), (1, 1)],
+        false => &[(0, 0)],
+    };
+    let step = if subsampled { 2 } else { 1 };
+
+    for y in 0..chroma_height {
+        for x in 0..chroma_width {
+            let mut average = [0.0f32; 3];
+            for (dx, dy) in samples {
+                let rgb = pixel(x * step + dx, y * step + dy);
+                for channel in 0..3 {
+                    average[channel] += rgb[channel] / samples.len() as f32;
+                }
+            }
+
+            let y_value = luma_of(average);
+            u_plane[y * chroma_width + x] = (128.0 + 224.0 * (average[2] - y_value) / 1.8556).round().clamp(0.0, 255.0) as u8;
+            v_plane[y * chroma_width + x] = (128.0 + 224.0 * (average[0] - y_value) / 1.5748).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    yuv
+}
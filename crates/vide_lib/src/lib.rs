@@ -5,10 +5,12 @@
 pub mod api;
 pub mod clip;
 pub mod effect;
+pub mod error;
 pub mod io;
 pub mod render;
 
 pub use cgmath;
+pub use wgpu;
 pub use paste;
 
 /// Contains everything you need to get started with Vide, just `use vide::prelude::*` and you're set!
@@ -16,8 +18,17 @@ pub mod prelude {
     pub use super::api::animation::ease;
     pub use super::api::animation::AnimatedPropertyBuilder as Animation;
     pub use super::api::animation::KeyframeTiming::*;
+    pub use super::api::audio::{Audio, AudioSource, Ducking};
+    pub use super::api::audio_analysis::{AnalysisSettings, AudioAnalysis, BandScale, Signal};
     pub use super::api::color::*;
+    pub use super::api::font::Font;
+    pub use super::api::image::{Image, ImageFilter, ImageFit, ImageSource};
+    pub use super::api::image_sequence::{ImageSequence, SequenceEnd, SequenceSource};
+    pub use super::api::video_source::{Footage, VideoSource};
     pub use super::api::rect::Rect;
+    pub use super::api::shader_effect::{ShaderEffect, ShaderEffectParameter, ShaderEffectSource};
+    pub use super::api::text::{Text, TextAlign, TextLayout, VerticalAlign};
+    pub use super::api::text_animator::{SelectorShape, TextAnimator, TextUnit};
     pub use super::api::transform::Transform;
     pub use super::api::video::*;
     pub use super::cubic_bezier;
@@ -4,14 +4,26 @@ use std::{
     time::Duration,
 };
 
+#[cfg(not(feature = "preview"))]
+use std::collections::VecDeque;
+
 use log::info;
 use wgpu::util::DeviceExt;
 
-use crate::{api::video::VideoSettings, clip::IntoFrame, effect::EffectRegistrationPacket};
+use crate::{api::video::{AdapterSelection, VideoSettings}, clip::IntoFrame, effect::EffectRegistrationPacket, error::{Error, Result}};
+
+/// Frames that can be rendered before the oldest one has to be read back. The
+/// GPU renders the next frames while earlier ones are copied and encoded
+#[cfg(not(feature = "preview"))]
+const STAGING_BUFFERS: usize = 3;
+
+/// Receives the result of mapping a staging buffer
+#[cfg(not(feature = "preview"))]
+type MapReceiver = futures_intrusive::channel::shared::OneshotReceiver<std::result::Result<(), wgpu::BufferAsyncError>>;
 
-pub(crate) type PushFunction = fn(&mut Box<dyn Any>, &Box<dyn Any>, u64);
+pub(crate) type PushFunction = fn(&mut Box<dyn Any>, &Box<dyn Any>, Time);
 pub(crate) type RenderFunction =
-    for<'a> fn(&'a mut Box<dyn Any>, MutexGuard<wgpu::RenderPass<'a>>, &wgpu::Device, &wgpu::Queue);
+    for<'a> fn(&'a mut Box<dyn Any>, MutexGuard<wgpu::RenderPass<'a>>, &wgpu::Device, &wgpu::Queue) -> Result<()>;
 
 /// Timing information needed for rendering
 #[derive(Default, Debug, Clone, Copy)]
@@ -62,7 +74,7 @@ pub enum RenderEvent<'a> {
         // Render effect
         id: usize,
         params: &'a Box<dyn Any>,
-        frame: u64,
+        time: Time,
     },
 }
 
@@ -70,6 +82,49 @@ pub trait Render {
     fn render(&self, renderer: &mut Renderer);
 }
 
+/// Picks the adapter asked for in `settings` and logs which one it is, so
+/// renders on other machines can use the same one
+fn select_adapter(instance: &wgpu::Instance, settings: &VideoSettings, surface: Option<&wgpu::Surface>) -> Result<wgpu::Adapter> {
+    let request = |power_preference, force_fallback_adapter| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference,
+            force_fallback_adapter,
+            compatible_surface: surface,
+        }))
+    };
+
+    let adapter = match settings.adapter {
+        AdapterSelection::HighPerformance => request(wgpu::PowerPreference::HighPerformance, false),
+        AdapterSelection::LowPower => request(wgpu::PowerPreference::LowPower, false),
+        // wgpu only considers CPU adapters (lavapipe, llvmpipe, WARP) as fallback
+        AdapterSelection::Software => request(wgpu::PowerPreference::LowPower, true),
+        AdapterSelection::Index(index) => instance
+            .enumerate_adapters(settings.backends)
+            .nth(index)
+            .filter(|adapter| surface.is_none_or(|surface| adapter.is_surface_supported(surface))),
+    };
+
+    let adapter = adapter.ok_or_else(|| {
+        let available = instance
+            .enumerate_adapters(settings.backends)
+            .enumerate()
+            .map(|(index, adapter)| format!("{}: {}", index, describe_adapter(&adapter.get_info())))
+            .collect::<Vec<_>>();
+
+        Error::Adapter(match available.is_empty() {
+            true => format!("no adapter found for backends {:?}", settings.backends),
+            false => format!("no adapter matches {:?}, available are {}", settings.adapter, available.join(", ")),
+        })
+    })?;
+
+    info!("Rendering on {}", describe_adapter(&adapter.get_info()));
+    Ok(adapter)
+}
+
+fn describe_adapter(info: &wgpu::AdapterInfo) -> String {
+    format!("{} ({:?} on {:?}, vendor {:#06x}, device {:#06x})", info.name, info.device_type, info.backend, info.vendor, info.device)
+}
+
 pub struct Renderer {
     pub settings: VideoSettings,
     pub screen_matrix: cgmath::Matrix4<f32>,
@@ -88,8 +143,19 @@ pub struct Renderer {
     unpadded_bytes_per_row: u32,
     #[cfg(not(feature = "preview"))]
     padded_bytes_per_row: u32,
+    /// Ring of buffers rendered frames are copied to, see [`STAGING_BUFFERS`]
+    #[cfg(not(feature = "preview"))]
+    out_buffers: Vec<wgpu::Buffer>,
+    /// Buffer the next frame is copied to
     #[cfg(not(feature = "preview"))]
-    out_buffer: wgpu::Buffer,
+    next_buffer: usize,
+    /// Frames that can be in flight at once, `1` reads every frame back before the next one renders
+    #[cfg(not(feature = "preview"))]
+    max_in_flight: usize,
+    /// Buffers holding frames that weren't read yet, oldest first, with the
+    /// submission that fills them and the result of mapping them
+    #[cfg(not(feature = "preview"))]
+    in_flight: VecDeque<(usize, wgpu::SubmissionIndex, MapReceiver)>,
 
     // Window surface for preview
     #[cfg(feature = "preview")]
@@ -113,21 +179,16 @@ impl Renderer {
     pub fn new(
         settings: VideoSettings,
         #[cfg(feature = "preview")] window: &winit::window::Window,
-    ) -> Self {
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+    ) -> Result<Self> {
+        let instance = wgpu::Instance::new(settings.backends);
 
         #[cfg(feature = "preview")]
         let surface = unsafe { instance.create_surface(window) };
 
-        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            force_fallback_adapter: false,
-            #[cfg(feature = "preview")]
-            compatible_surface: Some(&surface),
-            #[cfg(not(feature = "preview"))]
-            compatible_surface: None,
-        }))
-        .unwrap();
+        #[cfg(feature = "preview")]
+        let adapter = select_adapter(&instance, &settings, Some(&surface))?;
+        #[cfg(not(feature = "preview"))]
+        let adapter = select_adapter(&instance, &settings, None)?;
 
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -141,7 +202,7 @@ impl Renderer {
             },
             None,
         ))
-        .unwrap();
+        .map_err(|err| Error::Adapter(format!("unable to open {}: {}", adapter.get_info().name, err)))?;
 
         #[cfg(not(feature = "preview"))]
         let (
@@ -149,7 +210,7 @@ impl Renderer {
             out_texture_view,
             unpadded_bytes_per_row,
             padded_bytes_per_row,
-            out_buffer,
+            out_buffers,
         ) = {
             let out_texture = device.create_texture(&wgpu::TextureDescriptor {
                 label: Some("Output Texture"),
@@ -173,19 +234,23 @@ impl Renderer {
             let padded_bytes_per_row = unpadded_bytes_per_row + padding;
 
             let buffer_size = (padded_bytes_per_row * settings.resolution.1) as wgpu::BufferAddress;
-            let out_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("Output Buffer"),
-                size: buffer_size,
-                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-                mapped_at_creation: false,
-            });
+            let out_buffers = (0..STAGING_BUFFERS)
+                .map(|_| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Output Buffer"),
+                        size: buffer_size,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    })
+                })
+                .collect();
 
             (
                 out_texture,
                 out_texture_view,
                 unpadded_bytes_per_row,
                 padded_bytes_per_row,
-                out_buffer,
+                out_buffers,
             )
         };
 
@@ -264,7 +329,7 @@ impl Renderer {
 
         let depth_texture_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        Self {
+        Ok(Self {
             settings,
             screen_matrix,
 
@@ -281,7 +346,13 @@ impl Renderer {
             #[cfg(not(feature = "preview"))]
             padded_bytes_per_row,
             #[cfg(not(feature = "preview"))]
-            out_buffer,
+            out_buffers,
+            #[cfg(not(feature = "preview"))]
+            next_buffer: 0,
+            #[cfg(not(feature = "preview"))]
+            max_in_flight: STAGING_BUFFERS,
+            #[cfg(not(feature = "preview"))]
+            in_flight: VecDeque::new(),
 
             #[cfg(feature = "preview")]
             surface,
@@ -295,7 +366,15 @@ impl Renderer {
             transform_bind_group,
 
             depth_texture_view,
-        }
+        })
+    }
+
+    /// Adapters of `backends` on this machine, in the order [`AdapterSelection::Index`] refers to them
+    pub fn adapters(backends: wgpu::Backends) -> Vec<wgpu::AdapterInfo> {
+        wgpu::Instance::new(backends)
+            .enumerate_adapters(backends)
+            .map(|adapter| adapter.get_info())
+            .collect()
     }
 
     #[inline]
@@ -328,7 +407,7 @@ impl Renderer {
         &self.transform_bind_group_layout
     }
 
-    pub(crate) fn register_effects(&mut self, packets: Vec<EffectRegistrationPacket>) {
+    pub(crate) fn register_effects(&mut self, packets: Vec<EffectRegistrationPacket>) -> Result<()> {
         info!(
             "Renderer received {} effect registration packets",
             packets.len()
@@ -349,12 +428,32 @@ impl Renderer {
             if self.effect_render_functions[packet.id].is_none() {
                 self.effect_push_functions[packet.id] = Some(packet.push_function);
                 self.effect_render_functions[packet.id] = Some(packet.render_function);
-                self.effects[packet.id] = Some((packet.init_function)(self));
+                self.effects[packet.id] = Some((packet.init_function)(self)?);
             }
         }
+
+        Ok(())
+    }
+
+    /// Outside of preview, the frame ends up in a staging buffer and is read
+    /// with [`Renderer::read_frame`]. All staging buffers being in flight is a bug
+    pub(crate) fn render(&mut self, events: Vec<RenderEvent>) -> Result<()> {
+        // wgpu panics on validation errors nobody captures
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let result = self.submit(events);
+        let validation = pollster::block_on(self.device.pop_error_scope());
+
+        result?;
+        match validation {
+            Some(err) => Err(Error::Gpu(err.to_string())),
+            None => Ok(()),
+        }
     }
 
-    pub(crate) fn render(&mut self, events: Vec<RenderEvent>) -> Option<Vec<u8>> {
+    fn submit(&mut self, events: Vec<RenderEvent>) -> Result<()> {
+        #[cfg(not(feature = "preview"))]
+        assert!(self.can_render(), "all staging buffers are in flight, read a frame first");
+
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -363,7 +462,7 @@ impl Renderer {
 
         #[cfg(feature = "preview")]
         let (output, surface_view) = {
-            let output = self.surface.get_current_texture().unwrap();
+            let output = self.surface.get_current_texture().map_err(|err| Error::Gpu(err.to_string()))?;
             let view = output
                 .texture
                 .create_view(&wgpu::TextureViewDescriptor::default());
@@ -380,11 +479,12 @@ impl Renderer {
                     view: &surface_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
+                        // The target holds premultiplied colors, see `Mesh`'s blend state
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: self.settings.background_color.r,
-                            g: self.settings.background_color.g,
-                            b: self.settings.background_color.b,
-                            a: 1.0,
+                            r: self.settings.background_color.r * self.settings.background_color.a,
+                            g: self.settings.background_color.g * self.settings.background_color.a,
+                            b: self.settings.background_color.b * self.settings.background_color.a,
+                            a: self.settings.background_color.a,
                         }),
                         store: true,
                     },
@@ -418,11 +518,11 @@ impl Renderer {
                             bytemuck::cast_slice(&[Into::<[[f32; 4]; 4]>::into(transform)]),
                         );
                     }
-                    RenderEvent::Effect { id, params, frame } => {
+                    RenderEvent::Effect { id, params, time } => {
                         self.effect_push_functions[id].unwrap()(
                             self.effects.get_mut(id).unwrap().as_mut().unwrap(),
                             params,
-                            frame,
+                            time,
                         );
                     }
                 }
@@ -436,7 +536,7 @@ impl Renderer {
                         pass_ref.lock().unwrap(),
                         &self.device,
                         &self.queue,
-                    );
+                    )?;
                 }
             }
         }
@@ -450,7 +550,7 @@ impl Renderer {
                 origin: wgpu::Origin3d::ZERO,
             },
             wgpu::ImageCopyBuffer {
-                buffer: &self.out_buffer,
+                buffer: &self.out_buffers[self.next_buffer],
                 layout: wgpu::ImageDataLayout {
                     offset: 0,
                     bytes_per_row: std::num::NonZeroU32::new(self.padded_bytes_per_row),
@@ -464,37 +564,149 @@ impl Renderer {
             },
         );
 
-        self.queue.submit(core::iter::once(encoder.finish()));
-        #[cfg(feature = "preview")]
-        output.present();
         #[cfg(feature = "preview")]
-        return None;
+        {
+            self.queue.submit(core::iter::once(encoder.finish()));
+            output.present();
+        }
 
         #[cfg(not(feature = "preview"))]
         {
-            info!("Copying buffers...");
-
-            let buffer_slice = self.out_buffer.slice(..);
+            let submission = self.queue.submit(core::iter::once(encoder.finish()));
             let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-            buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-                tx.send(result).unwrap();
+            self.out_buffers[self.next_buffer].slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                // The receiver is gone when the render already failed
+                let _ = tx.send(result);
             });
-            self.device.poll(wgpu::Maintain::Wait);
-            let result = pollster::block_on(rx.receive()).unwrap();
-
-            match result {
-                Ok(()) => {
-                    let padded_data = buffer_slice.get_mapped_range();
-                    let data = padded_data
-                        .chunks(self.padded_bytes_per_row as _)
-                        .flat_map(|chunk| &chunk[..self.unpadded_bytes_per_row as _])
-                        .copied()
-                        .collect::<Vec<_>>();
-                    drop(padded_data);
-                    self.out_buffer.unmap();
-                    Some(data)
+            self.in_flight.push_back((self.next_buffer, submission, rx));
+            self.next_buffer = (self.next_buffer + 1) % STAGING_BUFFERS;
+        }
+
+        Ok(())
+    }
+
+    /// Whether another frame can be rendered before the oldest one is read
+    #[cfg(not(feature = "preview"))]
+    pub(crate) fn can_render(&self) -> bool {
+        self.in_flight.len() < self.max_in_flight
+    }
+
+    /// Without pipelining, every frame is read back before the next one renders
+    #[cfg(not(feature = "preview"))]
+    pub(crate) fn set_pipelined(&mut self, pipelined: bool) {
+        self.max_in_flight = if pipelined { STAGING_BUFFERS } else { 1 };
+    }
+
+    /// Waits for the oldest frame that wasn't read yet and replaces the
+    /// contents of `output` with it, `false` when no frame is in flight.
+    /// Reusing `output` between frames avoids allocating every frame
+    #[cfg(not(feature = "preview"))]
+    pub(crate) fn read_frame(&mut self, output: &mut Vec<u8>) -> Result<bool> {
+        let (buffer, submission, rx) = match self.in_flight.pop_front() {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+
+        info!("Copying buffers...");
+
+        // Frames rendered after this one keep the GPU busy
+        self.device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+        match pollster::block_on(rx.receive()) {
+            Some(Ok(())) => (),
+            Some(Err(err)) => return Err(Error::Readback(err.to_string())),
+            None => return Err(Error::Readback("the buffer was never mapped".to_string())),
+        }
+
+        let buffer = &self.out_buffers[buffer];
+        let padded_data = buffer.slice(..).get_mapped_range();
+        output.clear();
+        if self.padded_bytes_per_row == self.unpadded_bytes_per_row {
+            output.extend_from_slice(&padded_data);
+        } else {
+            for row in padded_data.chunks(self.padded_bytes_per_row as _) {
+                output.extend_from_slice(&row[..self.unpadded_bytes_per_row as _]);
+            }
+        }
+        drop(padded_data);
+        buffer.unmap();
+
+        // Only a transparent background leaves pixels that aren't opaque
+        if self.settings.alpha_mode == crate::api::video::AlphaMode::Straight && self.settings.background_color.a < 1.0 {
+            unpremultiply(output);
+        }
+        Ok(true)
+    }
+}
+
+/// Converts premultiplied sRGB pixels to straight alpha, the division happens
+/// on linear values like the blending did
+#[cfg(not(feature = "preview"))]
+fn unpremultiply(data: &mut [u8]) {
+    let to_linear = |value: u8| {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    };
+    let to_srgb = |value: f32| {
+        let value = value.clamp(0.0, 1.0);
+        let value = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+        (value * 255.0).round() as u8
+    };
+    let linear: [f32; 256] = std::array::from_fn(|value| to_linear(value as u8));
+
+    for pixel in data.chunks_exact_mut(4) {
+        match pixel[3] {
+            255 => {}
+            0 => pixel[..3].fill(0),
+            alpha => {
+                let alpha = alpha as f32 / 255.0;
+                for channel in pixel[..3].iter_mut() {
+                    *channel = to_srgb(linear[*channel as usize] / alpha);
                 }
-                _ => panic!("Something went wrong while copying GPU buffer to RAM for encoding!"),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "preview")))]
+mod tests {
+    use super::unpremultiply;
+
+    fn unpremultiplied(pixel: [u8; 4]) -> [u8; 4] {
+        let mut pixel = pixel;
+        unpremultiply(&mut pixel);
+        pixel
+    }
+
+    #[test]
+    fn opaque_and_transparent_pixels() {
+        assert_eq!(unpremultiplied([10, 20, 30, 255]), [10, 20, 30, 255]);
+        assert_eq!(unpremultiplied([10, 20, 30, 0]), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn colors_are_divided_in_linear_space() {
+        // 50% white, gray and dark gray blended over nothing
+        assert_eq!(unpremultiplied([188, 93, 45, 128]), [255, 129, 65, 128]);
+        // Colors brighter than alpha allows clamp to white
+        assert_eq!(unpremultiplied([255, 40, 0, 1]), [255, 255, 0, 1]);
+    }
+
+    #[test]
+    fn premultiplied_colors_round_trip() {
+        let srgb_to_linear = |value: u8| {
+            let value = value as f32 / 255.0;
+            if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+        };
+        let linear_to_srgb = |value: f32| {
+            let value = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+            (value * 255.0).round() as u8
+        };
+
+        for alpha in [128, 200] {
+            for value in 0..=255 {
+                let premultiplied = linear_to_srgb(srgb_to_linear(value) * alpha as f32 / 255.0);
+                let [result, ..] = unpremultiplied([premultiplied, 0, 0, alpha]);
+                assert!(result.abs_diff(value) <= 1, "{} at alpha {} became {}", value, alpha, result);
             }
         }
     }
@@ -66,5 +66,9 @@ fn main() {
         color: unanimated!("#0037da"),
     });
 
-    video.render(vide::quick_export::to("output.mp4"));
+    video.on_progress(|progress| {
+        println!("{}/{} frames, {:.1} fps, {}s left", progress.frames_done, progress.total_frames, progress.fps, progress.eta.as_secs());
+    });
+
+    video.render(vide::quick_export::to("output.mp4").unwrap()).unwrap();
 }
@@ -0,0 +1,65 @@
+//! Measures how many frames per second Vide renders and reads back. Frames are
+//! converted to a Y4M stream that is thrown away, so the encoder costs some CPU
+//! time without depending on ffmpeg.
+//!
+//! `cargo run --release -p benchmark -- [frames] [--software] [--no-pipelining]`
+//!
+//! `--software` renders on a software adapter like lavapipe instead of the GPU.
+//! `--no-pipelining` reads back and encodes every frame before the next one
+//! renders, the way frames were exported before pipelining.
+//! The adapter is logged with `RUST_LOG=info`. Compare the numbers before and
+//! after a change to the renderer on the same machine and adapter.
+
+use std::time::Duration;
+
+use vide::{io::stream::Y4mExporter, prelude::*};
+
+fn main() {
+    env_logger::init();
+
+    let frames = std::env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(600u64);
+    let adapter = match std::env::args().any(|arg| arg == "--software") {
+        true => AdapterSelection::Software,
+        false => AdapterSelection::HighPerformance,
+    };
+    let settings = VideoSettings {
+        duration: Duration::from_secs_f64(frames as f64 / 60.0),
+        adapter,
+        ..Default::default()
+    };
+
+    // The error lists the adapters that are available
+    let mut video = Video::try_new(settings).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    video.pipelined(!std::env::args().any(|arg| arg == "--no-pipelining"));
+
+    // Enough overlapping shapes that the GPU has some work to do
+    for i in 0..200 {
+        let (x, y) = ((i % 20) as f32 * 90.0 - 855.0, (i / 20) as f32 * 100.0 - 450.0);
+        video.root().new_clip(0.0..settings.duration.as_secs_f64()).effect(Rect {
+            position: Animation::new(60.0)
+                .keyframe(Abs(0.0), ease::LINEAR, (x, y))
+                .keyframe(Rel(1.0), ease::IN_OUT_BACK, (-x, -y))
+                .build(),
+            size: unanimated!((160.0, 160.0)),
+            color: unanimated!(rgba8!(0xda, 0x00, 0x37, 0x80)),
+        });
+    }
+
+    video.on_progress(move |progress| {
+        if progress.frames_done == progress.total_frames {
+            println!(
+                "{} frames at {}x{} in {:.2}s: {:.1} frames/s",
+                progress.total_frames,
+                settings.resolution.0,
+                settings.resolution.1,
+                progress.elapsed.as_secs_f64(),
+                progress.fps
+            );
+        }
+    });
+
+    video.render(Y4mExporter::new(std::io::sink())).unwrap();
+}
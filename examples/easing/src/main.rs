@@ -45,5 +45,5 @@ fn main() {
         });
     }
 
-    video.render(vide::quick_export::to("output.mp4"));
+    video.render(vide::quick_export::to("output.mp4").unwrap()).unwrap();
 }